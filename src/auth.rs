@@ -0,0 +1,177 @@
+use std::{
+    path::{Path, PathBuf},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use http::{header::AUTHORIZATION, HeaderValue, Request};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+/// How long a token is used before the token file is read again.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Authenticates requests sent to the API server with a bearer token read from a file.
+///
+/// Trailing whitespace, e.g. the newline written by `echo` and most other tools, is not part of the token.
+/// The file is read again once the token is a minute old, so rotated tokens are picked up.
+/// When reading it again fails, the last token is used.
+pub struct TokenFileLayer {
+    path: PathBuf,
+    token: HeaderValue,
+}
+
+impl TokenFileLayer {
+    /// Reads the token, failing if the file cannot be read or does not contain a token.
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let token = read_token(&path)?;
+        Ok(Self { path, token })
+    }
+}
+
+impl<S> Layer<S> for TokenFileLayer {
+    type Service = TokenFileAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TokenFileAuth {
+            inner,
+            path: self.path.clone(),
+            token: self.token.clone(),
+            read_at: Instant::now(),
+        }
+    }
+}
+
+pub struct TokenFileAuth<S> {
+    inner: S,
+    path: PathBuf,
+    /// Value of the `Authorization` header.
+    token: HeaderValue,
+    read_at: Instant,
+}
+
+impl<S, B> Service<Request<B>> for TokenFileAuth<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        if self.read_at.elapsed() >= RELOAD_INTERVAL {
+            match read_token(&self.path) {
+                Ok(token) => self.token = token,
+                Err(error) => tracing::debug!(%error, "using the last token"),
+            }
+            self.read_at = Instant::now();
+        }
+
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, self.token.clone());
+        self.inner.call(request)
+    }
+}
+
+/// Reads the token from the file and returns it as the value of the `Authorization` header.
+fn read_token(path: &Path) -> Result<HeaderValue> {
+    let token = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read token file {}", path.display()))?;
+    let token = token.trim_end();
+
+    let value = HeaderValue::from_str(&format!("Bearer {token}"));
+    let mut value = match value {
+        Ok(value) if !token.is_empty() => value,
+        _ => anyhow::bail!(
+            "token file {} must contain only the token, optionally followed by whitespace",
+            path.display()
+        ),
+    };
+    value.set_sensitive(true);
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, env, fs, process};
+
+    use tokio::time;
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    /// Writes the token file, unique to the test, and returns its path.
+    fn token_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("k8s-wait-full-{}-{name}", process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// Sends a request and returns the value of its `Authorization` header.
+    async fn authorization<S>(service: &mut S) -> HeaderValue
+    where
+        S: Service<Request<()>, Response = HeaderValue, Error = Infallible>,
+    {
+        service
+            .ready()
+            .await
+            .unwrap()
+            .call(Request::new(()))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn trailing_whitespace_is_trimmed() {
+        let path = token_file("trimmed", "secret\n");
+        let layer = TokenFileLayer::new(path.clone()).unwrap();
+        assert_eq!(layer.token, "Bearer secret");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rotated_tokens_are_picked_up() {
+        let path = token_file("rotated", "first\n");
+        let mut service = TokenFileLayer::new(path.clone()).unwrap().layer(service_fn(
+            |request: Request<()>| async move {
+                Ok::<_, Infallible>(request.headers()[AUTHORIZATION].clone())
+            },
+        ));
+        assert_eq!(authorization(&mut service).await, "Bearer first");
+
+        fs::write(&path, "second\n").unwrap();
+        time::advance(Duration::from_secs(30)).await;
+        assert_eq!(authorization(&mut service).await, "Bearer first");
+        time::advance(Duration::from_secs(30)).await;
+        assert_eq!(authorization(&mut service).await, "Bearer second");
+
+        // When the file cannot be read, the last token is used.
+        fs::remove_file(&path).unwrap();
+        time::advance(Duration::from_secs(60)).await;
+        assert_eq!(authorization(&mut service).await, "Bearer second");
+    }
+
+    #[test]
+    fn files_without_a_token_are_rejected() {
+        for content in ["", "\n", "sec\nret\n"] {
+            let path = token_file("invalid", content);
+            let error = TokenFileLayer::new(path.clone()).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "token file {} must contain only the token, optionally followed by whitespace",
+                    path.display()
+                ),
+                "{content:?}"
+            );
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
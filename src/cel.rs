@@ -0,0 +1,69 @@
+use std::{str::FromStr, sync::Arc};
+
+use cel_interpreter::{Context, Program};
+use serde_yaml::Value;
+
+/// CEL expression evaluated against the observed resource state, e.g. `object.status.phase == 'Running'`.
+#[derive(Clone)]
+pub struct CelExpression(Arc<Program>);
+
+impl CelExpression {
+    /// Checks whether the expression evaluates to `true` for the state, available as both `object` and `self`.
+    /// Evaluation errors, e.g. caused by missing fields, and non-boolean results do not match.
+    pub fn matches(&self, state: &Value) -> bool {
+        let Ok(object) = cel_interpreter::to_value(state) else {
+            return false;
+        };
+
+        let mut context = Context::default();
+        context.add_variable_from_value("object", object.clone());
+        context.add_variable_from_value("self", object);
+
+        matches!(
+            self.0.execute(&context),
+            Ok(cel_interpreter::Value::Bool(true))
+        )
+    }
+}
+
+impl FromStr for CelExpression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Program::compile(s)
+            .map(|program| Self(Arc::new(program)))
+            .map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(expression: &str, state: &str) -> bool {
+        let expression: CelExpression = expression.parse().unwrap();
+        expression.matches(&serde_yaml::from_str(state).unwrap())
+    }
+
+    #[test]
+    fn true_expressions_match() {
+        let state = "{ status: { phase: Running, readyReplicas: 3 } }";
+        assert!(matches("object.status.phase == 'Running'", state));
+        assert!(matches("self.status.readyReplicas >= 2", state));
+        assert!(!matches("object.status.phase == 'Pending'", state));
+    }
+
+    #[test]
+    fn failed_evaluations_do_not_match() {
+        let state = "{ status: { phase: Running } }";
+        assert!(!matches("object.status.readyReplicas >= 2", state));
+        assert!(!matches("object.metadata.name == 'web'", state));
+        assert!(!matches("object.status.phase", state));
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        assert!("object.status.phase ==".parse::<CelExpression>().is_err());
+        assert!("object.status.(phase)".parse::<CelExpression>().is_err());
+    }
+}
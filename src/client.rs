@@ -0,0 +1,375 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use http::{header::USER_AGENT, HeaderValue};
+use kube::{
+    client::{AuthError, ClientBuilder},
+    config::{KubeConfigOptions, Kubeconfig},
+    Client, Config,
+};
+
+use crate::{auth::TokenFileLayer, ratelimit::RateLimitLayer};
+
+/// Options used to construct the Kubernetes client.
+pub struct ClientOptions {
+    /// Value of the `User-Agent` header sent with every request.
+    pub user_agent: String,
+    /// Timeout for reading and writing a single request.
+    pub request_timeout: Option<Duration>,
+    /// Maximal sustained number of requests per second.
+    pub qps: Option<f64>,
+    /// Maximal number of requests sent at once, before the `qps` limit applies.
+    pub burst: Option<u32>,
+    /// File with a bearer token used instead of the credentials from the kubeconfig.
+    pub token_file: Option<PathBuf>,
+    /// Kubeconfig context used instead of the current context.
+    pub context: Option<String>,
+    /// Environment variable containing the kubeconfig itself, used instead of the kubeconfig files.
+    pub kubeconfig_env: Option<String>,
+}
+
+pub async fn create_client(options: ClientOptions) -> Result<Client> {
+    let kubeconfig_options = KubeConfigOptions {
+        context: options.context.clone(),
+        ..Default::default()
+    };
+    // The default namespace of the client is the namespace of the context.
+    let mut config = match (&options.kubeconfig_env, &options.context) {
+        (Some(var), _) => {
+            let content = env::var(var).with_context(|| {
+                format!("failed to read kubeconfig from environment variable {var}")
+            })?;
+            let kubeconfig = Kubeconfig::from_yaml(&content).with_context(|| {
+                format!("environment variable {var} does not contain a valid kubeconfig")
+            })?;
+            let mut config = Config::from_custom_kubeconfig(kubeconfig, &kubeconfig_options)
+                .await
+                .with_context(|| {
+                    format!("failed to load kubeconfig from environment variable {var}")
+                })?;
+            config.apply_debug_overrides();
+            config
+        }
+        (None, Some(context)) => {
+            let mut config = Config::from_kubeconfig(&kubeconfig_options)
+                .await
+                .with_context(|| format!("failed to load context {context} from kubeconfig"))?;
+            config.apply_debug_overrides();
+            config
+        }
+        (None, None) => Config::infer()
+            .await
+            .context("failed to infer Kubernetes client configuration")?,
+    };
+
+    let user_agent = HeaderValue::from_str(&options.user_agent).context("invalid user agent")?;
+    config.headers.push((USER_AGENT, user_agent));
+
+    // The token from the file replaces the credentials from the kubeconfig.
+    let token_file = options.token_file.map(TokenFileLayer::new).transpose()?;
+    if token_file.is_some() {
+        let auth_info = &mut config.auth_info;
+        auth_info.auth_provider = None;
+        auth_info.username = None;
+        auth_info.password = None;
+        auth_info.token = None;
+        auth_info.token_file = None;
+        auth_info.exec = None;
+    }
+
+    if let Some(timeout) = options.request_timeout {
+        config.read_timeout = Some(timeout);
+        config.write_timeout = Some(timeout);
+    }
+
+    // Exec credential plugins are run when the client is created.
+    let exec_command = config
+        .auth_info
+        .exec
+        .as_ref()
+        .and_then(|exec| exec.command.clone());
+    let builder = ClientBuilder::try_from(config)
+        .map_err(|error| {
+            match exec_command
+                .as_deref()
+                .and_then(|command| describe_exec_error(command, &error))
+            {
+                Some(description) => anyhow::anyhow!(description),
+                None => error.into(),
+            }
+        })
+        .context("failed to create Kubernetes client")?
+        .with_layer(&tower::util::option_layer(token_file));
+    let client = match options.qps {
+        Some(qps) => {
+            let burst = options
+                .burst
+                .unwrap_or_else(|| qps.ceil().min(u32::MAX.into()) as u32);
+            builder.with_layer(&RateLimitLayer::new(qps, burst)).build()
+        }
+        None => builder.build(),
+    };
+
+    Ok(client)
+}
+
+/// Describes a failure of the exec credential plugin configured in the kubeconfig, naming its command.
+fn describe_exec_error(command: &str, error: &kube::Error) -> Option<String> {
+    let kube::Error::Auth(error) = error else {
+        return None;
+    };
+
+    let description = match error {
+        AuthError::AuthExecStart(error) if error.kind() == std::io::ErrorKind::NotFound => format!(
+            "credential plugin `{command}` was not found, make sure that it is installed and in PATH"
+        ),
+        AuthError::AuthExecStart(error) => {
+            format!("failed to run credential plugin `{command}`: {error}")
+        }
+        AuthError::AuthExecRun { status, out, .. } => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            match stderr.trim() {
+                "" => format!("credential plugin `{command}` failed with {status}"),
+                stderr => format!("credential plugin `{command}` failed with {status}: {stderr}"),
+            }
+        }
+        AuthError::AuthExecParse(error) => format!(
+            "credential plugin `{command}` did not print a valid ExecCredential: {error}"
+        ),
+        AuthError::ExecPluginFailed => {
+            format!("credential plugin `{command}` did not print ExecCredential status")
+        }
+        _ => return None,
+    };
+
+    Some(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeApi, Response};
+
+    /// Returns options reading the kubeconfig from the environment variable,
+    /// unique to every test, so that tests running in parallel do not interfere.
+    fn options(kubeconfig_env: &str, api: &FakeApi) -> ClientOptions {
+        env::set_var(kubeconfig_env, api.kubeconfig());
+        ClientOptions {
+            user_agent: "k8s-wait-full/test".into(),
+            request_timeout: None,
+            qps: None,
+            burst: None,
+            token_file: None,
+            context: None,
+            kubeconfig_env: Some(kubeconfig_env.into()),
+        }
+    }
+
+    async fn fake_api() -> FakeApi {
+        FakeApi::start(|_| Response::Json(200, testing::pod_list(Vec::new()))).await
+    }
+
+    #[tokio::test]
+    async fn user_agent_is_sent() {
+        let api = fake_api().await;
+        let options = options("K8S_WAIT_TEST_USER_AGENT_KUBECONFIG", &api);
+
+        let client = create_client(options).await.unwrap();
+        testing::pods(client)
+            .list(&Default::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            api.requests()[0].header("user-agent"),
+            Some("k8s-wait-full/test")
+        );
+    }
+
+    #[tokio::test]
+    async fn token_from_the_file_is_sent() {
+        let api = fake_api().await;
+        let token_file =
+            env::temp_dir().join(format!("k8s-wait-full-{}-client-token", std::process::id()));
+        std::fs::write(&token_file, "secret\n").unwrap();
+        let options = ClientOptions {
+            token_file: Some(token_file.clone()),
+            ..options("K8S_WAIT_TEST_TOKEN_KUBECONFIG", &api)
+        };
+
+        let client = create_client(options).await.unwrap();
+        testing::pods(client)
+            .list(&Default::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            api.requests()[0].header("authorization"),
+            Some("Bearer secret")
+        );
+        std::fs::remove_file(token_file).unwrap();
+    }
+
+    /// Creates a client for the fake API, authenticating with the exec credential plugin.
+    async fn exec_client(kubeconfig_env: &str, command: &str, args: &[&str]) -> Result<Client> {
+        let api = fake_api().await;
+        let options = options(kubeconfig_env, &api);
+        let exec = serde_json::json!({
+            "apiVersion": "client.authentication.k8s.io/v1beta1",
+            "command": command,
+            "args": args,
+        });
+        let kubeconfig = api
+            .kubeconfig()
+            .replace("user: {}", &format!("user: {{ exec: {exec} }}"));
+        env::set_var(kubeconfig_env, kubeconfig);
+        create_client(options).await
+    }
+
+    #[tokio::test]
+    async fn missing_exec_plugin_is_named() {
+        let error = exec_client(
+            "K8S_WAIT_TEST_MISSING_PLUGIN_KUBECONFIG",
+            "k8s-wait-full-missing-plugin",
+            &[],
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(
+            format!("{error:#}"),
+            "failed to create Kubernetes client: credential plugin `k8s-wait-full-missing-plugin` was not found, \
+             make sure that it is installed and in PATH"
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_exec_plugin_is_described() {
+        let error = exec_client(
+            "K8S_WAIT_TEST_FAILING_PLUGIN_KUBECONFIG",
+            "sh",
+            &["-c", "echo 'token expired' >&2; exit 3"],
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(
+            format!("{error:#}"),
+            "failed to create Kubernetes client: credential plugin `sh` failed with exit status: 3: token expired"
+        );
+    }
+
+    /// Returns a kubeconfig with the contexts `first` and `second`, pointing to the APIs,
+    /// with `team-a` and `team-b` as their namespaces, and `first` as the current context.
+    fn two_contexts(first: &FakeApi, second: &FakeApi) -> String {
+        format!(
+            "apiVersion: v1
+kind: Config
+clusters:
+- name: first
+  cluster:
+    server: {}
+- name: second
+  cluster:
+    server: {}
+contexts:
+- name: first
+  context:
+    cluster: first
+    user: fake
+    namespace: team-a
+- name: second
+  context:
+    cluster: second
+    user: fake
+    namespace: team-b
+current-context: first
+users:
+- name: fake
+  user: {{}}
+",
+            first.url(),
+            second.url()
+        )
+    }
+
+    #[tokio::test]
+    async fn selected_context_is_used() {
+        let first = fake_api().await;
+        let second = fake_api().await;
+        let kubeconfig_env = "K8S_WAIT_TEST_CONTEXT_KUBECONFIG";
+        let options = ClientOptions {
+            context: Some("second".into()),
+            ..options(kubeconfig_env, &first)
+        };
+        env::set_var(kubeconfig_env, two_contexts(&first, &second));
+
+        let client = create_client(options).await.unwrap();
+        assert_eq!(client.default_namespace(), "team-b");
+        testing::pods(client)
+            .list(&Default::default())
+            .await
+            .unwrap();
+        assert!(first.requests().is_empty());
+        assert_eq!(
+            second.requests()[0].path(),
+            "/api/v1/namespaces/team-b/pods"
+        );
+    }
+
+    #[tokio::test]
+    async fn kubeconfig_from_the_variable_is_used() {
+        let first = fake_api().await;
+        let second = fake_api().await;
+        let kubeconfig_env = "K8S_WAIT_TEST_ENV_KUBECONFIG";
+        let options = options(kubeconfig_env, &first);
+        env::set_var(kubeconfig_env, two_contexts(&first, &second));
+
+        let client = create_client(options).await.unwrap();
+        assert_eq!(client.default_namespace(), "team-a");
+        testing::pods(client)
+            .list(&Default::default())
+            .await
+            .unwrap();
+        assert!(second.requests().is_empty());
+        assert_eq!(first.requests()[0].path(), "/api/v1/namespaces/team-a/pods");
+    }
+
+    #[tokio::test]
+    async fn missing_or_invalid_kubeconfig_variables_are_rejected() {
+        let api = fake_api().await;
+        let kubeconfig_env = "K8S_WAIT_TEST_INVALID_KUBECONFIG";
+        let options = || options(kubeconfig_env, &api);
+
+        let missing = options();
+        env::remove_var(kubeconfig_env);
+        let error = create_client(missing).await.err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("failed to read kubeconfig from environment variable {kubeconfig_env}")
+        );
+
+        let invalid = options();
+        env::set_var(kubeconfig_env, "clusters: 3");
+        let error = create_client(invalid).await.err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("environment variable {kubeconfig_env} does not contain a valid kubeconfig")
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_context_is_rejected() {
+        let api = fake_api().await;
+        let options = ClientOptions {
+            context: Some("missing".into()),
+            ..options("K8S_WAIT_TEST_UNKNOWN_CONTEXT_KUBECONFIG", &api)
+        };
+
+        let error = create_client(options).await.err().unwrap();
+        assert!(
+            error
+                .to_string()
+                .starts_with("failed to load kubeconfig from environment variable"),
+            "{error:#}"
+        );
+    }
+}
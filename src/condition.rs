@@ -0,0 +1,255 @@
+use std::str::FromStr;
+
+use serde_yaml::Value;
+
+/// Built-in condition that the resource state must meet in addition to the state filter.
+#[derive(Clone)]
+pub enum WaitFor {
+    /// Both `status.replicas` and `status.readyReplicas` are equal to `spec.replicas`.
+    /// Missing status fields count as 0, as the API server omits them when they are 0.
+    Scaled,
+    /// An entry in `status.conditions` has the given type and matching fields.
+    Condition(ConditionMatch),
+    /// At least `min` of the conditions of the given types are `True`.
+    ConditionsAtLeast { min: usize, types: Vec<String> },
+    /// `metadata.deletionTimestamp` is set, the resource is being deleted but may still wait for finalizers.
+    Terminating,
+}
+
+/// Expected fields of an entry in `status.conditions`, e.g. `Degraded=False;reason=AsExpected`.
+#[derive(Clone)]
+pub struct ConditionMatch {
+    pub condition_type: String,
+    pub status: String,
+    pub reason: Option<String>,
+    pub message: Option<String>,
+}
+
+impl ConditionMatch {
+    fn matches(&self, condition: &Value) -> bool {
+        condition["type"].as_str() == Some(&self.condition_type)
+            && condition["status"].as_str() == Some(&self.status)
+            && self
+                .reason
+                .as_ref()
+                .is_none_or(|reason| condition["reason"].as_str() == Some(reason))
+            && self
+                .message
+                .as_ref()
+                .is_none_or(|message| condition["message"].as_str() == Some(message))
+    }
+}
+
+impl FromStr for ConditionMatch {
+    type Err = String;
+
+    /// Parses `<type>[=<status>][;reason=<reason>][;message=<message>]`, the status defaults to `True`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(';');
+        let head = parts.next().unwrap_or_default();
+        let (condition_type, status) = head.split_once('=').unwrap_or((head, "True"));
+        if condition_type.is_empty() {
+            return Err("condition type must not be empty".into());
+        }
+
+        let mut condition = Self {
+            condition_type: condition_type.into(),
+            status: status.into(),
+            reason: None,
+            message: None,
+        };
+
+        for part in parts {
+            match part.split_once('=') {
+                Some(("reason", reason)) => condition.reason = Some(reason.into()),
+                Some(("message", message)) => condition.message = Some(message.into()),
+                _ => {
+                    return Err(format!(
+                        "unexpected `{part}`, expected `reason=<reason>` or `message=<message>`"
+                    ))
+                }
+            }
+        }
+
+        Ok(condition)
+    }
+}
+
+impl WaitFor {
+    pub fn matches(&self, state: &Value) -> bool {
+        match self {
+            Self::Scaled => {
+                let Some(desired) = state["spec"]["replicas"].as_u64() else {
+                    return false;
+                };
+
+                let status = |field: &str| match &state["status"][field] {
+                    Value::Null => Some(0),
+                    value => value.as_u64(),
+                };
+                status("replicas") == Some(desired) && status("readyReplicas") == Some(desired)
+            }
+
+            Self::Condition(expected) => {
+                state["status"]["conditions"]
+                    .as_sequence()
+                    .is_some_and(|conditions| {
+                        conditions
+                            .iter()
+                            .any(|condition| expected.matches(condition))
+                    })
+            }
+
+            Self::ConditionsAtLeast { min, types } => {
+                let Some(conditions) = state["status"]["conditions"].as_sequence() else {
+                    return false;
+                };
+
+                let met = types
+                    .iter()
+                    .filter(|condition_type| {
+                        conditions.iter().any(|condition| {
+                            condition["type"].as_str() == Some(condition_type.as_str())
+                                && condition["status"].as_str() == Some("True")
+                        })
+                    })
+                    .count();
+                met >= *min
+            }
+
+            Self::Terminating => !state["metadata"]["deletionTimestamp"].is_null(),
+        }
+    }
+}
+
+/// Parses `<min>:<type>,<type>,...`, e.g. `2:Ready,Available,Synced`.
+/// Duplicate types count once.
+fn parse_conditions_at_least(s: &str) -> Result<WaitFor, String> {
+    let (min, types) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected `<min>:<type>,...`, got `{s}`"))?;
+    let min: usize = min
+        .parse()
+        .map_err(|_| format!("invalid minimal number of conditions `{min}`"))?;
+
+    let mut unique_types: Vec<String> = Vec::new();
+    for condition_type in types.split(',') {
+        if condition_type.is_empty() {
+            return Err("condition type must not be empty".into());
+        }
+        if !unique_types.iter().any(|unique| unique == condition_type) {
+            unique_types.push(condition_type.into());
+        }
+    }
+
+    if min == 0 || min > unique_types.len() {
+        return Err(format!(
+            "minimal number of conditions must be between 1 and the number of condition types ({}), got {min}",
+            unique_types.len()
+        ));
+    }
+
+    Ok(WaitFor::ConditionsAtLeast {
+        min,
+        types: unique_types,
+    })
+}
+
+impl FromStr for WaitFor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(condition) = s.strip_prefix("condition=") {
+            return condition.parse().map(Self::Condition);
+        }
+
+        if let Some(conditions) = s.strip_prefix("any-of-conditions=") {
+            return parse_conditions_at_least(conditions);
+        }
+
+        match s {
+            "scaled" => Ok(Self::Scaled),
+            "ready" => "Ready".parse().map(Self::Condition),
+            "terminating" => Ok(Self::Terminating),
+            other => Err(format!(
+                "unknown condition `{other}`, expected `scaled`, `ready`, `terminating`, `condition=<type>[=<status>]` or `any-of-conditions=<min>:<type>,...`"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn scaled_to_zero_without_status_fields() {
+        let scaled: WaitFor = "scaled".parse().unwrap();
+        assert!(scaled.matches(&state("{ spec: { replicas: 0 }, status: {} }")));
+        assert!(scaled.matches(&state("{ spec: { replicas: 0 } }")));
+        assert!(!scaled.matches(&state("{ spec: { replicas: 0 }, status: { replicas: 1 } }")));
+        assert!(!scaled.matches(&state("{ spec: { replicas: 2 }, status: { replicas: 2 } }")));
+        assert!(scaled.matches(&state(
+            "{ spec: { replicas: 2 }, status: { replicas: 2, readyReplicas: 2 } }"
+        )));
+    }
+
+    #[test]
+    fn conditions_match_type_status_and_reason() {
+        let state = state(
+            "{ status: { conditions: [{ type: Ready, status: \"True\" }, { type: Degraded, status: \"False\", reason: AsExpected }] } }",
+        );
+        let matches = |wait_for: &str| wait_for.parse::<WaitFor>().unwrap().matches(&state);
+        assert!(matches("ready"));
+        assert!(matches("condition=Degraded=False"));
+        assert!(matches("condition=Degraded=False;reason=AsExpected"));
+        assert!(!matches("condition=Degraded=False;reason=Other"));
+        assert!(!matches("condition=Degraded"));
+        assert!(!matches("condition=Available"));
+    }
+
+    #[test]
+    fn invalid_conditions_are_rejected() {
+        for wait_for in ["healthy", "condition=", "condition=Ready;phase=Running"] {
+            assert!(wait_for.parse::<WaitFor>().is_err(), "{wait_for}");
+        }
+    }
+
+    #[test]
+    fn any_of_conditions_counts_true_ones() {
+        let state = state(
+            "{ status: { conditions: [{ type: Ready, status: \"True\" }, { type: Synced, status: \"False\" }, { type: Healthy, status: \"True\" }] } }",
+        );
+        let matches = |wait_for: &str| wait_for.parse::<WaitFor>().unwrap().matches(&state);
+        assert!(matches("any-of-conditions=1:Ready,Synced"));
+        assert!(!matches("any-of-conditions=2:Ready,Synced"));
+        assert!(matches("any-of-conditions=2:Ready,Synced,Healthy"));
+        assert!(!matches("any-of-conditions=1:Synced,Available"));
+    }
+
+    #[test]
+    fn invalid_any_of_conditions_are_rejected() {
+        for wait_for in [
+            "any-of-conditions=3:Ready,Synced",
+            "any-of-conditions=0:Ready",
+            "any-of-conditions=Ready",
+            "any-of-conditions=1:Ready,,Synced",
+            "any-of-conditions=1:",
+        ] {
+            assert!(wait_for.parse::<WaitFor>().is_err(), "{wait_for}");
+        }
+    }
+
+    #[test]
+    fn terminating_requires_deletion_timestamp() {
+        let terminating: WaitFor = "terminating".parse().unwrap();
+        assert!(terminating.matches(&state(
+            "{ metadata: { deletionTimestamp: \"2024-01-01T00:00:00Z\" } }"
+        )));
+        assert!(!terminating.matches(&state("{ metadata: { name: web } }")));
+    }
+}
@@ -0,0 +1,114 @@
+use std::process::ExitCode;
+
+use thiserror::Error;
+
+/// Failures that terminate the program with a dedicated exit code.
+///
+/// Can be returned directly or attached as a context to another error.
+#[derive(Debug, Error)]
+pub enum WaitError {
+    #[error("invalid state filter")]
+    InvalidStateFilter,
+
+    #[error("invalid sequence")]
+    InvalidSequence,
+
+    #[error("No API resources matching filtering criteria were found")]
+    ApiResourceNotFound,
+
+    #[error("resource {0} was not found")]
+    ResourceNotFound(String),
+
+    #[error("Multiple resources matching filtering criteria were found, try narrowing your filtering criteria, e.g. with `--api-version` set to one of: {}", .0.join(", "))]
+    AmbiguousApiResource(Vec<String>),
+
+    #[error("timeout expired")]
+    Timeout,
+
+    #[error("no resource state matching the filter was found")]
+    NotMatched,
+
+    #[error("state of {resource} matched the `--fail-if` filter {filter}")]
+    FailIfMatched { resource: String, filter: String },
+
+    #[error("interrupted by SIGINT")]
+    Interrupted,
+
+    #[error("terminated by SIGTERM")]
+    Terminated,
+}
+
+impl WaitError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Self::InvalidStateFilter | Self::InvalidSequence => 2,
+            Self::ApiResourceNotFound | Self::ResourceNotFound(_) => 3,
+            Self::AmbiguousApiResource(_) => 4,
+            Self::FailIfMatched { .. } => 5,
+            Self::Timeout | Self::NotMatched => 124,
+            // As if killed by SIGINT or SIGTERM, following the shell convention.
+            Self::Interrupted => 130,
+            Self::Terminated => 143,
+        }
+    }
+}
+
+/// Returns the exit code for the given error.
+/// Errors without a [`WaitError`] in their chain exit with code 1.
+pub fn exit_code(error: &anyhow::Error) -> ExitCode {
+    error
+        .downcast_ref::<WaitError>()
+        .map(WaitError::exit_code)
+        .unwrap_or(1)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn every_error_has_its_exit_code() {
+        let codes = [
+            (WaitError::InvalidStateFilter, 2),
+            (WaitError::InvalidSequence, 2),
+            (WaitError::ApiResourceNotFound, 3),
+            (WaitError::ResourceNotFound("pod/web".into()), 3),
+            (WaitError::AmbiguousApiResource(vec!["v1".into()]), 4),
+            (
+                WaitError::FailIfMatched {
+                    resource: "pod/web".into(),
+                    filter: "failed".into(),
+                },
+                5,
+            ),
+            (WaitError::Timeout, 124),
+            (WaitError::NotMatched, 124),
+            (WaitError::Interrupted, 130),
+            (WaitError::Terminated, 143),
+        ];
+        for (error, code) in codes {
+            assert_eq!(exit_code(&error.into()), ExitCode::from(code));
+        }
+    }
+
+    #[test]
+    fn exit_code_is_found_in_the_context() {
+        let error = Err::<(), _>(std::io::Error::other("connection reset"))
+            .context(WaitError::Timeout)
+            .context("watch failed")
+            .unwrap_err();
+        assert_eq!(exit_code(&error), ExitCode::from(124));
+    }
+
+    #[test]
+    fn other_errors_exit_with_one() {
+        assert_eq!(exit_code(&anyhow::anyhow!("failed")), ExitCode::from(1));
+        let error = Err::<(), _>(std::io::Error::other("connection reset"))
+            .context("watch failed")
+            .unwrap_err();
+        assert_eq!(exit_code(&error), ExitCode::from(1));
+    }
+}
@@ -0,0 +1,132 @@
+use futures::StreamExt;
+use glob::Pattern;
+use indicatif::ProgressBar;
+use k8s_openapi::api::core::v1::Event;
+use kube::{
+    runtime::{
+        self,
+        watcher::{self, Config},
+        WatchStreamExt,
+    },
+    Api,
+};
+
+/// Resource whose events are displayed.
+/// At least one of the name, the name pattern, the names or the UID is required,
+/// otherwise the events of all resources of the kind would be displayed.
+#[derive(Clone)]
+pub struct InvolvedObject {
+    pub kind: String,
+    pub name: Option<String>,
+    pub name_glob: Option<Pattern>,
+    /// Names of which the resource name must be one, any name if empty.
+    pub names: Vec<String>,
+    pub uid: Option<String>,
+}
+
+impl InvolvedObject {
+    /// Returns the field selector of the events, selecting by the kind and, if known, the name and the UID.
+    fn field_selector(&self) -> String {
+        let mut field_selector = format!("involvedObject.kind={}", self.kind);
+        if let Some(name) = &self.name {
+            field_selector.push_str(&format!(",involvedObject.name={name}"));
+        }
+        if let Some(uid) = &self.uid {
+            field_selector.push_str(&format!(",involvedObject.uid={uid}"));
+        }
+        field_selector
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        let name = event.involved_object.name.as_ref();
+        self.name_glob
+            .as_ref()
+            .is_none_or(|pattern| name.is_some_and(|name| pattern.matches(name)))
+            && (self.names.is_empty() || name.is_some_and(|name| self.names.contains(name)))
+    }
+}
+
+/// Watches events related to the resource and prints their reasons and messages to standard error.
+/// Runs until cancelled, watch errors are retried with backoff.
+pub async fn show_events(api: Api<Event>, involved: InvolvedObject, progress: ProgressBar) {
+    let config = Config::default().fields(&involved.field_selector());
+
+    let mut stream = Box::pin(runtime::watcher(api, config).default_backoff());
+    while let Some(item) = stream.next().await {
+        let event = match item {
+            Ok(watcher::Event::Apply(event) | watcher::Event::InitApply(event)) => event,
+            _ => continue,
+        };
+
+        if !involved.matches(&event) {
+            continue;
+        }
+
+        progress.suspend(|| {
+            eprintln!(
+                "Event: {} {} {}: {}",
+                event.type_.as_deref().unwrap_or("Unknown"),
+                event.involved_object.name.as_deref().unwrap_or_default(),
+                event.reason.as_deref().unwrap_or_default(),
+                event.message.as_deref().unwrap_or_default(),
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::ObjectReference;
+
+    use super::*;
+
+    fn any_pod() -> InvolvedObject {
+        InvolvedObject {
+            kind: "Pod".into(),
+            name: None,
+            name_glob: None,
+            names: Vec::new(),
+            uid: None,
+        }
+    }
+
+    fn event(name: &str) -> Event {
+        Event {
+            involved_object: ObjectReference {
+                name: Some(name.into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn field_selector_includes_name_and_uid() {
+        let involved = InvolvedObject {
+            name: Some("web-0".into()),
+            uid: Some("1234".into()),
+            ..any_pod()
+        };
+        assert_eq!(
+            involved.field_selector(),
+            "involvedObject.kind=Pod,involvedObject.name=web-0,involvedObject.uid=1234"
+        );
+    }
+
+    #[test]
+    fn names_are_matched_client_side() {
+        let involved = InvolvedObject {
+            names: vec!["web-0".into(), "web-1".into()],
+            ..any_pod()
+        };
+        assert!(involved.matches(&event("web-1")));
+        assert!(!involved.matches(&event("web-2")));
+
+        let involved = InvolvedObject {
+            name_glob: Some(Pattern::new("web-*").unwrap()),
+            ..any_pod()
+        };
+        assert!(involved.matches(&event("web-2")));
+        assert!(!involved.matches(&event("db-0")));
+    }
+}
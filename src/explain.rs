@@ -0,0 +1,368 @@
+use std::fmt::Write;
+
+use console::style;
+use serde_yaml::Value;
+
+use crate::{
+    filter::{match_state, DisplayKey, Filter, Operator},
+    path::ValuePath,
+};
+
+/// Explains whether the state matches the filter as a tree with a `✓` or `✗` line for every filter entry,
+/// e.g.
+///
+/// ```text
+/// ✗ status
+///   ✓ phase: "Running"
+///   ✗ replicas: expected 3, found 2
+///   ✗ readyReplicas: missing
+/// ```
+pub fn explain_state(filter: &Filter, state: &Value) -> String {
+    let mut explanation = String::new();
+    explain(filter, state, 0, &mut explanation);
+    explanation
+}
+
+/// Returns the path of the first value in the state that does not match the filter, walking the filter like
+/// [`explain_state`], or [`None`] if the state matches, e.g. `.status.replicas`, or `.status.readyReplicas`
+/// if it is missing. Values compared as a whole, e.g. with `--deep-contains`, are reported at their own path.
+pub fn first_mismatch(filter: &Filter, state: &Value) -> Option<ValuePath> {
+    mismatch(filter, state, ValuePath::default())
+}
+
+fn mismatch(filter: &Filter, state: &Value, path: ValuePath) -> Option<ValuePath> {
+    if match_state(filter, state) {
+        return None;
+    }
+
+    let nested = match (filter, state) {
+        (Filter::Mapping(entries) | Filter::Exact(entries), Value::Mapping(mapping)) => entries
+            .iter()
+            .find_map(|(key, filter)| {
+                let path = path.with_key(&DisplayKey(key).to_string());
+                match mapping.get(key) {
+                    Some(value) => mismatch(filter, value, path),
+                    None => Some(path),
+                }
+            })
+            .or_else(|| {
+                let Filter::Exact(entries) = filter else {
+                    return None;
+                };
+                mapping
+                    .keys()
+                    .find(|key| entries.iter().all(|(k, _)| k != *key))
+                    .map(|key| path.with_key(&DisplayKey(key).to_string()))
+            }),
+
+        (Filter::Sequence(elements), Value::Sequence(sequence)) => {
+            sequence.iter().enumerate().find_map(|(i, value)| {
+                elements
+                    .iter()
+                    .find_map(|element| mismatch(element, value, path.with_index(i)))
+            })
+        }
+
+        (Filter::All(filters), _) => filters
+            .iter()
+            .find_map(|filter| mismatch(filter, state, path.clone())),
+
+        _ => None,
+    };
+
+    Some(nested.unwrap_or(path))
+}
+
+/// Appends a single line of the explanation, indented according to the depth.
+/// Matching lines are green and failing ones are red, if colors are enabled for standard error.
+pub fn push_line(explanation: &mut String, depth: usize, matches: bool, text: &str) {
+    let line = if matches {
+        style(format!("✓ {text}")).green()
+    } else {
+        style(format!("✗ {text}")).red()
+    };
+    writeln!(
+        explanation,
+        "{:indent$}{}",
+        "",
+        line.for_stderr(),
+        indent = depth * 2
+    )
+    .expect("writing to a string should not fail");
+}
+
+/// Appends the explanation of the filter to an existing one, indented according to the depth.
+pub fn explain(filter: &Filter, state: &Value, depth: usize, explanation: &mut String) {
+    match (filter, state) {
+        (Filter::Mapping(entries), Value::Mapping(mapping)) => {
+            for (key, filter) in entries {
+                let label = DisplayKey(key).to_string();
+                explain_entry(&label, filter, mapping.get(key), depth, explanation);
+            }
+        }
+
+        (Filter::Exact(entries), Value::Mapping(mapping)) => {
+            for (key, filter) in entries {
+                let label = DisplayKey(key).to_string();
+                explain_entry(&label, filter, mapping.get(key), depth, explanation);
+            }
+            for key in mapping
+                .keys()
+                .filter(|key| entries.iter().all(|(k, _)| k != *key))
+            {
+                let label = DisplayKey(key).to_string();
+                push_line(explanation, depth, false, &format!("{label}: unexpected"));
+            }
+        }
+
+        (Filter::Sequence(elements), Value::Sequence(sequence)) => {
+            for (i, value) in sequence.iter().enumerate() {
+                for (j, element) in elements.iter().enumerate() {
+                    let label = if elements.len() == 1 {
+                        format!("[{i}]")
+                    } else {
+                        format!("[{i}] against element {} of the filter", j + 1)
+                    };
+                    explain_entry(&label, element, Some(value), depth, explanation);
+                }
+            }
+        }
+
+        (Filter::Identified { key, elements }, Value::Sequence(sequence)) => {
+            for element in elements {
+                let Some(identity) = element.identity(key) else {
+                    continue;
+                };
+                let mut counterparts = sequence
+                    .iter()
+                    .filter(|value| value.get(key.as_str()) == Some(identity));
+                let counterpart = counterparts
+                    .clone()
+                    .find(|value| match_state(element, value))
+                    .or_else(|| counterparts.next());
+
+                let label = format!("[{key}={}]", render(identity));
+                explain_entry(&label, element, counterpart, depth, explanation);
+            }
+        }
+
+        (Filter::Anywhere(filter), _) => match find_anywhere(filter, state, String::new()) {
+            Some((path, value)) => {
+                let path = if path.is_empty() { "." } else { &path };
+                push_line(explanation, depth, true, &format!("matches at {path}"));
+                explain(filter, value, depth + 1, explanation);
+            }
+            None => {
+                push_line(
+                    explanation,
+                    depth,
+                    false,
+                    "no nested value matches, compared with the top level",
+                );
+                explain(filter, state, depth + 1, explanation);
+            }
+        },
+
+        (Filter::All(filters), _) => {
+            for filter in filters {
+                explain(filter, state, depth, explanation);
+            }
+        }
+
+        _ => push_line(
+            explanation,
+            depth,
+            match_state(filter, state),
+            &describe_leaf(filter, state),
+        ),
+    }
+}
+
+/// Explains a mapping entry or a sequence element, missing from the state if `value` is `None`.
+fn explain_entry(
+    label: &str,
+    filter: &Filter,
+    value: Option<&Value>,
+    depth: usize,
+    explanation: &mut String,
+) {
+    let Some(value) = value else {
+        push_line(explanation, depth, false, &format!("{label}: missing"));
+        return;
+    };
+
+    let matches = match_state(filter, value);
+    match filter {
+        Filter::Scalar(_) | Filter::Operator(_) => push_line(
+            explanation,
+            depth,
+            matches,
+            &format!("{label}: {}", describe_leaf(filter, value)),
+        ),
+        _ => {
+            push_line(explanation, depth, matches, label);
+            explain(filter, value, depth + 1, explanation);
+        }
+    }
+}
+
+/// Describes what the filter expects, and what the state has instead if it does not match.
+fn describe_leaf(filter: &Filter, state: &Value) -> String {
+    let expected = match filter {
+        Filter::Scalar(value) => render(value),
+        Filter::Operator(operator) => operator.to_string(),
+        Filter::Mapping(_) => "a mapping".into(),
+        Filter::Exact(_) => "a mapping with exactly the given keys".into(),
+        Filter::Sequence(_) | Filter::Identified { .. } => "a sequence".into(),
+        Filter::Anywhere(_) | Filter::All(_) => "a matching value".into(),
+    };
+
+    if match_state(filter, state) {
+        return expected;
+    }
+
+    match filter {
+        // The whole value is large and not what is compared, so only the values at the paths are shown.
+        Filter::Operator(Operator::EqPath(left, right)) => {
+            let found = |path: &ValuePath| path.get(state).map_or_else(|| "missing".into(), render);
+            format!(
+                "expected {expected}, found {} and {}",
+                found(left),
+                found(right)
+            )
+        }
+        _ => format!("expected {expected}, found {}", render(state)),
+    }
+}
+
+/// Returns the path and the value of the first value nested in the state, at any depth, that matches the filter.
+fn find_anywhere<'a>(
+    filter: &Filter,
+    state: &'a Value,
+    path: String,
+) -> Option<(String, &'a Value)> {
+    if match_state(filter, state) {
+        return Some((path, state));
+    }
+
+    match state {
+        Value::Mapping(mapping) => mapping.iter().find_map(|(key, value)| {
+            find_anywhere(filter, value, format!("{path}.{}", DisplayKey(key)))
+        }),
+        Value::Sequence(sequence) => sequence
+            .iter()
+            .enumerate()
+            .find_map(|(i, value)| find_anywhere(filter, value, format!("{path}[{i}]"))),
+        Value::Tagged(tagged) => find_anywhere(filter, &tagged.value, path),
+        _ => None,
+    }
+}
+
+/// Renders a value as compact JSON, so that e.g. the string `"3"` and the number `3` can be told apart.
+fn render(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| DisplayKey(value).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Explains the state without colors.
+    fn explain_plain(filter: &str, state: &str) -> String {
+        console::set_colors_enabled_stderr(false);
+        let filter = Filter::parse(serde_yaml::from_str(filter).unwrap()).unwrap();
+        explain_state(&filter, &serde_yaml::from_str(state).unwrap())
+    }
+
+    #[test]
+    fn mismatched_and_missing_fields_are_explained() {
+        let explanation = explain_plain(
+            "{ status: { phase: Running, podIP: 10.0.0.1 } }",
+            "{ status: { phase: Pending } }",
+        );
+        assert_eq!(
+            explanation,
+            "✗ status\n  ✗ phase: expected \"Running\", found \"Pending\"\n  ✗ podIP: missing\n"
+        );
+    }
+
+    #[test]
+    fn matching_fields_are_explained() {
+        let explanation = explain_plain(
+            "{ status: { phase: Running, replicas: 3, readyReplicas: 3 } }",
+            "{ status: { phase: Running, replicas: 2 } }",
+        );
+        assert_eq!(
+            explanation,
+            "✗ status\n  ✓ phase: \"Running\"\n  ✗ replicas: expected 3, found 2\n  ✗ readyReplicas: missing\n"
+        );
+
+        let explanation = explain_plain("{ spec: { replicas: 3 } }", "{ spec: { replicas: 3 } }");
+        assert_eq!(explanation, "✓ spec\n  ✓ replicas: 3\n");
+    }
+
+    #[test]
+    fn strings_and_numbers_are_told_apart() {
+        let explanation = explain_plain("{ replicas: \"3\" }", "{ replicas: 3 }");
+        assert_eq!(explanation, "✗ replicas: expected \"3\", found 3\n");
+    }
+
+    #[test]
+    fn lines_are_not_colored_when_colors_are_disabled() {
+        let explanation = explain_plain(
+            "{ phase: Running, ready: true }",
+            "{ phase: Pending, ready: true }",
+        );
+        assert!(!explanation.contains('\x1b'), "{explanation:?}");
+    }
+
+    #[test]
+    fn first_mismatch_reports_the_path_of_the_failing_value() {
+        let path = |filter: &str, state: &str| {
+            let filter = Filter::parse(serde_yaml::from_str(filter).unwrap()).unwrap();
+            first_mismatch(&filter, &serde_yaml::from_str(state).unwrap())
+                .map(|path| path.to_string())
+        };
+
+        let filter = "{ status: { phase: Running, replicas: 3 } }";
+        assert_eq!(
+            path(filter, "{ status: { phase: Running, replicas: 2 } }").as_deref(),
+            Some(".status.replicas")
+        );
+        assert_eq!(
+            path(filter, "{ status: { phase: Running } }").as_deref(),
+            Some(".status.replicas")
+        );
+        assert_eq!(path(filter, "{ spec: {} }").as_deref(), Some(".status"));
+        assert_eq!(
+            path(filter, "{ status: { phase: Running, replicas: 3 } }"),
+            None
+        );
+
+        assert_eq!(
+            path(
+                "{ status: { containerStatuses: [{ ready: true }] } }",
+                "{ status: { containerStatuses: [{ ready: true }, { ready: false }] } }"
+            )
+            .as_deref(),
+            Some(".status.containerStatuses[1].ready")
+        );
+
+        assert_eq!(
+            path(
+                "{ status: { replicas: { $between: [3, 5] } } }",
+                "{ status: { replicas: 2 } }"
+            )
+            .as_deref(),
+            Some(".status.replicas")
+        );
+        assert_eq!(
+            path(
+                "{ metadata: { labels: { app.kubernetes.io/name: web } } }",
+                "{ metadata: { labels: { app.kubernetes.io/name: api } } }"
+            )
+            .as_deref(),
+            Some(".metadata.labels[\"app.kubernetes.io/name\"]")
+        );
+    }
+}
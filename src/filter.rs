@@ -0,0 +1,1203 @@
+use std::{cmp::Ordering, fmt};
+
+use anyhow::{Context, Result};
+use k8s_openapi::chrono::{DateTime, Utc};
+use regex::Regex;
+use semver::Version;
+use serde::{
+    de::{self, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_yaml::{
+    value::{Tag, TaggedValue},
+    Mapping, Number, Value,
+};
+
+use crate::{path::ValuePath, quantity::Quantity};
+
+/// Resource state filter parsed from its YAML representation.
+#[derive(Clone)]
+pub enum Filter {
+    /// Matches a mapping that contains all given keys with matching values.
+    Mapping(Vec<(Value, Filter)>),
+    /// Matches a mapping that contains all given keys with matching values, and no other keys.
+    /// See [`Filter::exact`].
+    Exact(Vec<(Value, Filter)>),
+    /// Matches a sequence whose every element matches every given filter.
+    Sequence(Vec<Filter>),
+    /// Matches a sequence that has, for every given filter, an element with the same value at `key`
+    /// that matches the filter, regardless of its position. See [`Filter::with_array_identity_key`].
+    Identified { key: String, elements: Vec<Filter> },
+    /// Matches a value equal to the given one.
+    Scalar(Value),
+    /// Matches a value using an operator, e.g. `{ $approx: { value: 0.8, epsilon: 0.05 } }`.
+    Operator(Operator),
+    /// Matches a value that matches the filter itself or contains a nested value, at any depth, that does.
+    Anywhere(Box<Filter>),
+    /// Matches a value that matches all given filters.
+    All(Vec<Filter>),
+}
+
+/// State filter read from a file, named after the file in diagnostics.
+#[derive(Clone)]
+pub struct NamedFilter {
+    pub name: String,
+    pub filter: Filter,
+}
+
+/// Returns the first of the filters that matches the state.
+pub fn first_matching<'a>(filters: &'a [NamedFilter], state: &Value) -> Option<&'a NamedFilter> {
+    filters
+        .iter()
+        .find(|named| match_state(&named.filter, state))
+}
+
+/// Deserializes the YAML document of a filter.
+/// Unquoted YAML 1.1 booleans in values, e.g. `yes` or `Off`, are deserialized as the booleans they stand for,
+/// see [`yaml11_bool`], while quoted ones, e.g. `"yes"`, and keys stay strings.
+pub fn from_slice(raw_bytes: &[u8]) -> serde_yaml::Result<Value> {
+    Yaml11Value { raw_bytes }.deserialize(serde_yaml::Deserializer::from_slice(raw_bytes))
+}
+
+/// Operators that can be used in place of a plain value in the state filter.
+#[derive(Clone)]
+pub enum Operator {
+    /// Matches a number within `epsilon` of `value`, inclusive.
+    Approx { value: f64, epsilon: f64 },
+    /// Matches a number between `min` and `max`, inclusive, e.g. `{ $between: [3, 9] }`.
+    Between { min: f64, max: f64 },
+    /// Matches a semantic version that compares to `version` as required,
+    /// e.g. `{ $semverGte: "1.20.0" }`.
+    Semver {
+        comparison: Comparison,
+        version: Version,
+    },
+    /// Matches a Kubernetes quantity that compares to `quantity` as required,
+    /// e.g. `{ $quantityGte: "5Gi" }`.
+    Quantity {
+        comparison: Comparison,
+        quantity: Quantity,
+    },
+    /// Matches a sequence or a mapping whose number of elements compares to `size` as required,
+    /// e.g. `{ $sizeGte: 3 }`.
+    Size { comparison: Comparison, size: usize },
+    /// Matches a value of the given type, e.g. `{ $type: string }`.
+    Type(ValueType),
+    /// Matches an RFC3339 timestamp strictly after the given instant, e.g. `{ $after: "2024-05-01T12:00:00Z" }`.
+    After(DateTime<Utc>),
+    /// Matches an RFC3339 timestamp strictly before the given instant, e.g. `{ $before: "2024-05-01T12:00:00Z" }`.
+    Before(DateTime<Utc>),
+    /// Matches a value with equal values at both paths, relative to the value,
+    /// e.g. `{ $eqPath: [".status.replicas", ".spec.replicas"] }`.
+    EqPath(ValuePath, ValuePath),
+    /// Matches a string that matches any of the regular expressions, e.g. `{ $regexAny: ["^Ready$", "^Healthy$"] }`.
+    RegexAny(Vec<Regex>),
+}
+
+impl Filter {
+    /// Returns a filter that matches any resource state.
+    pub fn any() -> Self {
+        Self::Mapping(Vec::new())
+    }
+
+    /// Adds a requirement that the matched mapping contains `key` with a value matching `filter`.
+    /// Requirements for a key that is already present apply together with the existing ones.
+    /// The key of a filter matching anywhere, or of an operator, is still required at the top level.
+    pub fn with_entry(self, key: &str, filter: Filter) -> Result<Self> {
+        let entry = (key.into(), filter);
+        match self {
+            Self::Mapping(mut entries) => {
+                entries.push(entry);
+                Ok(Self::Mapping(entries))
+            }
+            Self::Exact(mut entries) => {
+                entries.push(entry);
+                Ok(Self::Exact(entries))
+            }
+            Self::Anywhere(_) | Self::Operator(_) => {
+                Ok(Self::All(vec![self, Self::Mapping(vec![entry])]))
+            }
+            Self::All(mut filters) => {
+                filters.push(Self::Mapping(vec![entry]));
+                Ok(Self::All(filters))
+            }
+            _ => anyhow::bail!("state filter must be a mapping to require key `{key}`"),
+        }
+    }
+
+    /// Makes the filter match the value nested in the state under the keys, e.g. `status` and `conditions`,
+    /// instead of the whole state.
+    pub fn rooted_at(self, keys: &[String]) -> Self {
+        keys.iter().rev().fold(self, |filter, key| {
+            Self::Mapping(vec![(key.as_str().into(), filter)])
+        })
+    }
+
+    /// Makes the filter match a value that matches it at any depth, not only at the top level,
+    /// see [`Filter::Anywhere`].
+    pub fn anywhere(self) -> Self {
+        Self::Anywhere(Box::new(self))
+    }
+
+    /// Makes every mapping of the filter match only mappings without other keys, see [`Filter::Exact`].
+    /// Applies at every depth, including elements of sequences and filters matching anywhere.
+    pub fn exact(self) -> Self {
+        let exact_entries = |entries: Vec<(Value, Filter)>| {
+            entries
+                .into_iter()
+                .map(|(key, filter)| (key, filter.exact()))
+                .collect()
+        };
+
+        match self {
+            Self::Mapping(entries) | Self::Exact(entries) => Self::Exact(exact_entries(entries)),
+            Self::Sequence(elements) => {
+                Self::Sequence(elements.into_iter().map(Self::exact).collect())
+            }
+            Self::Identified { key, elements } => Self::Identified {
+                key,
+                elements: elements.into_iter().map(Self::exact).collect(),
+            },
+            Self::Anywhere(filter) => filter.exact().anywhere(),
+            Self::All(filters) => Self::All(filters.into_iter().map(Self::exact).collect()),
+            other => other,
+        }
+    }
+
+    /// Makes sequences match regardless of the order of their elements,
+    /// pairing every element of the filter with the element of the state that has the same value at `key`.
+    /// Applies only to sequences whose every element is a mapping with a plain value at `key`.
+    pub fn with_array_identity_key(self, key: &str) -> Self {
+        match self {
+            Self::Mapping(entries) => Self::Mapping(
+                entries
+                    .into_iter()
+                    .map(|(k, filter)| (k, filter.with_array_identity_key(key)))
+                    .collect(),
+            ),
+
+            Self::Exact(entries) => Self::Exact(
+                entries
+                    .into_iter()
+                    .map(|(k, filter)| (k, filter.with_array_identity_key(key)))
+                    .collect(),
+            ),
+
+            Self::Sequence(elements) => {
+                let elements = elements
+                    .into_iter()
+                    .map(|element| element.with_array_identity_key(key))
+                    .collect::<Vec<_>>();
+
+                if elements.is_empty()
+                    || elements
+                        .iter()
+                        .any(|element| element.identity(key).is_none())
+                {
+                    return Self::Sequence(elements);
+                }
+
+                Self::Identified {
+                    key: key.into(),
+                    elements,
+                }
+            }
+
+            Self::Anywhere(filter) => filter.with_array_identity_key(key).anywhere(),
+
+            Self::All(filters) => Self::All(
+                filters
+                    .into_iter()
+                    .map(|filter| filter.with_array_identity_key(key))
+                    .collect(),
+            ),
+
+            other => other,
+        }
+    }
+
+    /// Returns the plain value required at `key`, if this is a mapping filter.
+    pub fn identity(&self, key: &str) -> Option<&Value> {
+        let (Self::Mapping(entries) | Self::Exact(entries)) = self else {
+            return None;
+        };
+
+        entries.iter().find_map(|(k, filter)| match filter {
+            Self::Scalar(value) if k.as_str() == Some(key) => Some(value),
+            _ => None,
+        })
+    }
+
+    pub fn parse(value: Value) -> Result<Self> {
+        match value {
+            Value::Mapping(mapping) => {
+                let operator = mapping
+                    .keys()
+                    .filter_map(Value::as_str)
+                    .find(|key| key.starts_with('$'))
+                    .map(ToString::to_string);
+
+                if let Some(operator) = operator {
+                    anyhow::ensure!(
+                        mapping.len() == 1,
+                        "operator `{operator}` must be the only key in its mapping"
+                    );
+                    let (_, args) = mapping.into_iter().next().expect("length was just checked");
+                    return Operator::parse(&operator, args)
+                        .map(Self::Operator)
+                        .with_context(|| format!("invalid `{operator}` operator"));
+                }
+
+                mapping
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let filter = Self::parse(value).with_context(|| {
+                            format!("invalid filter for key `{}`", DisplayKey(&key))
+                        })?;
+                        Ok((key, filter))
+                    })
+                    .collect::<Result<_>>()
+                    .map(Self::Mapping)
+            }
+
+            Value::Sequence(sequence) => sequence
+                .into_iter()
+                .map(Self::parse)
+                .collect::<Result<_>>()
+                .map(Self::Sequence),
+
+            Value::Tagged(tagged) => Self::parse(tagged.value),
+
+            scalar => Ok(Self::Scalar(scalar)),
+        }
+    }
+}
+
+impl Operator {
+    fn parse(name: &str, args: Value) -> Result<Self> {
+        match name {
+            "$approx" => {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct Args {
+                    value: f64,
+                    epsilon: f64,
+                }
+
+                let Args { value, epsilon } = serde_yaml::from_value(args)?;
+                anyhow::ensure!(epsilon >= 0.0, "epsilon must not be negative");
+
+                Ok(Self::Approx { value, epsilon })
+            }
+
+            "$between" => {
+                let [min, max]: [f64; 2] = serde_yaml::from_value(args)
+                    .context("expected a sequence of two numbers, the minimum and the maximum")?;
+                anyhow::ensure!(min <= max, "minimum must not be greater than the maximum");
+
+                Ok(Self::Between { min, max })
+            }
+
+            "$type" => {
+                // Unquoted `null` is parsed as a null value rather than a string.
+                if args.is_null() {
+                    return Ok(Self::Type(ValueType::Null));
+                }
+
+                let name: String = serde_yaml::from_value(args)?;
+                ValueType::parse(&name).map(Self::Type)
+            }
+
+            "$eqPath" => {
+                let [left, right]: [String; 2] =
+                    serde_yaml::from_value(args).context("expected a sequence of two paths")?;
+                let parse = |path: String| path.parse::<ValuePath>().map_err(anyhow::Error::msg);
+
+                Ok(Self::EqPath(parse(left)?, parse(right)?))
+            }
+
+            "$regexAny" => {
+                let patterns: Vec<String> = serde_yaml::from_value(args)
+                    .context("expected a sequence of regular expressions")?;
+                anyhow::ensure!(
+                    !patterns.is_empty(),
+                    "at least one regular expression is required"
+                );
+
+                patterns
+                    .iter()
+                    .map(|pattern| {
+                        Regex::new(pattern)
+                            .with_context(|| format!("invalid regular expression `{pattern}`"))
+                    })
+                    .collect::<Result<_>>()
+                    .map(Self::RegexAny)
+            }
+
+            "$after" | "$before" => {
+                let timestamp: String = serde_yaml::from_value(args)?;
+                let timestamp = parse_timestamp(&timestamp)
+                    .with_context(|| format!("`{timestamp}` is not a valid RFC3339 timestamp"))?;
+
+                Ok(if name == "$after" {
+                    Self::After(timestamp)
+                } else {
+                    Self::Before(timestamp)
+                })
+            }
+
+            _ => {
+                if let Some(comparison) = name
+                    .strip_prefix("$semver")
+                    .and_then(Comparison::from_suffix)
+                {
+                    let version: String = serde_yaml::from_value(args)?;
+                    let version = parse_semver(&version)
+                        .with_context(|| format!("`{version}` is not a valid semantic version"))?;
+
+                    return Ok(Self::Semver {
+                        comparison,
+                        version,
+                    });
+                }
+
+                if let Some(comparison) = name
+                    .strip_prefix("$quantity")
+                    .and_then(Comparison::from_suffix)
+                {
+                    let quantity = Quantity::from_value(&args).with_context(|| {
+                        format!("`{}` is not a valid quantity", DisplayKey(&args))
+                    })?;
+
+                    return Ok(Self::Quantity {
+                        comparison,
+                        quantity,
+                    });
+                }
+
+                if let Some(comparison) = name.strip_prefix("$size").and_then(|suffix| {
+                    if suffix.is_empty() {
+                        Some(Comparison::Eq)
+                    } else {
+                        Comparison::from_suffix(suffix)
+                    }
+                }) {
+                    let size = serde_yaml::from_value(args)?;
+
+                    return Ok(Self::Size { comparison, size });
+                }
+
+                anyhow::bail!("unknown operator")
+            }
+        }
+    }
+
+    fn matches(&self, state: &Value) -> bool {
+        match self {
+            Self::Approx { value, epsilon } => state
+                .as_f64()
+                .is_some_and(|state| state >= value - epsilon && state <= value + epsilon),
+
+            Self::Between { min, max } => state
+                .as_f64()
+                .is_some_and(|state| state >= *min && state <= *max),
+
+            Self::Semver {
+                comparison,
+                version,
+            } => state
+                .as_str()
+                .and_then(parse_semver)
+                // Build metadata does not affect precedence, e.g. `1.28.3+k3s1` equals `1.28.3`.
+                .is_some_and(|state| comparison.holds(state.cmp_precedence(version))),
+
+            Self::Quantity {
+                comparison,
+                quantity,
+            } => Quantity::from_value(state)
+                .is_some_and(|state| comparison.holds(state.cmp(quantity))),
+
+            Self::Size { comparison, size } => {
+                let len = match state {
+                    Value::Sequence(sequence) => sequence.len(),
+                    Value::Mapping(mapping) => mapping.len(),
+                    _ => return false,
+                };
+                comparison.holds(len.cmp(size))
+            }
+
+            Self::Type(value_type) => value_type.matches(state),
+
+            Self::After(timestamp) => state
+                .as_str()
+                .and_then(parse_timestamp)
+                .is_some_and(|state| state > *timestamp),
+
+            Self::Before(timestamp) => state
+                .as_str()
+                .and_then(parse_timestamp)
+                .is_some_and(|state| state < *timestamp),
+
+            Self::EqPath(left, right) => match (left.get(state), right.get(state)) {
+                (Some(left), Some(right)) => equal_values(left, right),
+                _ => false,
+            },
+
+            Self::RegexAny(regexes) => state
+                .as_str()
+                .is_some_and(|state| regexes.iter().any(|regex| regex.is_match(state))),
+        }
+    }
+}
+
+/// Displays the operator as in the state filter, e.g. `$semverGte 1.20.0`.
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Approx { value, epsilon } => write!(f, "$approx {value} ± {epsilon}"),
+            Self::Between { min, max } => write!(f, "$between [{min}, {max}]"),
+            Self::Semver {
+                comparison,
+                version,
+            } => write!(f, "$semver{} {version}", comparison.suffix()),
+            Self::Quantity {
+                comparison,
+                quantity,
+            } => write!(f, "$quantity{} {quantity}", comparison.suffix()),
+            Self::Size { comparison, size } => write!(f, "$size{} {size}", comparison.suffix()),
+            Self::Type(value_type) => write!(f, "$type {}", value_type.name()),
+            Self::After(timestamp) => write!(f, "$after {}", timestamp.to_rfc3339()),
+            Self::Before(timestamp) => write!(f, "$before {}", timestamp.to_rfc3339()),
+            Self::EqPath(left, right) => write!(f, "$eqPath [{left}, {right}]"),
+            Self::RegexAny(regexes) => {
+                let patterns = regexes
+                    .iter()
+                    .map(|regex| format!("{:?}", regex.as_str()))
+                    .collect::<Vec<_>>();
+                write!(f, "$regexAny [{}]", patterns.join(", "))
+            }
+        }
+    }
+}
+
+/// Checks whether the resource state, serialized once by the caller, matches the filter.
+pub fn match_state(filter: &Filter, state: &Value) -> bool {
+    match (filter, state) {
+        (Filter::Mapping(m1), Value::Mapping(m2)) => match_entries(m1, m2),
+
+        (Filter::Exact(m1), Value::Mapping(m2)) => {
+            // Keys may repeat in the filter, see `Filter::with_entry`, so the keys are not just counted.
+            m2.keys().all(|k| m1.iter().any(|(k1, _)| k1 == k)) && match_entries(m1, m2)
+        }
+
+        (Filter::Sequence(s1), Value::Sequence(s2)) => {
+            s1.iter().all(|v1| s2.iter().all(|v2| match_state(v1, v2)))
+        }
+
+        (Filter::Identified { key, elements }, Value::Sequence(s2)) => elements.iter().all(|v1| {
+            let identity = v1.identity(key).expect("elements should have identities");
+            s2.iter()
+                .any(|v2| v2.get(key.as_str()) == Some(identity) && match_state(v1, v2))
+        }),
+
+        (Filter::Operator(operator), _) => operator.matches(state),
+
+        (Filter::Anywhere(filter), _) => match_anywhere(filter, state),
+
+        (Filter::All(filters), _) => filters.iter().all(|filter| match_state(filter, state)),
+
+        (Filter::Scalar(value), _) => equal_values(value, state),
+
+        _ => false,
+    }
+}
+
+/// Checks whether the mapping contains all keys of the entries with matching values.
+fn match_entries(entries: &[(Value, Filter)], mapping: &Mapping) -> bool {
+    for (k, v1) in entries {
+        let Some(v2) = mapping.get(k) else {
+            return false;
+        };
+
+        if !match_state(v1, v2) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Checks whether the values are equal.
+/// Integers and floating point numbers are compared by value, e.g. `3` equals `3.0`, as serializers differ in how they
+/// represent numbers. Integers are compared with each other exactly.
+fn equal_values(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => {
+            match (exact_integer(left), exact_integer(right)) {
+                (Some(left), Some(right)) => left == right,
+                _ => left.as_f64() == right.as_f64(),
+            }
+        }
+        _ => left == right,
+    }
+}
+
+/// Returns the exact integer value of the number, or `None` for a floating point number with a fraction.
+/// Integers are not converted to floating point numbers, which would round them above 2^53.
+fn exact_integer(number: &Number) -> Option<i128> {
+    const LIMIT: f64 = (1u128 << 127) as f64;
+
+    match (number.as_i64(), number.as_u64(), number.as_f64()) {
+        (Some(integer), _, _) => Some(integer.into()),
+        (_, Some(integer), _) => Some(integer.into()),
+        (_, _, Some(float)) if float.fract() == 0.0 && (-LIMIT..LIMIT).contains(&float) => {
+            Some(float as i128)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the boolean that the string stands for in YAML 1.1, e.g. `yes` for `true` and `Off` for `false`.
+/// Booleans of YAML 1.2, e.g. `true` or `False`, are already parsed as booleans.
+/// The single letters `y` and `n` are left out, as they are more likely to be meant as strings.
+fn yaml11_bool(s: &str) -> Option<bool> {
+    match s {
+        "yes" | "Yes" | "YES" | "on" | "On" | "ON" => Some(true),
+        "no" | "No" | "NO" | "off" | "Off" | "OFF" => Some(false),
+        _ => None,
+    }
+}
+
+/// Deserializes a [`Value`] from the YAML document, see [`from_slice`].
+#[derive(Clone, Copy)]
+struct Yaml11Value<'de> {
+    raw_bytes: &'de [u8],
+}
+
+impl<'de> Yaml11Value<'de> {
+    /// Checks whether the string, borrowed from the document, is written in quotes.
+    fn is_quoted(self, s: &str) -> bool {
+        let Some(start) = (s.as_ptr() as usize).checked_sub(self.raw_bytes.as_ptr() as usize)
+        else {
+            return false;
+        };
+        let before = start
+            .checked_sub(1)
+            .and_then(|before| self.raw_bytes.get(before));
+        let after = self.raw_bytes.get(start + s.len());
+        matches!(
+            (before, after),
+            (Some(b'"'), Some(b'"')) | (Some(b'\''), Some(b'\''))
+        )
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Yaml11Value<'de> {
+    type Value = Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Yaml11Value<'de> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any YAML value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Value::Number(v.into()))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(Value::Number(v.into()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Value::Number(v.into()))
+    }
+
+    /// Plain scalars are always borrowed from the document, the other strings are written in quotes or as blocks.
+    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E> {
+        match yaml11_bool(v) {
+            Some(b) if !self.is_quoted(v) => Ok(Value::Bool(b)),
+            _ => Ok(Value::String(v.into())),
+        }
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Value::String(v.into()))
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut sequence = Vec::new();
+        while let Some(value) = seq.next_element_seed(self)? {
+            sequence.push(value);
+        }
+        Ok(Value::Sequence(sequence))
+    }
+
+    /// Keys are deserialized as they are, as keys in the state are strings.
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut mapping = Mapping::new();
+        while let Some(key) = map.next_key::<Value>()? {
+            if mapping.contains_key(&key) {
+                return Err(de::Error::custom(format_args!(
+                    "duplicate entry with key {}",
+                    DisplayKey(&key)
+                )));
+            }
+            let value = map.next_value_seed(self)?;
+            mapping.insert(key, value);
+        }
+        Ok(Value::Mapping(mapping))
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        let (tag, variant) = data.variant::<String>()?;
+        let value = variant.newtype_variant_seed(self)?;
+        Ok(Value::Tagged(Box::new(TaggedValue {
+            tag: Tag::new(tag),
+            value,
+        })))
+    }
+}
+
+/// Checks whether the state or any value nested in it matches the filter.
+/// Every nested value is tried, so the cost grows with the size of the state.
+fn match_anywhere(filter: &Filter, state: &Value) -> bool {
+    match_state(filter, state)
+        || match state {
+            Value::Mapping(mapping) => mapping.values().any(|value| match_anywhere(filter, value)),
+            Value::Sequence(sequence) => sequence.iter().any(|value| match_anywhere(filter, value)),
+            Value::Tagged(tagged) => match_anywhere(filter, &tagged.value),
+            _ => false,
+        }
+}
+
+/// Comparison required by an ordering operator, selected with the operator name suffix.
+#[derive(Clone, Copy)]
+pub enum Comparison {
+    Lt,
+    Lte,
+    Eq,
+    Gte,
+    Gt,
+}
+
+impl Comparison {
+    /// Parses the suffix of an operator name, e.g. `Gte` in `$semverGte`.
+    fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "Lt" => Some(Self::Lt),
+            "Lte" => Some(Self::Lte),
+            "Eq" => Some(Self::Eq),
+            "Gte" => Some(Self::Gte),
+            "Gt" => Some(Self::Gt),
+            _ => None,
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match self {
+            Self::Lt => "Lt",
+            Self::Lte => "Lte",
+            Self::Eq => "Eq",
+            Self::Gte => "Gte",
+            Self::Gt => "Gt",
+        }
+    }
+
+    /// Returns whether `state.cmp(filter)` satisfies this comparison.
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Self::Lt => ordering.is_lt(),
+            Self::Lte => ordering.is_le(),
+            Self::Eq => ordering.is_eq(),
+            Self::Gte => ordering.is_ge(),
+            Self::Gt => ordering.is_gt(),
+        }
+    }
+}
+
+/// Type of a value checked by the `$type` operator.
+#[derive(Clone, Copy)]
+pub enum ValueType {
+    String,
+    Number,
+    Bool,
+    Sequence,
+    Mapping,
+    Null,
+}
+
+impl ValueType {
+    fn name(self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Number => "number",
+            Self::Bool => "bool",
+            Self::Sequence => "sequence",
+            Self::Mapping => "mapping",
+            Self::Null => "null",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "string" => Ok(Self::String),
+            "number" => Ok(Self::Number),
+            "bool" => Ok(Self::Bool),
+            "sequence" => Ok(Self::Sequence),
+            "mapping" => Ok(Self::Mapping),
+            "null" => Ok(Self::Null),
+            _ => anyhow::bail!(
+                "unknown type `{name}`, expected one of `string`, `number`, `bool`, `sequence`, `mapping` or `null`"
+            ),
+        }
+    }
+
+    fn matches(self, value: &Value) -> bool {
+        match (self, value) {
+            (_, Value::Tagged(tagged)) => self.matches(&tagged.value),
+            (Self::String, Value::String(_))
+            | (Self::Number, Value::Number(_))
+            | (Self::Bool, Value::Bool(_))
+            | (Self::Sequence, Value::Sequence(_))
+            | (Self::Mapping, Value::Mapping(_))
+            | (Self::Null, Value::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Parses a semantic version, allowing an optional `v` prefix.
+fn parse_semver(value: &str) -> Option<Version> {
+    Version::parse(value.strip_prefix('v').unwrap_or(value)).ok()
+}
+
+/// Parses an RFC3339 timestamp.
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.to_utc())
+}
+
+/// Displays a mapping key in error messages and explanations.
+pub struct DisplayKey<'a>(pub &'a Value);
+
+impl fmt::Display for DisplayKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Value::String(key) => f.write_str(key),
+            other => {
+                let serialized = serde_yaml::to_string(other).map_err(|_| fmt::Error)?;
+                f.write_str(serialized.trim_end())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(filter: &str) -> Filter {
+        Filter::parse(serde_yaml::from_str(filter).unwrap()).unwrap()
+    }
+
+    fn matches(filter: &str, state: &str) -> bool {
+        match_state(&parse(filter), &serde_yaml::from_str(state).unwrap())
+    }
+
+    #[test]
+    fn semver_ignores_build_metadata() {
+        assert!(matches("{ $semverEq: 1.28.3 }", "v1.28.3+k3s1"));
+        assert!(matches("{ $semverGte: 1.28.3+other }", "1.28.3+k3s1"));
+        assert!(!matches("{ $semverGt: 1.28.3 }", "1.28.3+k3s1"));
+        assert!(matches("{ $semverLt: 1.28.3 }", "1.28.3-rc.1+build.5"));
+    }
+
+    #[test]
+    fn numbers_are_compared_exactly() {
+        assert!(matches("3", "3.0"));
+        assert!(matches("3.0", "3"));
+        assert!(!matches("3", "3.5"));
+        assert!(matches("9007199254740993", "9007199254740993"));
+        // 2^53 + 1 has no floating point representation, so it must not be rounded to 2^53.
+        assert!(!matches("9007199254740993", "9007199254740992.0"));
+        assert!(!matches("9007199254740992.0", "9007199254740993"));
+        assert!(matches("9007199254740992.0", "9007199254740992"));
+        assert!(matches("18446744073709551615", "18446744073709551615"));
+        assert!(!matches("18446744073709551615", "18446744073709551614"));
+        assert!(!matches("-9223372036854775807", "-9223372036854775808.0"));
+    }
+
+    #[test]
+    fn approx_allows_a_tolerance() {
+        let filter = "{ $approx: { value: 1.0, epsilon: 0.1 } }";
+        assert!(matches(filter, "1.05"));
+        assert!(matches(filter, "1"));
+        assert!(matches(filter, "0.95"));
+        assert!(!matches(filter, "1.2"));
+        assert!(!matches(filter, "\"1.0\""));
+        assert!(matches("{ $approx: { value: 3, epsilon: 0 } }", "3.0"));
+
+        let parse = |filter: &str| Filter::parse(serde_yaml::from_str(filter).unwrap());
+        assert!(parse("{ $approx: { value: 1, epsilon: -0.1 } }").is_err());
+        assert!(parse("{ $approx: { value: 1 } }").is_err());
+        assert!(parse("{ $approx: { value: 1, epsilon: 0.1, other: 2 } }").is_err());
+        assert!(parse("{ $approx: 1, other: 2 }").is_err());
+    }
+
+    #[test]
+    fn timestamps_are_compared_in_time() {
+        assert!(matches(
+            "{ $after: \"2024-01-01T00:00:00Z\" }",
+            "2024-06-01T12:00:00Z"
+        ));
+        assert!(!matches(
+            "{ $before: \"2024-01-01T00:00:00Z\" }",
+            "2024-06-01T12:00:00Z"
+        ));
+        assert!(matches(
+            "{ $before: \"2024-01-01T00:00:00Z\" }",
+            "2024-01-01T00:30:00+01:00"
+        ));
+        assert!(!matches("{ $after: \"2024-01-01T00:00:00Z\" }", "soon"));
+
+        let parse = |filter: &str| Filter::parse(serde_yaml::from_str(filter).unwrap());
+        assert!(parse("{ $after: yesterday }").is_err());
+        assert!(parse("{ $before: \"2024-01-01\" }").is_err());
+        assert!(parse("{ $after: 1704067200 }").is_err());
+    }
+
+    #[test]
+    fn dotted_keys_are_compared_literally() {
+        let filter = "{ metadata: { labels: { app.kubernetes.io/name: web } } }";
+        assert!(matches(
+            filter,
+            "{ metadata: { labels: { app.kubernetes.io/name: web, tier: frontend } } }"
+        ));
+        assert!(!matches(
+            filter,
+            "{ metadata: { labels: { app: { kubernetes: { io/name: web } } } } }"
+        ));
+    }
+
+    #[test]
+    fn with_entry_adds_requirements() {
+        let filter = parse("{ status: { phase: Running } }")
+            .with_entry("metadata", parse("{ labels: { app: web } }"))
+            .unwrap();
+        assert!(match_state(
+            &filter,
+            &serde_yaml::from_str(
+                "{ metadata: { labels: { app: web } }, status: { phase: Running } }"
+            )
+            .unwrap()
+        ));
+        assert!(!match_state(
+            &filter,
+            &serde_yaml::from_str("{ status: { phase: Running } }").unwrap()
+        ));
+
+        // Requirements are merged with the ones already present for the key.
+        let filter = parse("{ metadata: { name: web } }")
+            .with_entry("metadata", parse("{ labels: { app: web } }"))
+            .unwrap();
+        assert!(!match_state(
+            &filter,
+            &serde_yaml::from_str("{ metadata: { name: web } }").unwrap()
+        ));
+        assert!(!match_state(
+            &filter,
+            &serde_yaml::from_str("{ metadata: { labels: { app: web } } }").unwrap()
+        ));
+
+        assert!(parse("[a]").with_entry("metadata", Filter::any()).is_err());
+    }
+
+    #[test]
+    fn quantities_are_compared_by_value() {
+        assert!(matches("{ $quantityGte: 1Gi }", "1024Mi"));
+        assert!(matches("{ $quantityEq: 1 }", "1000m"));
+        assert!(matches("{ $quantityLt: 2 }", "1.5"));
+        assert!(!matches("{ $quantityGt: 500m }", "0.5"));
+        assert!(!matches("{ $quantityGte: 1Gi }", "1G"));
+        assert!(!matches("{ $quantityGte: 1Gi }", "big"));
+
+        let parse = |filter: &str| Filter::parse(serde_yaml::from_str(filter).unwrap());
+        assert!(parse("{ $quantityGt: 1x }").is_err());
+        assert!(parse("{ $quantityAbout: 1 }").is_err());
+    }
+
+    #[test]
+    fn sequences_match_every_element() {
+        assert!(matches(
+            "[{ ready: true }]",
+            "[{ ready: true, name: a }, { ready: true, name: b }]"
+        ));
+        assert!(!matches(
+            "[{ ready: true }]",
+            "[{ ready: true, name: a }, { ready: false, name: b }]"
+        ));
+    }
+
+    #[test]
+    fn array_identity_key_pairs_elements() {
+        let filter = parse("{ conditions: [{ type: Ready, status: \"True\" }] }")
+            .with_array_identity_key("type");
+        let state = |yaml: &str| serde_yaml::from_str::<Value>(yaml).unwrap();
+        assert!(match_state(
+            &filter,
+            &state("{ conditions: [{ type: Synced, status: \"False\" }, { type: Ready, status: \"True\" }] }")
+        ));
+        assert!(!match_state(
+            &filter,
+            &state("{ conditions: [{ type: Ready, status: \"False\" }] }")
+        ));
+        assert!(!match_state(
+            &filter,
+            &state("{ conditions: [{ type: Synced, status: \"True\" }] }")
+        ));
+
+        // Without the key in every element, the elements are matched as without the option.
+        let filter =
+            parse("{ conditions: [{ status: \"True\" }] }").with_array_identity_key("type");
+        assert!(!match_state(
+            &filter,
+            &state("{ conditions: [{ type: Synced, status: \"False\" }, { type: Ready, status: \"True\" }] }")
+        ));
+    }
+
+    #[test]
+    fn anywhere_matches_nested_values() {
+        let filter = parse("{ phase: Failed }").anywhere();
+        let state = |yaml: &str| serde_yaml::from_str::<Value>(yaml).unwrap();
+        let nested = state("{ status: { containers: [{ phase: Failed }] } }");
+        assert!(match_state(&filter, &nested));
+        assert!(!match_state(&parse("{ phase: Failed }"), &nested));
+        assert!(match_state(&filter, &state("{ phase: Failed }")));
+        assert!(!match_state(
+            &filter,
+            &state("{ status: { containers: [{ phase: Running }] } }")
+        ));
+    }
+
+    #[test]
+    fn sizes_of_sequences_and_mappings_are_compared() {
+        assert!(matches("{ $size: 3 }", "[a, b, c]"));
+        assert!(!matches("{ $size: 2 }", "[a, b, c]"));
+        assert!(matches("{ $sizeGte: 2 }", "[a, b, c]"));
+        assert!(!matches("{ $sizeLt: 3 }", "[a, b, c]"));
+        assert!(matches("{ $size: 0 }", "[]"));
+        assert!(matches("{ $sizeGt: 1 }", "{ a: 1, b: 2 }"));
+        // Strings and missing values have no size.
+        assert!(!matches("{ $size: 3 }", "abc"));
+        assert!(!matches("{ items: { $size: 0 } }", "{}"));
+
+        let parse = |filter: &str| Filter::parse(serde_yaml::from_str(filter).unwrap());
+        assert!(parse("{ $size: -1 }").is_err());
+        assert!(parse("{ $sizeAbout: 1 }").is_err());
+    }
+
+    #[test]
+    fn types_of_values_are_checked() {
+        assert!(matches("{ $type: string }", "web"));
+        assert!(matches("{ $type: string }", "\"3\""));
+        assert!(!matches("{ $type: string }", "3"));
+        assert!(matches("{ $type: number }", "3.5"));
+        assert!(matches("{ $type: bool }", "false"));
+        assert!(matches("{ $type: sequence }", "[]"));
+        assert!(matches("{ $type: mapping }", "{}"));
+        assert!(matches("{ $type: null }", "null"));
+        assert!(matches("{ $type: \"null\" }", "~"));
+        assert!(!matches("{ $type: null }", "{}"));
+        assert!(matches("{ $type: string }", "!custom web"));
+
+        let error = Filter::parse(serde_yaml::from_str("{ $type: text }").unwrap())
+            .err()
+            .unwrap();
+        assert!(format!("{error:#}").contains(
+            "unknown type `text`, expected one of `string`, `number`, `bool`, `sequence`, `mapping` or `null`"
+        ));
+    }
+
+    #[test]
+    fn between_includes_both_bounds() {
+        assert!(matches("{ $between: [1, 3] }", "1"));
+        assert!(matches("{ $between: [1, 3] }", "2.5"));
+        assert!(matches("{ $between: [1, 3] }", "3"));
+        assert!(!matches("{ $between: [1, 3] }", "3.5"));
+        assert!(!matches("{ $between: [1, 3] }", "0"));
+        assert!(matches("{ $between: [2, 2] }", "2"));
+        assert!(!matches("{ $between: [1, 3] }", "\"2\""));
+
+        let parse = |filter: &str| Filter::parse(serde_yaml::from_str(filter).unwrap());
+        assert!(parse("{ $between: [3, 1] }").is_err());
+        assert!(parse("{ $between: [1] }").is_err());
+        assert!(parse("{ $between: 1 }").is_err());
+    }
+
+    #[test]
+    fn rooted_filters_match_the_nested_value() {
+        let root = ["status".to_string(), "loadBalancer".to_string()];
+        let filter = parse("{ ingress: [{ ip: 10.0.0.1 }] }").rooted_at(&root);
+        let state = |yaml: &str| serde_yaml::from_str::<Value>(yaml).unwrap();
+        assert!(match_state(
+            &filter,
+            &state("{ status: { loadBalancer: { ingress: [{ ip: 10.0.0.1 }] } } }")
+        ));
+        assert!(!match_state(
+            &filter,
+            &state("{ ingress: [{ ip: 10.0.0.1 }] }")
+        ));
+        assert!(!match_state(&filter, &state("{ status: {} }")));
+
+        let filter = parse("{ phase: Running }").rooted_at(&[]);
+        assert!(match_state(&filter, &state("{ phase: Running }")));
+    }
+
+    #[test]
+    fn unquoted_yaml11_booleans_are_booleans() {
+        let matches = |filter: &str, state: &str| {
+            let filter = Filter::parse(from_slice(filter.as_bytes()).unwrap()).unwrap();
+            match_state(&filter, &serde_yaml::from_str(state).unwrap())
+        };
+        for filter in ["true", "yes", "Yes", "on", "ON"] {
+            let filter = format!("{{ enabled: {filter} }}");
+            assert!(matches(&filter, "{ enabled: true }"), "{filter}");
+            assert!(!matches(&filter, "{ enabled: false }"), "{filter}");
+        }
+        assert!(matches("{ enabled: Off }", "{ enabled: false }"));
+        assert!(matches("[no, OFF]", "[false, false]"));
+
+        // Quoted values, keys and other strings stay strings.
+        assert!(!matches("{ enabled: \"yes\" }", "{ enabled: true }"));
+        assert!(matches("{ enabled: 'yes' }", "{ enabled: \"yes\" }"));
+        assert!(!matches("{ enabled: yes }", "{ enabled: \"yes\" }"));
+        assert!(matches("{ on: push }", "{ \"on\": push }"));
+        assert!(!matches("{ enabled: y }", "{ enabled: true }"));
+        assert!(!matches("{ status: \"True\" }", "{ status: true }"));
+        assert_eq!(from_slice(b"|\n  yes\n").unwrap(), "yes\n");
+    }
+
+    #[test]
+    fn filters_are_deserialized_as_yaml() {
+        assert_eq!(
+            from_slice(b"{ replicas: 3, image: !digest sha256, ready: null }").unwrap(),
+            serde_yaml::from_str::<Value>("{ replicas: 3, image: !digest sha256, ready: null }")
+                .unwrap()
+        );
+        assert_eq!(from_slice(b"").unwrap(), Value::Null);
+        assert!(from_slice(b"{ a: 1, a: 2 }").is_err());
+        assert!(from_slice(b"a: 1\n---\nb: 2\n").is_err());
+    }
+
+    #[test]
+    fn exact_mappings_reject_other_keys() {
+        let filter = parse("{ status: { phase: Running } }").exact();
+        let state = |yaml: &str| serde_yaml::from_str::<Value>(yaml).unwrap();
+        assert!(match_state(
+            &filter,
+            &state("{ status: { phase: Running } }")
+        ));
+        assert!(!match_state(
+            &filter,
+            &state("{ status: { phase: Running, podIP: 10.0.0.1 } }")
+        ));
+        assert!(!match_state(
+            &filter,
+            &state("{ status: { phase: Running }, spec: {} }")
+        ));
+
+        // Mappings in sequences are exact as well.
+        let filter = parse("{ containers: [{ name: app }] }").exact();
+        assert!(match_state(
+            &filter,
+            &state("{ containers: [{ name: app }] }")
+        ));
+        assert!(!match_state(
+            &filter,
+            &state("{ containers: [{ name: app, image: web }] }")
+        ));
+    }
+
+    #[test]
+    fn eq_path_compares_the_values_at_both_paths() {
+        let filter = "{ $eqPath: [.status.readyReplicas, .spec.replicas] }";
+        assert!(matches(
+            filter,
+            "{ spec: { replicas: 3 }, status: { readyReplicas: 3 } }"
+        ));
+        assert!(matches(
+            filter,
+            "{ spec: { replicas: 3 }, status: { readyReplicas: 3.0 } }"
+        ));
+        assert!(!matches(
+            filter,
+            "{ spec: { replicas: 3 }, status: { readyReplicas: 2 } }"
+        ));
+        assert!(!matches(
+            filter,
+            "{ spec: { replicas: 3 }, status: { readyReplicas: \"3\" } }"
+        ));
+        // A missing value never matches, even when both are missing.
+        assert!(!matches(filter, "{ spec: { replicas: 3 }, status: {} }"));
+        assert!(!matches(filter, "{}"));
+
+        let parse = |filter: &str| Filter::parse(serde_yaml::from_str(filter).unwrap());
+        assert!(parse("{ $eqPath: [.spec.replicas] }").is_err());
+        assert!(parse("{ $eqPath: .spec.replicas }").is_err());
+        assert!(parse("{ $eqPath: [.spec.replicas, ..status] }").is_err());
+    }
+
+    #[test]
+    fn regex_any_matches_any_of_the_expressions() {
+        let filter = "{ reason: { $regexAny: [\"^Ready$\", \"^Healthy\"] } }";
+        assert!(matches(filter, "{ reason: Ready }"));
+        assert!(matches(filter, "{ reason: HealthyAgain }"));
+        assert!(!matches(filter, "{ reason: NotReady }"));
+        assert!(!matches(filter, "{ reason: 3 }"));
+        assert!(!matches(filter, "{}"));
+
+        let parse = |filter: &str| Filter::parse(serde_yaml::from_str(filter).unwrap());
+        assert!(parse("{ $regexAny: [] }").is_err());
+        assert!(parse("{ $regexAny: \"^Ready$\" }").is_err());
+        assert!(parse("{ $regexAny: [\"^Ready$\", \"(\"] }").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use k8s_openapi::chrono::{SecondsFormat, Utc};
+use serde_yaml::Value;
+
+use crate::watch;
+
+/// Bounded history of the observed resource states, for `--record-history`.
+/// Only the fields describing the progress of a resource are kept, see [`History::record`].
+#[derive(Default)]
+pub struct History {
+    entries: VecDeque<serde_json::Value>,
+}
+
+impl History {
+    /// Maximal number of kept states, older states are dropped first.
+    pub const CAPACITY: usize = 100;
+
+    /// Records the observed state as its namespace and name, resourceVersion, generation and status,
+    /// together with the time of the observation, e.g.
+    ///
+    /// ```json
+    /// {"observedAt":"2024-05-01T12:00:00Z","resource":"default/web","resourceVersion":"123","generation":2,"status":{...}}
+    /// ```
+    pub fn record(&mut self, state: &Value) {
+        if self.entries.len() == Self::CAPACITY {
+            self.entries.pop_front();
+        }
+
+        let metadata = &state["metadata"];
+        let to_json = |value: &Value| serde_json::to_value(value).unwrap_or_default();
+
+        self.entries.push_back(serde_json::json!({
+            "observedAt": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "resource": watch::display_name(state),
+            "resourceVersion": to_json(&metadata["resourceVersion"]),
+            "generation": to_json(&metadata["generation"]),
+            "status": to_json(&state["status"]),
+        }));
+    }
+
+    /// Returns the recorded states as a single line of JSON, an array from the oldest to the latest state.
+    pub fn to_json(&self) -> String {
+        serde_json::Value::from_iter(self.entries.iter().cloned()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::chrono::DateTime;
+
+    use super::*;
+
+    fn state(resource_version: usize) -> Value {
+        serde_yaml::from_str(&format!(
+            "{{ metadata: {{ name: web, namespace: default, resourceVersion: '{resource_version}', generation: 2 }}, spec: {{ replicas: 3 }}, status: {{ readyReplicas: 1 }} }}"
+        ))
+        .unwrap()
+    }
+
+    fn entries(history: &History) -> Vec<serde_json::Value> {
+        serde_json::from_str(&history.to_json()).unwrap()
+    }
+
+    #[test]
+    fn progress_fields_are_recorded() {
+        let mut history = History::default();
+        assert_eq!(history.to_json(), "[]");
+        history.record(&state(7));
+
+        let [entry] = &entries(&history)[..] else {
+            panic!("a single state should be recorded");
+        };
+        assert_eq!(entry["resource"], "default/web");
+        assert_eq!(entry["resourceVersion"], "7");
+        assert_eq!(entry["generation"], 2);
+        assert_eq!(entry["status"], serde_json::json!({ "readyReplicas": 1 }));
+        assert!(entry.get("spec").is_none());
+        let observed_at: DateTime<Utc> = entry["observedAt"].as_str().unwrap().parse().unwrap();
+        assert!((Utc::now() - observed_at).num_seconds() < 5);
+    }
+
+    #[test]
+    fn only_the_latest_states_are_kept() {
+        let mut history = History::default();
+        for resource_version in 0..History::CAPACITY + 5 {
+            history.record(&state(resource_version));
+        }
+
+        let entries = entries(&history);
+        assert_eq!(entries.len(), History::CAPACITY);
+        assert_eq!(entries[0]["resourceVersion"], "5");
+        assert_eq!(
+            entries[History::CAPACITY - 1]["resourceVersion"],
+            (History::CAPACITY + 4).to_string()
+        );
+    }
+}
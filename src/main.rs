@@ -1,42 +1,161 @@
+// Standard output is reserved for the result, see `print_result`.
+#![deny(clippy::print_stdout)]
+
 use std::{
-    ffi::OsStr,
+    cell::{Cell, RefCell},
+    env,
+    ffi::{OsStr, OsString},
+    net::SocketAddr,
     path::{Path, PathBuf},
-    time::Duration,
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result};
-use clap::Parser;
-use futures::StreamExt;
-use kube::{
-    api::{ApiResource, DynamicObject},
-    discovery::{ApiGroup, Scope},
-    runtime::{
-        self,
-        watcher::{Config, Event},
-        WatchStreamExt,
-    },
-    Api, Client, Discovery,
+use cel::CelExpression;
+use clap::{
+    builder::RangedU64ValueParser, error::ErrorKind, parser::ValueSource, CommandFactory,
+    FromArgMatches, Parser, Subcommand, ValueEnum,
 };
+use client::ClientOptions;
+use condition::WaitFor;
+use error::WaitError;
+use events::InvolvedObject;
+use filter::{Filter, NamedFilter};
+use glob::Pattern;
+use history::History;
+use indicatif::ProgressBar;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::{api::DynamicObject, discovery::Scope, runtime::watcher, Api, Client, Discovery};
+use metrics::Metrics;
+use output::{OutputField, OutputFormat, OutputTemplate};
+use path::ValuePath;
+use reqwest::Url;
+use resource::{Namespace, ResourceFilter, SubresourcePath};
+use sequence::Step;
 use serde_yaml::Value;
 use tokio::{
     fs,
     io::{self, AsyncReadExt},
+    net::TcpListener,
     time,
 };
+use tracing_subscriber::{filter::LevelFilter, EnvFilter};
+use watch::{
+    poll_subresource, watch_any_for_condition_met, Matcher, ObjectFilter, WatchMode, WatchOptions,
+};
+
+mod auth;
+mod cel;
+mod client;
+mod condition;
+mod error;
+mod events;
+mod explain;
+mod filter;
+mod history;
+mod metrics;
+mod notify;
+mod output;
+mod path;
+mod progress;
+mod quantity;
+mod ratelimit;
+mod resource;
+mod selector;
+mod sequence;
+mod signal;
+#[cfg(test)]
+mod testing;
+mod watch;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Arguments of `wait`, used when no subcommand is given.
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Wait for the resource to reach a state matching the filter. The default when no subcommand is given.
+    Wait(Args),
+    /// List the resources once and print all states matching the filter, without waiting.
+    /// Fails when no state matches.
+    Get(Args),
+    /// List the resources once and print the number of resources whose state matches the filter,
+    /// without waiting.
+    Count(Args),
+}
+
+/// What is done with the resources, selected with the subcommand.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Wait,
+    Get,
+    Count,
+}
+
+#[derive(clap::Args)]
 struct Args {
     /// Kind of the resource in PascalCase, e.g. `Deployment` or `ReplicaSet`.
-    kind: String,
+    /// Taken from the `K8S_WAIT_KIND` environment variable when omitted, the argument takes precedence.
+    /// When the variable is set and a single positional argument is given, the argument is the name of the resource,
+    /// unless it starts with an uppercase letter, as names cannot, or `--selector`, `--watch-count` or `--count` is given.
+    /// Not used with `--sequence`.
+    #[arg(env = KIND_ENV, required_unless_present = "sequence")]
+    kind: Option<String>,
+
+    /// Name of the resource. Required unless `--name-glob`, `--any-name`, `--selector`, `--watch-count` or `--count` is given.
+    name: Option<String>,
+
+    /// Glob pattern matched against names of the resources, e.g. `web-*`.
+    /// Supports `?` (any character), `*` (any sequence of characters), `[...]` (any character in the brackets)
+    /// and `[!...]` (any character not in the brackets).
+    #[arg(long, conflicts_with = "name")]
+    name_glob: Option<Pattern>,
+
+    /// Comma-separated names of the resources, e.g. `leader-0,leader-1`, of which any one may match.
+    /// The first matching resource wins and its name is printed to standard error.
+    /// The names are matched client-side, like `--name-glob`.
+    #[arg(long, value_delimiter = ',', value_name = "NAMES", conflicts_with_all = ["name", "name_glob"])]
+    any_name: Vec<String>,
+
+    /// Match the resource name client-side, instead of passing it to the API server in a field selector.
+    /// Needed for resources whose API server does not support selecting them by name.
+    /// When the API server rejects the field selector, the name is matched client-side anyway.
+    #[arg(long, requires = "name")]
+    no_name_field_selector: bool,
 
-    /// Name of the resource.
-    name: String,
+    /// Label selector narrowing down the watched resources, e.g. `app=web,env in (prod,stage),!deprecated`.
+    /// Supports all operators of Kubernetes label selectors and is passed to the API server unmodified.
+    #[arg(short = 'l', long, visible_alias = "label-selector", value_parser = selector::parse_label_selector)]
+    selector: Option<String>,
 
-    /// Namespace where the resource lives.
+    /// Namespace where the resource lives, `all` for all namespaces.
+    /// Accepts a comma-separated list, e.g. `team-a,team-b`, to watch every namespace in the list
+    /// and wait until the resource matches in any of them.
     /// Ignored for cluster-wide resources.
-    #[arg(long)]
-    namespace: Option<String>,
+    /// Defaults to the namespace of the kubeconfig context, see `--context`, or to `default` if it has none.
+    /// Inside a cluster without a kubeconfig, defaults to the namespace of the service account.
+    #[arg(long, value_delimiter = ',')]
+    namespace: Vec<String>,
+
+    /// Watch the resource in all namespaces, same as `--namespace all`.
+    #[arg(short = 'A', long)]
+    all_namespaces: bool,
 
     /// Resource group. Can be used to narrow down search results when discovering available resources.
     #[arg(long)]
@@ -46,7 +165,8 @@ struct Args {
     #[arg(long)]
     group_version: Option<String>,
 
-    /// Resource apiVersion. Can be used to narrow down search results when discovering available resources.
+    /// Resource apiVersion, e.g. `apps/v1` or `v1` for the core group.
+    /// Sets both the group and the group version, which must match `--group` and `--group-version` if given.
     #[arg(long)]
     api_version: Option<String>,
 
@@ -54,42 +174,974 @@ struct Args {
     #[arg(long)]
     plural: Option<String>,
 
+    /// Scope of the resource, `namespaced` or `cluster`.
+    /// Together with the group and the version (e.g. from `--api-version`) and `--plural`, describes the resource
+    /// completely, so that the API server is not asked to discover it, e.g. when discovery is forbidden.
+    #[arg(long, value_enum, conflicts_with = "sequence")]
+    scope: Option<ResourceScope>,
+
+    /// When the version is not pinned and the kind is served in multiple versions, pick the most stable one
+    /// instead of the version preferred by the API server. Also resolves ambiguity between groups.
+    /// Stable versions (e.g. `v2`, `v1`) come first, then beta (e.g. `v2beta1`, `v1beta2`), then alpha,
+    /// with higher versions first within each level. Other versions come last, in alphabetical order.
+    #[arg(long, conflicts_with_all = ["api_version", "group_version"])]
+    prefer_stable: bool,
+
+    /// Comma-separated apiVersions tried in order, e.g. `apps/v1,extensions/v1beta1`.
+    /// The first one that serves the kind is used, even if other groups or versions serve it as well.
+    /// Useful during migrations of resources between groups and versions.
+    /// When none of them serves the kind, the resource is selected as if this option was not given.
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["api_version", "group_version"])]
+    prefer: Vec<String>,
+
     /// Timeout for watching resource state (seconds).
-    #[arg(short, long)]
-    timeout: Option<u64>,
+    /// With `--sequence`, the timeout is shared by all steps.
+    /// Zero or negative value means a single check: the resources are listed once without watching,
+    /// and the program fails immediately if none matches.
+    #[arg(short, long, allow_negative_numbers = true)]
+    timeout: Option<i64>,
+
+    /// When the wait ends without a match, with the timeout or after a single check,
+    /// print the last observed state of any resource, matching or not, and exit with code 0.
+    /// Nothing is printed if no state was observed. Other errors still fail the wait.
+    #[arg(long, conflicts_with_all = ["sequence", "follow", "watch_count"])]
+    timeout_exit_zero: bool,
+
+    /// Instead of watching, list the resources repeatedly at this interval, e.g. `10s`.
+    /// Useful for resources that do not support watching, e.g. served by some aggregated API servers.
+    #[arg(long, value_parser = parse_interval)]
+    poll: Option<Duration>,
+
+    /// Watch the resources, but when the API server does not support watching them (responds with 405),
+    /// fall back to listing them repeatedly at this interval, e.g. `10s`.
+    #[arg(long, conflicts_with = "poll", value_parser = parse_interval)]
+    poll_fallback: Option<Duration>,
+
+    /// Randomly shorten or lengthen every polling interval by up to this fraction of it, e.g. `0.1` for 9 to 11 seconds
+    /// with `--poll 10s`, so that many programs started together do not poll the API server at the same time.
+    /// Applies to `--poll`, `--poll-fallback` and `--subresource-path`. `0` polls at exactly the interval.
+    #[arg(long, default_value_t = 0.1, value_parser = parse_jitter)]
+    poll_jitter: f64,
+
+    /// Give up after processing this many resource events without finding a matching state.
+    /// Resources listed when the watch starts or restarts, or when polling, count as events as well.
+    /// With `--sequence`, the limit applies to every resource separately,
+    /// and with multiple namespaces, to every namespace.
+    #[arg(long)]
+    max_events: Option<u64>,
+
+    /// Give up when the watch fails (or, when polling, listing fails) more than this many times in a row.
+    /// The count is reset whenever the watch recovers. By default, errors are retried indefinitely.
+    /// Expired resource versions (`410 Gone`) are not counted, the resources are listed again instead.
+    #[arg(long)]
+    max_errors: Option<u64>,
+
+    /// Give up when the watch fails (or, when polling, listing fails) more than this many times in total.
+    /// Unlike `--max-errors`, the count is never reset, so intermittent errors add up over the whole wait.
+    /// With `--sequence`, the limit applies to every resource separately,
+    /// and with multiple namespaces, to every namespace.
+    #[arg(long)]
+    error_threshold: Option<u64>,
+
+    /// Subresource whose changes trigger matching. With `status`, resources are matched when first observed
+    /// and then only when their status changes, updates that leave the status untouched are ignored.
+    /// The resources are still watched whole, as the API server does not support watching subresources.
+    #[arg(long, value_enum)]
+    subresource: Option<Subresource>,
+
+    /// Match the payload of the subresource at this path instead of the resource, e.g. `scale`
+    /// or a custom subresource of an aggregated API, e.g. `metrics/latest`.
+    /// The API server does not support watching subresources, so the subresource is fetched
+    /// at the `--poll` interval, every 2 seconds by default. Requires the resource name and a single namespace.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["subresource", "poll_fallback", "name_glob", "selector", "show_events", "watch_count", "count_conditions", "follow"]
+    )]
+    subresource_path: Option<SubresourcePath>,
+
+    /// When the resources are listed, i.e. when the watch (re)starts, in every poll and with `--timeout 0`,
+    /// match the last matching resource of the list instead of the first one.
+    /// Makes no difference when waiting for a single resource by name.
+    #[arg(long)]
+    last_match_in_list: bool,
+
+    /// Skip resources that take more than this many bytes when serialized as JSON, with a warning.
+    /// Protects against matching pathologically large resources, e.g. custom resources with megabytes of status.
+    /// Unlimited by default.
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_object_bytes: Option<usize>,
+
+    /// Path to YAML file containing a list of resources to wait for one after another,
+    /// each with its own state filter. See the README for the format.
+    /// Every resource is watched only after the previous one matched,
+    /// and the matching states of all resources are printed as separate YAML documents.
+    /// Other options apply to all resources, `--namespace` is the default for resources without a namespace.
+    #[arg(
+        long,
+        conflicts_with_all = ["name", "name_glob", "any_name", "file", "filter_url", "wait_for", "cel", "has_label", "has_annotation", "count_conditions"]
+    )]
+    sequence: Option<PathBuf>,
 
     /// Path to YAML file containing resource state filter.
     /// Omit or pass '-' to read from standard input.
+    /// When omitted together with `--for`, `--cel` or `--has-label`, no state filter is used.
+    /// Can be repeated, a state matches only if it matches all of the filters.
+    /// Prefix the path with a root path and `=@`, e.g. `status=@readiness.yaml`, to match the filter
+    /// against the value at the root path instead of the whole state.
     #[arg(short, long)]
-    file: Option<PathBuf>,
+    file: Vec<FilterFile>,
+
+    /// URL of YAML document containing resource state filter, e.g. `https://example.com/filters/ready.yaml`.
+    /// Fetched with `--request-timeout` (30 seconds by default), following up to 10 redirects.
+    /// Responses with a status other than 2xx are reported as errors.
+    #[arg(long, conflicts_with = "file", value_parser = parse_http_url)]
+    filter_url: Option<Url>,
+
+    /// Path to YAML file containing an alternative resource state filter. Can be repeated,
+    /// a state matches if it matches any of the alternatives, and the path of the first one
+    /// that matches is reported to standard error.
+    /// Applies together with `--file`, `--filter-url`, `--for` and `--cel`.
+    /// When given without `--file` or `--filter-url`, no other state filter is used.
+    /// Accepts a root path like `--file`.
+    #[arg(long, conflicts_with_all = ["sequence", "count_conditions"])]
+    any_of: Vec<FilterFile>,
+
+    /// Path to YAML file containing an additional resource state filter. Can be repeated,
+    /// a state matches only if it matches all of them at once.
+    /// Unlike a single filter with all requirements merged, `--explain` shows every filter separately.
+    /// Applies together with `--file`, `--filter-url`, `--for`, `--cel` and `--any-of`.
+    /// When given without `--file` or `--filter-url`, no other state filter is used.
+    /// Accepts a root path like `--file`.
+    #[arg(long, conflicts_with_all = ["sequence", "count_conditions"])]
+    all_of: Vec<FilterFile>,
+
+    /// Path to YAML file containing a state filter of a failure, e.g. a failed job. Can be repeated.
+    /// As soon as an observed state matches any of them, the wait fails with exit code 5,
+    /// instead of waiting for a match until the timeout. Accepts a root path like `--file`.
+    #[arg(long, conflicts_with_all = ["sequence", "count_conditions", "watch_count"])]
+    fail_if: Vec<FilterFile>,
+
+    /// Built-in condition that the resource state must meet in addition to the state filter.
+    /// `scaled`: both `status.replicas` and `status.readyReplicas` are equal to `spec.replicas`, missing ones count as 0.
+    /// `ready`: the same as `condition=Ready`.
+    /// `condition=<type>[=<status>][;reason=<reason>][;message=<message>]`: an entry in `status.conditions`
+    /// has the given type, status (`True` by default) and optionally reason and message,
+    /// e.g. `condition=Degraded=False;reason=AsExpected`.
+    /// `any-of-conditions=<min>:<type>,...`: at least `<min>` of the entries in `status.conditions` with the given types
+    /// are `True`, e.g. `any-of-conditions=2:Ready,Available,Synced`.
+    /// `terminating`: `metadata.deletionTimestamp` is set, e.g. the resource waits for its finalizers.
+    #[arg(long = "for")]
+    wait_for: Option<WaitFor>,
+
+    /// CEL expression that the resource state must satisfy in addition to the state filter,
+    /// e.g. `object.status.phase == 'Running'`. The state is available as both `object` and `self`.
+    /// Matches only when the expression evaluates to `true`, evaluation errors (e.g. missing fields) do not match.
+    /// When given without `--file`, no other state filter is used.
+    #[arg(long)]
+    cel: Option<CelExpression>,
+
+    /// Match the state filter against every value nested in the resource state, at any depth,
+    /// e.g. to find a mapping with given keys without knowing its path.
+    /// The resource matches when any of the values does. See the README for performance implications.
+    /// `--has-label`, `--for` and `--cel` still apply to the whole resource state.
+    #[arg(long)]
+    deep_contains: bool,
+
+    /// Match sequences in the state filter regardless of the order of their elements,
+    /// pairing elements of the filter and the state by the value of this field, e.g. `name` or `type`.
+    /// Applies to sequences whose every element in the filter has a plain value of this field.
+    #[arg(long)]
+    array_identity_key: Option<String>,
+
+    /// Match mappings in the state filter only against mappings with exactly the same keys,
+    /// at every level of the state filter, instead of allowing additional keys in the state.
+    /// `--has-label`, `--for` and `--cel` are not affected.
+    #[arg(long)]
+    exact: bool,
+
+    /// Print an explanation of every observed resource state that does not match to standard error,
+    /// as a tree showing which entries of the state filter match and what the state has instead,
+    /// e.g. `✗ replicas: expected 3, found 2`. Meant for interactive debugging of state filters.
+    #[arg(long)]
+    explain: bool,
+
+    /// While waiting, print events related to the resource to standard error, e.g. why a pod is pending.
+    /// Only events in the namespaces of the resource are displayed.
+    /// Requires the resource name, `--name-glob`, `--any-name` or `--uid`, which select the related events.
+    #[arg(long, conflicts_with_all = ["sequence", "count_conditions"])]
+    show_events: bool,
+
+    /// Address on which to serve Prometheus metrics at `/metrics` while watching, e.g. `0.0.0.0:9090`.
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// When the wait ends, with a match or with the timeout, print the number of processed watch events,
+    /// per kind, and the elapsed time to standard error.
+    #[arg(long)]
+    report_stats: bool,
+
+    /// When the wait ends, with a match or with the timeout, print the highest resourceVersion
+    /// of the received resources to standard error, e.g. to start a later watch from it.
+    #[arg(long)]
+    report_rv: bool,
+
+    /// Record the observed resource states and, when the wait ends without a match, print them to standard error
+    /// as a JSON array, with the namespace, name, resourceVersion, generation and status of every state.
+    /// Only the latest 100 states are kept.
+    #[arg(long, conflicts_with_all = ["sequence", "follow", "watch_count"])]
+    record_history: bool,
+
+    /// When the wait ends, print a JSON line describing the result to standard error,
+    /// e.g. `{"result":"matched","elapsed_ms":1234,"events":7}`. See the README for the possible results.
+    #[arg(long)]
+    status_json: bool,
+
+    /// URL of a webhook notified about the match, e.g. `https://example.com/hooks/ready`.
+    /// The matching resource state is sent in a JSON POST request, see the README for the format.
+    /// Sent with `--request-timeout` (30 seconds by default). Failures are reported, but do not fail the wait.
+    #[arg(long, conflicts_with = "count_conditions", value_parser = parse_http_url)]
+    notify_url: Option<Url>,
+
+    /// Only match resources created at or after this instant.
+    /// Accepts an RFC3339 timestamp (e.g. `2024-05-01T12:00:00Z`)
+    /// or a duration relative to the start of the program (e.g. `10m`).
+    /// Resources listed when the watch starts or restarts are checked as well,
+    /// so pre-existing resources created before the cutoff never match.
+    #[arg(long, value_parser = parse_since)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only match resources that have existed for at least this long, e.g. `5m`.
+    /// The age is computed from the resource creation timestamp.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    min_age: Option<Duration>,
+
+    /// After the resource first matches, keep watching it for this long, e.g. `10s`,
+    /// and succeed only if it still matches, with its latest state.
+    /// If it stops matching in the meantime, the wait goes on as if it never matched.
+    /// Guards against controllers reverting a change right away. Cannot be used with `--timeout 0`.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    confirm_after: Option<Duration>,
+
+    /// Succeed only once the value at the path, e.g. `.status.observedGeneration`, differs from its value
+    /// in the first observed state of the resource, in addition to the other filters.
+    /// A value missing at first has changed once it is present. Cannot be used with `--timeout 0`.
+    #[arg(long, conflicts_with_all = ["sequence", "count_conditions", "watch_count"])]
+    changed: Option<ValuePath>,
+
+    /// Instead of exiting on the first match, keep watching and print every matching state as it arrives,
+    /// until the timeout expires or the program is interrupted.
+    /// States are printed once per resource version, and the program exits successfully
+    /// on the timeout if any state was printed.
+    #[arg(long, conflicts_with_all = ["sequence", "count_conditions", "notify_url"])]
+    follow: bool,
+
+    /// Instead of waiting for a single match, keep watching and print the number of matching resources
+    /// and the number of all watched resources, e.g. `3/5 matching`, whenever any of them changes.
+    /// Meant for watching many resources, e.g. with `--selector`, so the resource name is not required.
+    /// Runs until the timeout expires, which is a success, or until `--count` resources match.
+    #[arg(
+        long,
+        conflicts_with_all = ["sequence", "count_conditions", "follow", "poll", "min_age", "confirm_after", "max_events", "notify_url", "explain", "subresource", "last_match_in_list"]
+    )]
+    watch_count: bool,
+
+    /// Succeed once at least this many distinct resources match at the same time, e.g. 3 ready pods with
+    /// `--selector app=web --for ready --count 3`, and print their states. The timeout is then a failure, as usual.
+    /// With `--watch-count`, the counts are printed instead of the states.
+    #[arg(
+        long,
+        conflicts_with_all = ["sequence", "count_conditions", "follow", "poll", "min_age", "confirm_after", "max_events", "notify_url", "timeout_exit_zero", "subresource_path", "fail_if", "record_history", "changed", "explain", "subresource", "last_match_in_list"]
+    )]
+    count: Option<usize>,
+
+    /// Only match the resource with this UID, e.g. a specific instance of a pod with a reused name.
+    /// Checked in addition to the name, since the API server cannot select resources by UID.
+    #[arg(long)]
+    uid: Option<String>,
+
+    /// Only match resources owned by the resource with this UID, e.g. pods of a specific ReplicaSet.
+    /// The UID must be listed in `metadata.ownerReferences`.
+    #[arg(long)]
+    owner_uid: Option<String>,
+
+    /// Only match resources with this label, e.g. `app=web`. Can be repeated, all labels are required.
+    /// Equivalent to adding the labels to `metadata.labels` in the state filter.
+    /// When given without `--file`, no other state filter is used.
+    #[arg(long, value_parser = selector::parse_label)]
+    has_label: Vec<(String, String)>,
+
+    /// Only match resources with this annotation, e.g. `example.com/owner=team-a`. Can be repeated,
+    /// all annotations are required. Equivalent to adding the annotations to `metadata.annotations` in the state filter.
+    /// When given without `--file`, no other state filter is used.
+    #[arg(long, value_parser = selector::parse_annotation)]
+    has_annotation: Vec<(String, String)>,
+
+    /// Instead of waiting, print the number of conditions in `status.conditions` with status `True` and exit.
+    /// The state filter is not used.
+    #[arg(long, conflicts_with_all = ["name_glob", "has_label", "has_annotation", "filter_url", "cel"])]
+    count_conditions: bool,
+
+    /// Format of the matched resource state: `yaml` for the whole state,
+    /// `yaml-flow` for the whole state on a single line, e.g. for logs, or `json`, the same line named after its other format,
+    /// `name` for a kubectl reference, e.g. `deployment.apps/web` as printed by `kubectl get -o name`,
+    /// or `ref` for the reference prefixed with the namespace of namespaced resources, e.g. `default/deployment.apps/web`.
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value_t = Output::Yaml,
+        conflicts_with_all = ["output_fields", "output_env", "output_template", "output_labels", "count_conditions"]
+    )]
+    output: Output,
+
+    /// Keep `metadata.managedFields` in the printed resource state, which is removed by default.
+    /// Only affects the output of the whole state, not matching.
+    #[arg(long, conflicts_with_all = ["output_fields", "output_env", "output_template", "output_labels", "count_conditions"])]
+    keep_managed_fields: bool,
+
+    /// Instead of the whole matched resource state, print the given fields as `<name>\t<value>` lines.
+    /// Accepts a comma-separated list of `<name>=<path>`, e.g. `ip=.status.podIP,node=.spec.nodeName`.
+    /// Keys containing dots are quoted in brackets, e.g. `.metadata.labels['app.kubernetes.io/name']`.
+    /// Missing values are printed as empty strings.
+    #[arg(long, value_delimiter = ',')]
+    output_fields: Vec<OutputField>,
+
+    /// Instead of the whole matched resource state, print the given field as a shell variable assignment,
+    /// e.g. `IP=.status.podIP` as `IP='10.0.0.1'`, for `eval` or `source`. Can be repeated.
+    /// Values are quoted, so that the shell takes them literally. Missing values are assigned empty strings.
+    #[arg(long, conflicts_with_all = ["output_fields", "count_conditions"], value_parser = output::parse_env_field)]
+    output_env: Vec<OutputField>,
+
+    /// Skip the `--output-env` assignments of missing values, instead of assigning empty strings.
+    #[arg(long, requires = "output_env")]
+    output_env_skip_missing: bool,
+
+    /// Instead of the whole matched resource state, print it rendered through the Jinja template in the file,
+    /// e.g. `{{ metadata.name }} is {{ status.phase }}`. See the README for details.
+    #[arg(long, conflicts_with_all = ["output_fields", "output_env", "count_conditions"])]
+    output_template: Option<PathBuf>,
+
+    /// Instead of the whole matched resource state, print its labels as `<key>=<value>` lines, sorted by key.
+    #[arg(long, conflicts_with_all = ["output_fields", "output_env", "output_template", "count_conditions"])]
+    output_labels: bool,
+
+    /// Write the result to this file instead of standard output, replacing the file if it exists.
+    /// Unless `-o` is given, the format of the whole state follows the extension of the file:
+    /// `.json` for `json`, and `.yaml` or `.yml` for `yaml`. Other extensions use `-o`.
+    #[arg(long, conflicts_with_all = ["follow", "watch_count", "count_conditions"])]
+    output_file: Option<PathBuf>,
+
+    /// Before watching, print the resource selected from the discovered ones to standard error,
+    /// as `<name>\t<value>` lines with its group, version, kind, plural and scope.
+    /// Helps to debug the narrowing options, e.g. `--api-version`.
+    #[arg(long)]
+    print_resource: bool,
+
+    /// Print the selected resources to standard output, as with `--print-resource`, and exit without waiting.
+    /// With `--sequence`, every resource of the sequence is printed, in order.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Value of the `User-Agent` header sent with every request to the API server.
+    #[arg(long, default_value = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))]
+    user_agent: String,
+
+    /// Name of the kubeconfig context to use, instead of the current context.
+    /// The namespace of the context is the default for `--namespace`.
+    #[arg(long)]
+    context: Option<String>,
+
+    /// Name of an environment variable containing the whole kubeconfig, e.g. `KUBECONFIG_CONTENT`,
+    /// used instead of the kubeconfig files. Meant for CI secrets that are easier to pass as variables.
+    #[arg(long, value_name = "VAR")]
+    kubeconfig_from_env: Option<String>,
+
+    /// File with a bearer token used to authenticate to the API server,
+    /// instead of the credentials from the kubeconfig. Trailing whitespace, e.g. a newline, is ignored.
+    /// The file is read again at least once a minute, so rotated tokens are picked up.
+    #[arg(long)]
+    token_file: Option<PathBuf>,
+
+    /// Timeout for a single request to the API server, e.g. `30s`.
+    /// Independent of `--timeout`, which limits the whole wait.
+    /// Watch requests are long polls, so the API server is asked to end them 5 seconds before this timeout
+    /// (or after 1 second, if the timeout is shorter) and they are restarted transparently.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    request_timeout: Option<Duration>,
+
+    /// Limit the number of requests sent to the API server per second, e.g. `5` or `0.5`.
+    /// Unlimited by default.
+    #[arg(long, value_parser = parse_qps)]
+    qps: Option<f64>,
+
+    /// Maximal number of requests sent at once before the `--qps` limit applies.
+    /// Defaults to `--qps` rounded up.
+    #[arg(long, requires = "qps", value_parser = clap::value_parser!(u32).range(1..))]
+    burst: Option<u32>,
+
+    /// Do not display the progress spinner.
+    /// The spinner is displayed only when standard error is a terminal.
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Do not color the output of `--explain`.
+    /// The output is colored only when standard error is a terminal and `NO_COLOR` is not set.
+    #[arg(long)]
+    no_color: bool,
+}
+
+/// Subresource selected with `--subresource`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Subresource {
+    Status,
 }
 
+/// Scope selected with `--scope`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ResourceScope {
+    Namespaced,
+    Cluster,
+}
+
+/// Format selected with `--output`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Yaml,
+    YamlFlow,
+    /// Same as [`Output::YamlFlow`], as flow-style YAML on a single line is also JSON.
+    Json,
+    Name,
+    Ref,
+}
+
+/// Timeout for requests to URLs other than the API server when `--request-timeout` is not given.
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval at which `--subresource-path` is fetched when `--poll` is not given.
+const DEFAULT_SUBRESOURCE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Environment variable with the default resource kind.
+const KIND_ENV: &str = "K8S_WAIT_KIND";
+
 impl Args {
-    fn filter_resource(&self, api_resource: &ApiResource) -> bool {
-        self.group
-            .as_ref()
-            .map(|g| *g == api_resource.group)
-            .unwrap_or(true)
-            && self
-                .group_version
-                .as_ref()
-                .map(|v| *v == api_resource.version)
-                .unwrap_or(true)
-            && self
-                .api_version
-                .as_ref()
-                .map(|av| *av == api_resource.api_version)
-                .unwrap_or(true)
-            && self.kind == api_resource.kind
-            && self
-                .plural
+    /// Parses the command line arguments.
+    ///
+    /// When the kind is available in [`KIND_ENV`] and only one positional argument is given,
+    /// the argument is treated as the resource name, unless it looks like a kind, e.g. `Pod`,
+    /// or the resources are selected without a name, with `--selector`, `--watch-count` or `--count`.
+    fn parse_resolved() -> (Mode, Self) {
+        Self::try_parse_resolved_from(env::args_os()).unwrap_or_else(|error| error.exit())
+    }
+
+    /// Parses the given command line arguments as [`Self::parse_resolved`] does, but returns errors instead of exiting.
+    fn try_parse_resolved_from<I, T>(itr: I) -> Result<(Mode, Self), clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let cli_matches = Cli::command().try_get_matches_from(itr)?;
+        let (mode, matches) = match cli_matches.subcommand() {
+            Some(("wait", matches)) => (Mode::Wait, matches),
+            Some(("get", matches)) => (Mode::Get, matches),
+            Some(("count", matches)) => (Mode::Count, matches),
+            Some((name, _)) => unreachable!("unknown subcommand `{name}`"),
+            None => (Mode::Wait, &cli_matches),
+        };
+        let mut args = Self::from_arg_matches(matches)?;
+
+        if args.name.is_none()
+            && args.name_glob.is_none()
+            && args.any_name.is_empty()
+            && args.selector.is_none()
+            && !args.watch_count
+            && args.count.is_none()
+            && matches.value_source("kind") == Some(ValueSource::CommandLine)
+            && !args
+                .kind
+                .as_deref()
+                .is_some_and(|kind| kind.starts_with(|c: char| c.is_ascii_uppercase()))
+        {
+            if let Ok(kind) = env::var(KIND_ENV) {
+                args.name = args.kind.replace(kind);
+            }
+        }
+
+        if matches.value_source("output") != Some(ValueSource::CommandLine) {
+            let extension = args
+                .output_file
+                .as_deref()
+                .and_then(Path::extension)
+                .and_then(OsStr::to_str);
+            match extension {
+                Some("json") => args.output = Output::Json,
+                Some("yaml" | "yml") => args.output = Output::Yaml,
+                _ => {}
+            }
+        }
+
+        if args.all_namespaces && args.namespace.iter().any(|namespace| namespace != "all") {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "`--all-namespaces` cannot be used with a specific `--namespace`",
+            ));
+        }
+
+        if mode != Mode::Wait
+            && (args.sequence.is_some()
+                || args.count_conditions
+                || args.confirm_after.is_some()
+                || args.changed.is_some()
+                || !args.fail_if.is_empty()
+                || args.follow
+                || args.watch_count
+                || args.count.is_some()
+                || args.subresource_path.is_some())
+        {
+            return Err(Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "`--sequence`, `--count-conditions`, `--confirm-after`, `--changed`, `--fail-if`, `--follow`, `--watch-count`, `--count` and `--subresource-path` can only be used with `wait`",
+                ));
+        }
+
+        if (args.watch_count || args.count.is_some())
+            && args.timeout.is_some_and(|timeout| timeout <= 0)
+        {
+            return Err(Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "`--watch-count` and `--count` cannot be used with a single check (`--timeout` of 0 or less), use the `count` subcommand instead",
+                ));
+        }
+
+        if args.changed.is_some() && args.timeout.is_some_and(|timeout| timeout <= 0) {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "`--changed` cannot be used with a single check (`--timeout` of 0 or less)",
+            ));
+        }
+
+        if args.confirm_after.is_some() && args.timeout.is_some_and(|timeout| timeout <= 0) {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "`--confirm-after` cannot be used with a single check (`--timeout` of 0 or less)",
+            ));
+        }
+
+        if args.sequence.is_some() {
+            // The kind is not declared as conflicting, because it can come from the environment.
+            if matches.value_source("kind") == Some(ValueSource::CommandLine) {
+                return Err(Cli::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "the resource kind cannot be used with `--sequence`",
+                ));
+            }
+
+            // Applied to every step, see `Args::apply_preferences`.
+            let mut any_step = ResourceFilter::new(String::new(), None, None, None, None)
+                .expect("filter without constraints should be valid");
+            if let Err(error) = args.apply_preferences(&mut any_step) {
+                return Err(Cli::command().error(ErrorKind::ValueValidation, error));
+            }
+
+            return Ok((mode, args));
+        }
+
+        if args.name.is_none()
+            && args.name_glob.is_none()
+            && args.any_name.is_empty()
+            && args.selector.is_none()
+            && !args.watch_count
+            && args.count.is_none()
+        {
+            return Err(Cli::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "either the resource name, `--name-glob`, `--any-name` or `--selector` is required",
+            ));
+        }
+
+        if args.show_events
+            && args.name.is_none()
+            && args.name_glob.is_none()
+            && args.any_name.is_empty()
+            && args.uid.is_none()
+        {
+            return Err(Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "`--show-events` requires the resource name, `--name-glob`, `--any-name` or `--uid`, otherwise the events of all resources of the kind would be shown",
+                ));
+        }
+
+        if args.count_conditions && args.name.is_none() {
+            return Err(Cli::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "`--count-conditions` requires the resource name",
+            ));
+        }
+
+        if args.count_conditions
+            && !matches!(
+                args.namespaces()[..],
+                [Namespace::Default | Namespace::Named(_)]
+            )
+        {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "`--count-conditions` requires a single namespace",
+            ));
+        }
+
+        if args.subresource_path.is_some() && args.name.is_none() {
+            return Err(Cli::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "`--subresource-path` requires the resource name",
+            ));
+        }
+
+        if args.subresource_path.is_some()
+            && !matches!(
+                args.namespaces()[..],
+                [Namespace::Default | Namespace::Named(_)]
+            )
+        {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "`--subresource-path` requires a single namespace",
+            ));
+        }
+
+        match args.resource_filter() {
+            Err(error) => return Err(Cli::command().error(ErrorKind::ArgumentConflict, error)),
+            Ok(filter) if args.scope.is_some() && filter.explicit().is_none() => {
+                return Err(Cli::command().error(
+                    ErrorKind::MissingRequiredArgument,
+                    "`--scope` requires the group and the version of the resource, e.g. with `--api-version`, and `--plural`",
+                ))
+            }
+            Ok(_) => {}
+        }
+
+        Ok((mode, args))
+    }
+
+    /// Returns the namespaces given with `--namespace` or `--all-namespaces`, without duplicates.
+    /// When all namespaces are requested, no other namespace is returned.
+    fn namespaces(&self) -> Vec<Namespace<'_>> {
+        let mut namespaces = Vec::new();
+        for namespace in self
+            .namespace
+            .iter()
+            .map(|namespace| Namespace::parse(namespace))
+        {
+            if !namespaces.contains(&namespace) {
+                namespaces.push(namespace);
+            }
+        }
+
+        if self.all_namespaces || namespaces.contains(&Namespace::All) {
+            vec![Namespace::All]
+        } else if namespaces.is_empty() {
+            vec![Namespace::Default]
+        } else {
+            namespaces
+        }
+    }
+
+    /// Returns the APIs for the resource given on the command line, one per namespace.
+    fn apis(
+        &self,
+        client: &Client,
+        discovery: Option<&Discovery>,
+        progress: &ProgressBar,
+    ) -> Result<Vec<Api<DynamicObject>>> {
+        let filter = self
+            .resource_filter()
+            .expect("resource filter was checked when parsing");
+
+        resolve_api(
+            &filter,
+            client,
+            discovery,
+            &self.namespaces(),
+            self.print_resource,
+            progress,
+        )
+    }
+
+    fn client_options(&self) -> ClientOptions {
+        ClientOptions {
+            user_agent: self.user_agent.clone(),
+            request_timeout: self.request_timeout,
+            qps: self.qps,
+            burst: self.burst,
+            token_file: self.token_file.clone(),
+            context: self.context.clone(),
+            kubeconfig_env: self.kubeconfig_from_env.clone(),
+        }
+    }
+
+    fn watcher_config(&self) -> watcher::Config {
+        let mut config = watcher::Config {
+            field_selector: self
+                .name
                 .as_ref()
-                .map(|p| *p == api_resource.api_version)
-                .unwrap_or(true)
+                .filter(|_| !self.no_name_field_selector)
+                .map(|name| format!("metadata.name={name}")),
+            label_selector: self.selector.clone(),
+            ..Default::default()
+        };
+
+        if let Some(request_timeout) = self.request_timeout {
+            /// Server-side timeout that kube uses for watch requests by default.
+            const DEFAULT_WATCH_TIMEOUT: u64 = 290;
+
+            let watch_timeout = request_timeout
+                .as_secs()
+                .saturating_sub(5)
+                .clamp(1, DEFAULT_WATCH_TIMEOUT);
+            config.timeout = Some(watch_timeout.try_into().expect("clamped to a small value"));
+        }
+
+        config
+    }
+
+    fn object_filter(&self) -> ObjectFilter {
+        ObjectFilter {
+            name_glob: self.name_glob.clone(),
+            names: match &self.name {
+                Some(name) if self.no_name_field_selector => vec![name.clone()],
+                _ => self.any_name.clone(),
+            },
+            since: self.since,
+            min_age: self.min_age,
+            uid: self.uid.clone(),
+            owner_uid: self.owner_uid.clone(),
+        }
+    }
+
+    /// Applies the options that modify the state filter.
+    fn complete_filter(&self, filter: Filter) -> Result<Filter> {
+        self.with_metadata(self.adjust_filter(filter))
+    }
+
+    /// Applies `--deep-contains`, `--array-identity-key` and `--exact` to the state filter.
+    fn adjust_filter(&self, mut filter: Filter) -> Filter {
+        if self.deep_contains {
+            filter = filter.anywhere();
+        }
+
+        if let Some(key) = &self.array_identity_key {
+            filter = filter.with_array_identity_key(key);
+        }
+
+        if self.exact {
+            filter = filter.exact();
+        }
+
+        filter
+    }
+
+    /// Reads the `--file` state filters, combined so that all of them must match.
+    /// Without any, the state filter is read from standard input.
+    async fn read_file_filters(&self) -> Result<Filter> {
+        let mut filters = Vec::with_capacity(self.file.len());
+        for file in &self.file {
+            filters.push(self.read_filter_file(file).await?);
+        }
+
+        match filters.len() {
+            0 => Ok(self.adjust_filter(read_state_filter(None).await?)),
+            1 => Ok(filters.pop().expect("length was just checked")),
+            _ => Ok(Filter::All(filters)),
+        }
+    }
+
+    /// Reads the `--any-of` or `--all-of` state filters.
+    async fn read_named_filters(&self, files: &[FilterFile]) -> Result<Vec<NamedFilter>> {
+        let mut filters = Vec::with_capacity(files.len());
+        for file in files {
+            let filter = self
+                .read_filter_file(file)
+                .await
+                .with_context(|| format!("failed to construct state filter from {}", file.arg))?;
+            filters.push(NamedFilter {
+                name: file.arg.clone(),
+                filter,
+            });
+        }
+
+        Ok(filters)
+    }
+
+    /// Reads the state filter from the file and roots it at the path given with the file.
+    /// `--deep-contains` and `--array-identity-key` apply below the root.
+    async fn read_filter_file(&self, file: &FilterFile) -> Result<Filter> {
+        let filter = read_state_filter(Some(&file.path)).await?;
+
+        Ok(self.adjust_filter(filter).rooted_at(&file.root))
+    }
+
+    /// Adds the `--has-label` and `--has-annotation` requirements to the state filter.
+    fn with_metadata(&self, filter: Filter) -> Result<Filter> {
+        if self.has_label.is_empty() && self.has_annotation.is_empty() {
+            return Ok(filter);
+        }
+
+        let entries = |pairs: &[(String, String)]| {
+            Filter::Mapping(
+                pairs
+                    .iter()
+                    .map(|(key, value)| {
+                        (key.as_str().into(), Filter::Scalar(value.as_str().into()))
+                    })
+                    .collect(),
+            )
+        };
+        let mut metadata = Filter::any();
+        if !self.has_label.is_empty() {
+            metadata = metadata.with_entry("labels", entries(&self.has_label))?;
+        }
+        if !self.has_annotation.is_empty() {
+            metadata = metadata.with_entry("annotations", entries(&self.has_annotation))?;
+        }
+
+        filter
+            .with_entry("metadata", metadata)
+            .context(WaitError::InvalidStateFilter)
+    }
+
+    async fn output_format(&self) -> Result<OutputFormat> {
+        let format = if let Some(path) = &self.output_template {
+            OutputFormat::Template(OutputTemplate::read(path).await?)
+        } else if !self.output_env.is_empty() {
+            OutputFormat::Env {
+                fields: self.output_env.clone(),
+                skip_missing: self.output_env_skip_missing,
+            }
+        } else if !self.output_fields.is_empty() {
+            OutputFormat::Fields(self.output_fields.clone())
+        } else if self.output_labels {
+            OutputFormat::Labels
+        } else {
+            match self.output {
+                Output::Yaml => OutputFormat::State {
+                    keep_managed_fields: self.keep_managed_fields,
+                },
+                Output::YamlFlow | Output::Json => OutputFormat::FlowState {
+                    keep_managed_fields: self.keep_managed_fields,
+                },
+                Output::Name => OutputFormat::Name,
+                Output::Ref => OutputFormat::Ref,
+            }
+        };
+
+        Ok(format)
+    }
+
+    /// Returns the options of the watch, which expires with `--timeout` if started now.
+    fn watch_options(&self) -> WatchOptions {
+        let deadline = self
+            .timeout
+            .and_then(|timeout| u64::try_from(timeout).ok())
+            .filter(|timeout| *timeout > 0)
+            .map(|timeout| time::Instant::now() + Duration::from_secs(timeout));
+
+        let mode = if self.timeout.is_some_and(|timeout| timeout <= 0) {
+            WatchMode::CheckOnce
+        } else if let Some(interval) = self.poll {
+            WatchMode::Poll(interval)
+        } else if self.subresource_path.is_some() {
+            WatchMode::Poll(DEFAULT_SUBRESOURCE_POLL_INTERVAL)
+        } else {
+            WatchMode::Watch {
+                poll_fallback: self.poll_fallback,
+            }
+        };
+
+        WatchOptions {
+            mode,
+            max_events: self.max_events,
+            max_errors: self.max_errors,
+            error_threshold: self.error_threshold,
+            deadline,
+            poll_jitter: self.poll_jitter,
+        }
+    }
+
+    fn resource_filter(&self) -> Result<ResourceFilter, String> {
+        let mut filter = ResourceFilter::new(
+            self.kind
+                .clone()
+                .expect("kind is required without a sequence"),
+            self.group.clone(),
+            self.group_version.clone(),
+            self.api_version.as_deref(),
+            self.plural.clone(),
+        )?;
+        filter.scope = self.scope.map(|scope| match scope {
+            ResourceScope::Namespaced => Scope::Namespaced,
+            ResourceScope::Cluster => Scope::Cluster,
+        });
+        self.apply_preferences(&mut filter)?;
+
+        Ok(filter)
+    }
+
+    /// Applies `--prefer-stable` and `--prefer` to the resource filter, also used for the steps of a sequence.
+    fn apply_preferences(&self, filter: &mut ResourceFilter) -> Result<(), String> {
+        filter.prefer_stable = self.prefer_stable;
+        filter.prefer(&self.prefer)
+    }
+}
+
+fn parse_qps(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(qps) if qps.is_finite() && qps > 0.0 => Ok(qps),
+        _ => Err("expected a positive number".into()),
+    }
+}
+
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    match humantime::parse_duration(value) {
+        Ok(interval) if !interval.is_zero() => Ok(interval),
+        Ok(_) => Err("interval must not be zero".into()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn parse_jitter(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(jitter) if (0.0..1.0).contains(&jitter) => Ok(jitter),
+        Ok(_) => Err("jitter must be at least 0 and less than 1".into()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn parse_since(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.to_utc());
+    }
+
+    let duration = humantime::parse_duration(value)
+        .map_err(|error| format!("expected an RFC3339 timestamp or a duration: {error}"))?;
+
+    Ok(Utc::now() - duration)
+}
+
+fn parse_http_url(value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|error| error.to_string())?;
+
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!(
+            "unsupported scheme `{scheme}`, expected `http` or `https`"
+        )),
     }
 }
 
-async fn read_state_filter(path: Option<&Path>) -> anyhow::Result<Value> {
+async fn read_state_filter(path: Option<&Path>) -> anyhow::Result<Filter> {
     let raw_bytes = match path {
         Some(path) if path != OsStr::new("-") => fs::read(path)
             .await
@@ -104,134 +1156,1093 @@ async fn read_state_filter(path: Option<&Path>) -> anyhow::Result<Value> {
         }
     };
 
-    serde_yaml::from_slice(&raw_bytes).context("failed to deserialize state filter")
+    parse_state_filter(&raw_bytes)
 }
 
-fn match_state(filter: &Value, state: &Value) -> bool {
-    let serialized = serde_yaml::to_value(state).expect("serialization should not fail");
+async fn fetch_state_filter(url: &Url, user_agent: &str, timeout: Duration) -> Result<Filter> {
+    let client = reqwest::Client::builder()
+        .user_agent(user_agent)
+        .timeout(timeout)
+        .build()
+        .context("failed to create HTTP client")?;
 
-    match (filter, &serialized) {
-        (Value::Mapping(m1), Value::Mapping(m2)) => {
-            for (k, v1) in m1 {
-                let Some(v2) = m2.get(k) else {
-                    return false;
-                };
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("failed to fetch state filter from {url}"))?;
 
-                if !match_state(v1, v2) {
-                    return false;
-                }
-            }
+    let status = response.status();
+    anyhow::ensure!(
+        status.is_success(),
+        "failed to fetch state filter from {url}: server responded with {status}"
+    );
 
-            true
+    let raw_bytes = response
+        .bytes()
+        .await
+        .with_context(|| format!("failed to read state filter from {url}"))?;
+
+    parse_state_filter(&raw_bytes)
+}
+
+/// Describes how the wait ended for `--status-json`, as a single line of JSON.
+fn completion_status(result: &Result<Vec<Value>>, elapsed: Duration, metrics: &Metrics) -> String {
+    let mut status = serde_json::json!({
+        "result": match result {
+            Ok(_) => "matched",
+            Err(error) => match error.downcast_ref() {
+                Some(WaitError::Timeout) => "timeout",
+                Some(WaitError::NotMatched) => "not_matched",
+                Some(WaitError::FailIfMatched { .. }) => "failed",
+                Some(WaitError::Interrupted | WaitError::Terminated) => "terminated",
+                _ => "error",
+            },
+        },
+        "elapsed_ms": u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+        "events": metrics.events(),
+    });
+    if let (Err(error), Some(object)) = (result, status.as_object_mut()) {
+        object.insert("error".into(), format!("{error:#}").into());
+    }
+
+    status.to_string()
+}
+
+/// State filter file, optionally with a root path at which the filter applies, e.g. `status=@readiness.yaml`.
+#[derive(Clone)]
+struct FilterFile {
+    /// Argument as given on the command line, used in diagnostics.
+    arg: String,
+    /// Keys of the root path, empty for the whole state.
+    root: Vec<String>,
+    path: PathBuf,
+}
+
+impl FromStr for FilterFile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((root, path)) = s.split_once("=@") else {
+            return Ok(Self {
+                arg: s.into(),
+                root: Vec::new(),
+                path: s.into(),
+            });
+        };
+
+        let root = root.parse::<ValuePath>()?;
+        let root = root.keys().ok_or_else(|| {
+            format!("root path of `{s}` must consist of mapping keys, not sequence indices")
+        })?;
+        if path.is_empty() {
+            return Err(format!("missing file path after `=@` in `{s}`"));
         }
 
-        (Value::Sequence(s1), Value::Sequence(s2)) => {
-            s1.iter().all(|v1| s2.iter().all(|v2| match_state(v1, v2)))
+        Ok(Self {
+            arg: s.into(),
+            root: root.into_iter().map(String::from).collect(),
+            path: path.into(),
+        })
+    }
+}
+
+fn parse_state_filter(raw_bytes: &[u8]) -> Result<Filter> {
+    let value = filter::from_slice(raw_bytes).context("failed to deserialize state filter")?;
+
+    Filter::parse(value).context(WaitError::InvalidStateFilter)
+}
+
+/// Returns the APIs for the single discovered resource matching the filter, one per namespace.
+/// A cluster-wide resource has a single API, whatever the namespaces.
+/// Warns when the API server prefers a more stable or more recent version of the resource.
+/// With `print_resource`, the resource is described on standard error.
+/// Resources described completely by the filter are used without discovery, see [`ResourceFilter::explicit`].
+fn resolve_api(
+    filter: &ResourceFilter,
+    client: &Client,
+    discovery: Option<&Discovery>,
+    namespaces: &[Namespace],
+    print_resource: bool,
+    progress: &ProgressBar,
+) -> Result<Vec<Api<DynamicObject>>> {
+    let (api_resource, api_capabilities) = filter.select(discovery)?;
+
+    if print_resource {
+        let description = resource::describe(&api_resource, &api_capabilities);
+        progress.suspend(|| eprint!("Selected resource:\n{description}"));
+    }
+
+    if let Some(replacement) =
+        discovery.and_then(|discovery| resource::preferred_replacement(discovery, &api_resource))
+    {
+        progress.suspend(|| {
+            eprintln!(
+                "Warning: {} is not the preferred version of {}, consider using {replacement}.",
+                api_resource.api_version, api_resource.kind
+            )
+        });
+    }
+
+    let namespaces = match api_capabilities.scope {
+        Scope::Cluster => &[Namespace::All],
+        Scope::Namespaced => namespaces,
+    };
+
+    Ok(namespaces
+        .iter()
+        .map(|namespace| resource::api(client, &api_resource, &api_capabilities, *namespace))
+        .collect())
+}
+
+/// Waits for the resources in the sequence one after another.
+/// Returns the matching states of all resources, with the time from `started` until each match.
+#[allow(clippy::too_many_arguments)]
+async fn wait_sequence(
+    args: &Args,
+    client: &Client,
+    discovery: Option<&Discovery>,
+    steps: Vec<Step>,
+    metrics: &Metrics,
+    watch_options: WatchOptions,
+    progress: &ProgressBar,
+    started: Instant,
+) -> Result<Vec<(Value, Duration)>> {
+    let mut states = Vec::with_capacity(steps.len());
+
+    for (i, step) in steps.into_iter().enumerate() {
+        let namespaces = match &step.namespace {
+            Some(namespace) => vec![Namespace::parse(namespace)],
+            None => args.namespaces(),
+        };
+        let apis = resolve_api(
+            &step.resource,
+            client,
+            discovery,
+            &namespaces,
+            args.print_resource,
+            progress,
+        )
+        .with_context(|| format!("failed to find resource of step {}", i + 1))?;
+
+        let mut config = args.watcher_config();
+        config.field_selector = Some(format!("metadata.name={}", step.name));
+
+        let state = watch_any_for_condition_met(
+            apis,
+            config,
+            Matcher::new(
+                args.object_filter(),
+                args.complete_filter(step.filter)?,
+                step.wait_for,
+                None,
+                metrics,
+            )
+            .with_confirm_after(args.confirm_after)
+            .with_explain(args.explain.then(|| progress.clone()))
+            .with_max_object_bytes(args.max_object_bytes, progress)
+            .with_status_changes_only(args.subresource == Some(Subresource::Status))
+            .with_last_listed_match(args.last_match_in_list),
+            watch_options,
+            progress,
+        )
+        .await
+        .with_context(|| format!("failed to wait for step {}", i + 1))?;
+        states.push((state, started.elapsed()));
+    }
+
+    Ok(states)
+}
+
+/// Returns the number of entries in `status.conditions` with status `True`.
+fn count_true_conditions(object: &DynamicObject) -> usize {
+    object.data["status"]["conditions"]
+        .as_array()
+        .map(|conditions| {
+            conditions
+                .iter()
+                .filter(|condition| condition["status"] == "True")
+                .count()
+        })
+        .unwrap_or_default()
+}
+
+/// Reports the first `--any-of` state filter that matches the state to standard error.
+fn report_any_of(any_of: &[NamedFilter], state: &Value) {
+    if let Some(matching) = filter::first_matching(any_of, state) {
+        eprintln!("State matched the `--any-of` filter {}.", matching.name);
+    }
+}
+
+/// Writes the result to standard output.
+/// Nothing else is ever written there, diagnostics and progress go to standard error.
+#[allow(clippy::print_stdout)]
+fn print_result(result: &str) {
+    print!("{result}");
+}
+
+/// Writes the result to the `--output-file`, if given, or to standard output.
+async fn write_result(output_file: Option<&Path>, result: &str) -> Result<()> {
+    match output_file {
+        Some(path) => fs::write(path, result)
+            .await
+            .with_context(|| format!("failed to write the result to {}", path.display())),
+        None => {
+            print_result(result);
+            Ok(())
         }
+    }
+}
 
-        _ => filter == &serialized,
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    // Off unless enabled with `RUST_LOG`, e.g. `RUST_LOG=k8s_wait_full=debug`.
+    // Logs go to standard error, as standard output is reserved for the result.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::OFF.into())
+                .from_env_lossy(),
+        )
+        .with_writer(std::io::stderr)
+        .with_ansi(console::colors_enabled_stderr())
+        .init();
+
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+            error::exit_code(&error)
+        }
     }
 }
 
-async fn watch_for_condition_met(
-    api: Api<DynamicObject>,
-    name: &str,
-    filter: Value,
-) -> Result<Value> {
-    let config = Config {
-        field_selector: Some(format!("metadata.name={name}")),
-        ..Default::default()
+async fn run() -> Result<()> {
+    let started = Instant::now();
+    let (mode, args) = Args::parse_resolved();
+
+    let sequence = match &args.sequence {
+        Some(path) => {
+            let mut steps = sequence::read_sequence(path).await?;
+            for step in &mut steps {
+                args.apply_preferences(&mut step.resource)
+                    .expect("preferences were checked when parsing");
+            }
+            Some(steps)
+        }
+        None => None,
+    };
+
+    let state_filter = if args.count_conditions || args.dry_run || sequence.is_some() {
+        None
+    } else {
+        let state_filter = if let Some(url) = &args.filter_url {
+            let filter = fetch_state_filter(
+                url,
+                &args.user_agent,
+                args.request_timeout.unwrap_or(DEFAULT_HTTP_TIMEOUT),
+            )
+            .await
+            .context("failed to construct state filter for the resource")?;
+            args.adjust_filter(filter)
+        } else if args.file.is_empty()
+            && (args.wait_for.is_some()
+                || args.cel.is_some()
+                || !args.has_label.is_empty()
+                || !args.has_annotation.is_empty()
+                || !args.any_of.is_empty()
+                || !args.all_of.is_empty())
+        {
+            Filter::any()
+        } else {
+            args.read_file_filters()
+                .await
+                .context("failed to construct state filter for the resource")?
+        };
+        // `--deep-contains` and `--array-identity-key` were applied when reading the state filter.
+        Some(args.with_metadata(state_filter)?)
     };
+    let any_of = args.read_named_filters(&args.any_of).await?;
+    let output_format = args.output_format().await?;
+    let all_of = args.read_named_filters(&args.all_of).await?;
+    let fail_if = args.read_named_filters(&args.fail_if).await?;
 
-    let mut stream = Box::pin(runtime::watcher(api, config).default_backoff());
-    while let Some(item) = stream.next().await {
-        match item {
-            Ok(Event::Applied(state)) => {
-                let serialized =
-                    serde_yaml::to_value(state).expect("serialization should not fail");
-                if match_state(&filter, &serialized) {
-                    return Ok(serialized);
-                }
+    let metrics = Arc::new(Metrics::default());
+    let metrics_server = match args.metrics_addr {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("failed to bind metrics server to {addr}"))?;
+            Some(tokio::spawn(metrics::serve(listener, metrics.clone())))
+        }
+        None => None,
+    };
+
+    let client = client::create_client(args.client_options()).await?;
+    let explicit = sequence.is_none()
+        && args
+            .resource_filter()
+            .expect("resource filter was checked when parsing")
+            .explicit()
+            .is_some();
+    let discovery = if explicit {
+        None
+    } else {
+        Some(Discovery::new(client.clone()).run().await?)
+    };
+    let discovery = discovery.as_ref();
+
+    if args.dry_run {
+        let single;
+        let filters: Vec<&ResourceFilter> = match &sequence {
+            Some(steps) => steps.iter().map(|step| &step.resource).collect(),
+            None => {
+                single = args
+                    .resource_filter()
+                    .expect("resource filter was checked when parsing");
+                vec![&single]
             }
+        };
+        let mut descriptions = Vec::with_capacity(filters.len());
+        for filter in filters {
+            let (api_resource, api_capabilities) = filter.select(discovery)?;
+            descriptions.push(resource::describe(&api_resource, &api_capabilities));
+        }
+        print_result(&descriptions.join("---\n"));
+        return Ok(());
+    }
+
+    if args.count_conditions {
+        let name = args.name.as_deref().expect("name was checked when parsing");
+        let [api] = &args.apis(&client, discovery, &ProgressBar::hidden())?[..] else {
+            unreachable!("namespace was checked when parsing");
+        };
+        let object = api
+            .get_opt(name)
+            .await
+            .with_context(|| format!("failed to get resource {name}"))?
+            .ok_or_else(|| WaitError::ResourceNotFound(name.into()))?;
+        print_result(&format!("{}\n", count_true_conditions(&object)));
+        return Ok(());
+    }
+
+    if mode != Mode::Wait {
+        let matcher = Matcher::new(
+            args.object_filter(),
+            state_filter.expect("state filter is read when not waiting for a sequence"),
+            args.wait_for.clone(),
+            args.cel.clone(),
+            &metrics,
+        )
+        .with_any_of(any_of.clone())
+        .with_all_of(all_of.clone())
+        .with_max_object_bytes(args.max_object_bytes, &ProgressBar::hidden());
+        let apis = args.apis(&client, discovery, &ProgressBar::hidden())?;
+        let watcher_config = args.watcher_config();
+        let progress = ProgressBar::hidden();
+        // Asked to stop before the list is done, nothing is printed.
+        let terminated = signal::terminated();
+        let states = tokio::select! {
+            states = watch::list_matching(apis, &watcher_config, matcher, &progress) => states?,
+            error = terminated => return Err(error.into()),
+        };
+
+        if mode == Mode::Count {
+            write_result(args.output_file.as_deref(), &format!("{}\n", states.len())).await?;
+        } else {
+            anyhow::ensure!(!states.is_empty(), WaitError::NotMatched);
+            for state in &states {
+                report_any_of(&any_of, state);
+            }
+            write_result(
+                args.output_file.as_deref(),
+                &output::render_states(&output_format, &states)?,
+            )
+            .await?;
+        }
+
+        return Ok(());
+    }
+
+    if args.no_color {
+        console::set_colors_enabled_stderr(false);
+    }
+    let progress = progress::spinner(args.quiet);
+    let followed = Cell::new(false);
+    let last_observed = RefCell::new(None);
+    let history = RefCell::new(History::default());
+    // With `--sequence`, the time from the start of the program until the match of every step.
+    let step_elapsed = RefCell::new(Vec::new());
+    let follow = |state: &Value| {
+        progress.suspend(|| report_any_of(&any_of, state));
+        let separator = if followed.replace(true) {
+            output_format.separator()
+        } else {
+            ""
+        };
+        match output::render_states(&output_format, std::slice::from_ref(state)) {
+            Ok(rendered) => progress.suspend(|| print_result(&format!("{separator}{rendered}"))),
+            Err(error) => progress.suspend(|| eprintln!("Warning: {error:#}.")),
+        }
+    };
+    let watch = async {
+        // Computed when the timeout starts, as the watch is started within it.
+        let watch_options = args.watch_options();
+        if let Some(steps) = sequence {
+            let (states, elapsed) = wait_sequence(
+                &args,
+                &client,
+                discovery,
+                steps,
+                &metrics,
+                watch_options,
+                &progress,
+                started,
+            )
+            .await?
+            .into_iter()
+            .unzip();
+            *step_elapsed.borrow_mut() = elapsed;
+            return Ok(states);
+        }
+
+        let matcher = Matcher::new(
+            args.object_filter(),
+            state_filter.expect("state filter is read when not waiting for a sequence"),
+            args.wait_for.clone(),
+            args.cel.clone(),
+            &metrics,
+        )
+        .with_confirm_after(args.confirm_after)
+        .with_explain(args.explain.then(|| progress.clone()))
+        .with_any_of(any_of.clone())
+        .with_all_of(all_of.clone())
+        .with_fail_if(fail_if)
+        .with_max_object_bytes(args.max_object_bytes, &progress)
+        .with_status_changes_only(args.subresource == Some(Subresource::Status))
+        .with_last_listed_match(args.last_match_in_list)
+        .with_changed(args.changed.clone())
+        .with_follow(args.follow.then_some(&follow as &dyn Fn(&Value)))
+        .with_last_observed(args.timeout_exit_zero.then_some(&last_observed))
+        .with_history(args.record_history.then_some(&history));
+        let mut apis = args.apis(&client, discovery, &progress)?;
 
-            Ok(Event::Deleted(_)) => {}
+        if let Some(path) = &args.subresource_path {
+            let api = apis.pop().expect("a single namespace is required");
+            let name = args.name.as_deref().expect("name is required");
+            let state =
+                poll_subresource(api, name, path, matcher, watch_options, &progress).await?;
+            return Ok(vec![state]);
+        }
 
-            Ok(Event::Restarted(states)) => {
-                for state in states {
-                    let serialized =
-                        serde_yaml::to_value(state).expect("serialization should not fail");
-                    if match_state(&filter, &serialized) {
-                        return Ok(serialized);
+        if args.watch_count || args.count.is_some() {
+            let states = watch::watch_count(
+                apis,
+                args.watcher_config(),
+                matcher,
+                watch_options,
+                args.count,
+                &progress,
+                |matching, total| {
+                    if args.watch_count {
+                        progress.suspend(|| print_result(&format!("{matching}/{total} matching\n")))
                     }
-                }
+                },
+            )
+            .await?;
+            // With `--watch-count`, the counts are the result.
+            return Ok(if args.watch_count { Vec::new() } else { states });
+        }
+
+        let mut events = Vec::new();
+        if args.show_events {
+            let involved = InvolvedObject {
+                kind: args
+                    .kind
+                    .clone()
+                    .expect("kind is required without a sequence"),
+                name: args.name.clone(),
+                name_glob: args.name_glob.clone(),
+                names: args.any_name.clone(),
+                uid: args.uid.clone(),
+            };
+
+            for namespace in args.namespaces() {
+                let events_api = match namespace.name(&client) {
+                    Some(namespace) => Api::namespaced(client.clone(), namespace),
+                    None => Api::all(client.clone()),
+                };
+                events.push(tokio::spawn(events::show_events(
+                    events_api,
+                    involved.clone(),
+                    progress.clone(),
+                )));
             }
+        }
+
+        let state = watch_any_for_condition_met(
+            apis,
+            args.watcher_config(),
+            matcher,
+            watch_options,
+            &progress,
+        )
+        .await;
+
+        for events in events {
+            events.abort();
+        }
 
-            Err(error) => {
-                eprintln!(
-                    "Watcher stream encountered an error and will restart with backoff: {error}."
-                );
+        if let (Ok(state), false) = (&state, args.any_name.is_empty()) {
+            let name = state["metadata"]["name"].as_str().unwrap_or_default();
+            progress.suspend(|| eprintln!("Matched resource {name}."));
+        }
+
+        Ok(vec![state?])
+    };
+    let watch = async {
+        match args.timeout.and_then(|timeout| u64::try_from(timeout).ok()) {
+            Some(timeout) if timeout > 0 => time::timeout(Duration::from_secs(timeout), watch)
+                .await
+                .context(WaitError::Timeout)
+                .and_then(|result| result),
+            _ => watch.await,
+        }
+    };
+    // The wait ends as usual when the program is asked to stop, so that the output is still complete.
+    let terminated = signal::terminated();
+    let found_states = tokio::select! {
+        found_states = watch => found_states,
+        error = terminated => Err(error.into()),
+    };
+    progress.finish_and_clear();
+    if args.report_stats {
+        eprintln!("{}", metrics.report(started.elapsed()));
+    }
+    if args.report_rv {
+        match metrics.max_resource_version() {
+            Some(version) => eprintln!("Highest resourceVersion seen: {version}."),
+            None => eprintln!("No resourceVersion seen."),
+        }
+    }
+    if args.status_json {
+        eprintln!(
+            "{}",
+            completion_status(&found_states, started.elapsed(), &metrics)
+        );
+    }
+    if let Err(error) = &found_states {
+        if args.record_history
+            && matches!(
+                error.downcast_ref(),
+                Some(WaitError::Timeout | WaitError::NotMatched)
+            )
+        {
+            eprintln!("{}", history.borrow().to_json());
+        }
+    }
+    // Whether the printed state is the last observed one, because the wait timed out.
+    let mut timed_out = false;
+    let found_states = match found_states {
+        // In follow mode, the wait ends only with the timeout, or after a single check without a match.
+        // Without a target count, watching the count ends only with the timeout.
+        Err(error)
+            if (followed.get() || (args.watch_count && args.count.is_none()))
+                && matches!(
+                    error.downcast_ref(),
+                    Some(WaitError::Timeout | WaitError::NotMatched)
+                ) =>
+        {
+            Vec::new()
+        }
+        Err(error)
+            if args.timeout_exit_zero
+                && matches!(
+                    error.downcast_ref(),
+                    Some(WaitError::Timeout | WaitError::NotMatched)
+                ) =>
+        {
+            let last_observed = last_observed.take();
+            match last_observed {
+                Some(_) => eprintln!("Warning: {error}, printing the last observed state instead."),
+                None => eprintln!("Warning: {error}, no state was observed."),
+            }
+            timed_out = true;
+            last_observed.into_iter().collect()
+        }
+        found_states => found_states?,
+    };
+    let elapsed = started.elapsed();
+
+    if let Some(server) = metrics_server {
+        server.abort();
+    }
+
+    for found_state in &found_states {
+        report_any_of(&any_of, found_state);
+    }
+    write_result(
+        args.output_file.as_deref(),
+        &output::render_states(&output_format, &found_states)?,
+    )
+    .await?;
+
+    if let Some(url) = &args.notify_url {
+        let step_elapsed = step_elapsed.take();
+        for (i, found_state) in found_states.iter().enumerate() {
+            if let Err(error) = notify::notify(
+                url,
+                &args.user_agent,
+                args.request_timeout.unwrap_or(DEFAULT_HTTP_TIMEOUT),
+                found_state,
+                step_elapsed.get(i).copied().unwrap_or(elapsed),
+                !timed_out,
+            )
+            .await
+            {
+                eprintln!("Warning: {error:#}.");
             }
         }
     }
 
-    anyhow::bail!("Watcher stream finished unexpectedly");
+    Ok(())
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, PoisonError};
 
-    let state_filter = read_state_filter(args.file.as_deref())
-        .await
-        .context("failed to construct state filter for the resource")?;
+    use super::*;
+    use crate::testing::{self, FakeApi, Response};
 
-    let client = Client::try_default().await?;
-    let discovery = Discovery::new(client.clone()).run().await?;
+    /// Serializes the tests reading and modifying [`KIND_ENV`].
+    static KIND_ENV_LOCK: Mutex<()> = Mutex::new(());
 
-    let found = discovery
-        .groups()
-        .flat_map(ApiGroup::recommended_resources)
-        .filter(|(api_resource, _)| args.filter_resource(api_resource))
-        .collect::<Vec<_>>();
+    /// Parses the arguments with [`KIND_ENV`] set to the kind, or unset.
+    fn parse(kind: Option<&str>, args: &[&str]) -> Result<(Mode, Args), clap::Error> {
+        let _guard = KIND_ENV_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        match kind {
+            Some(kind) => env::set_var(KIND_ENV, kind),
+            None => env::remove_var(KIND_ENV),
+        }
+        let parsed = Args::try_parse_resolved_from([&["k8s-wait-full"], args].concat());
+        env::remove_var(KIND_ENV);
+        parsed
+    }
 
-    anyhow::ensure!(
-        !found.is_empty(),
-        "No API resources matching filtering criteria were found"
-    );
+    fn args(args: &[&str]) -> Args {
+        parse(None, args).unwrap().1
+    }
 
-    anyhow::ensure!(
-        found.len() == 1,
-        "Multiple resources matching filtering criteria were found, try narrowing your filtering criteria"
-    );
+    fn parse_error(kind: Option<&str>, args: &[&str]) -> ErrorKind {
+        parse(kind, args).err().expect("parsing should fail").kind()
+    }
 
-    let (api_resource, api_capabilities) =
-        found.into_iter().next().expect("length was just checked");
+    #[test]
+    fn kind_is_taken_from_the_environment() {
+        let (_, args) = parse(Some("Deployment"), &["web"]).unwrap();
+        assert_eq!(args.kind.as_deref(), Some("Deployment"));
+        assert_eq!(args.name.as_deref(), Some("web"));
 
-    let api: Api<DynamicObject> = match api_capabilities.scope {
-        Scope::Cluster => Api::all_with(client.clone(), &api_resource),
-        Scope::Namespaced => Api::namespaced_with(
-            client.clone(),
-            args.namespace
-                .as_deref()
-                .unwrap_or_else(|| client.default_namespace()),
-            &api_resource,
-        ),
-    };
+        let (_, args) = parse(Some("Deployment"), &["Pod", "web"]).unwrap();
+        assert_eq!(args.kind.as_deref(), Some("Pod"));
+        assert_eq!(args.name.as_deref(), Some("web"));
+
+        let (_, args) = parse(Some("Deployment"), &["--selector", "app=web"]).unwrap();
+        assert_eq!(args.kind.as_deref(), Some("Deployment"));
+        assert_eq!(args.name, None);
+
+        assert_eq!(
+            parse_error(None, &["--selector", "app=web"]),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn positional_stays_the_kind_when_it_cannot_be_a_name() {
+        // Names cannot start with an uppercase letter.
+        assert_eq!(
+            parse_error(Some("Deployment"), &["Pod"]),
+            ErrorKind::MissingRequiredArgument
+        );
+        let (_, args) = parse(Some("Deployment"), &["Pod", "--selector", "app=web"]).unwrap();
+        assert_eq!(args.kind.as_deref(), Some("Pod"));
+        assert_eq!(args.name, None);
 
-    let found_state = match args.timeout {
-        Some(timeout) => time::timeout(
-            Duration::from_secs(timeout),
-            watch_for_condition_met(api, &args.name, state_filter),
+        for selection in [&["--selector", "app=web"][..], &["--count", "2"]] {
+            let (_, args) = parse(Some("Deployment"), &[&["pods"], selection].concat()).unwrap();
+            assert_eq!(args.kind.as_deref(), Some("pods"));
+            assert_eq!(args.name, None);
+        }
+    }
+
+    #[test]
+    fn watch_requests_end_before_the_request_timeout() {
+        let watch_timeout = |request_timeout: &str| {
+            args(&["Pod", "web", "--request-timeout", request_timeout])
+                .watcher_config()
+                .timeout
+        };
+        assert_eq!(watch_timeout("30s"), Some(25));
+        assert_eq!(watch_timeout("5s"), Some(1));
+        assert_eq!(watch_timeout("500ms"), Some(1));
+        assert_eq!(watch_timeout("1h"), Some(290));
+        assert_eq!(args(&["Pod", "web"]).watcher_config().timeout, None);
+    }
+
+    #[test]
+    fn since_accepts_timestamps_and_durations() {
+        assert_eq!(
+            parse_since("2024-05-01T10:00:00+02:00").unwrap(),
+            "2024-05-01T08:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        );
+
+        let before = Utc::now();
+        let since = parse_since("1h 30m").unwrap();
+        let expected = before - Duration::from_secs(90 * 60);
+        assert!(since >= expected && (since - expected).num_seconds() < 5);
+
+        assert!(parse_since("yesterday").is_err());
+        assert!(parse_since("2024-05-01").is_err());
+        assert!(parse_since("").is_err());
+    }
+
+    fn state_matches(filter: &Filter, state: &str) -> bool {
+        filter::match_state(filter, &serde_yaml::from_str(state).unwrap())
+    }
+
+    #[test]
+    fn labels_are_required_by_the_filter() {
+        let args = args(&[
+            "Pod",
+            "web",
+            "--has-label",
+            "app=web",
+            "--has-label",
+            "tier=db",
+        ]);
+        let filter = args.with_metadata(Filter::any()).unwrap();
+        assert!(state_matches(
+            &filter,
+            "{ metadata: { labels: { app: web, tier: db, extra: label } } }"
+        ));
+        assert!(!state_matches(
+            &filter,
+            "{ metadata: { labels: { app: web } } }"
+        ));
+        assert!(!state_matches(&filter, "{ metadata: { name: web } }"));
+    }
+
+    #[test]
+    fn output_file_extension_selects_the_format() {
+        let output = |args: &[&str]| {
+            parse(None, &[&["Pod", "web"], args].concat())
+                .unwrap()
+                .1
+                .output
+        };
+        assert!(output(&["--output-file", "result.json"]) == Output::Json);
+        assert!(output(&["--output-file", "result.yaml"]) == Output::Yaml);
+        assert!(output(&["--output-file", "result.yml", "-o", "yaml-flow"]) == Output::YamlFlow);
+        assert!(output(&["--output-file", "result.txt", "-o", "name"]) == Output::Name);
+        assert!(output(&["--output-file", "result.txt"]) == Output::Yaml);
+        assert!(output(&["--output-file", "result"]) == Output::Yaml);
+        // An explicit format takes precedence over the extension.
+        assert!(output(&["--output-file", "result.json", "-o", "yaml"]) == Output::Yaml);
+        assert!(output(&["--output-file", "result.yaml", "-o", "ref"]) == Output::Ref);
+        assert!(output(&["-o", "json"]) == Output::Json);
+    }
+
+    #[test]
+    fn annotations_are_required_by_the_filter() {
+        let args = args(&[
+            "Pod",
+            "web",
+            "--has-label",
+            "app=web",
+            "--has-annotation",
+            "example.com/owner=team a",
+        ]);
+        let filter = args
+            .with_metadata(
+                Filter::parse(serde_yaml::from_str("{ status: { phase: Running } }").unwrap())
+                    .unwrap(),
+            )
+            .unwrap();
+        let state = |annotations: &str| {
+            format!("{{ metadata: {{ labels: {{ app: web }}, annotations: {annotations} }}, status: {{ phase: Running }} }}")
+        };
+        assert!(state_matches(
+            &filter,
+            &state("{ example.com/owner: team a, other: x }")
+        ));
+        assert!(!state_matches(
+            &filter,
+            &state("{ example.com/owner: team-a }")
+        ));
+        assert!(!state_matches(&filter, &state("{}")));
+        // The requirements are added to the state filter.
+        assert!(!state_matches(
+            &filter,
+            "{ metadata: { labels: { app: web }, annotations: { example.com/owner: team a } }, status: { phase: Pending } }"
+        ));
+    }
+
+    fn pod_step(name: &str, filter: &str) -> Step {
+        Step {
+            resource: ResourceFilter::new("Pod".into(), None, None, Some("v1"), None).unwrap(),
+            name: name.into(),
+            namespace: None,
+            filter: Filter::parse(serde_yaml::from_str(filter).unwrap()).unwrap(),
+            wait_for: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn sequence_steps_are_waited_for_in_order() {
+        let api = FakeApi::start(|request| {
+            if let Some(response) = testing::discovery(request, &[("v1", "Pod")]) {
+                return response;
+            }
+            let name = request.query("fieldSelector").unwrap_or_default();
+            match (name.as_str(), request.is_watch()) {
+                ("metadata.name=db", false) => {
+                    Response::Json(200, testing::pod_list(vec![testing::pod("db", "Running")]))
+                }
+                ("metadata.name=web", false) => {
+                    Response::Json(200, testing::pod_list(vec![testing::pod("web", "Pending")]))
+                }
+                ("metadata.name=web", true) => Response::Events(vec![testing::event(
+                    "MODIFIED",
+                    testing::pod("web", "Running"),
+                )]),
+                _ => Response::Events(Vec::new()),
+            }
+        })
+        .await;
+        let client = api.client();
+        let discovery = Discovery::new(client.clone()).run().await.unwrap();
+        let args = args(&["--sequence", "sequence.yaml"]);
+        let metrics = Metrics::default();
+
+        let steps = vec![
+            pod_step("db", "status: { phase: Running }"),
+            pod_step("web", "status: { phase: Running }"),
+        ];
+        let states = wait_sequence(
+            &args,
+            &client,
+            Some(&discovery),
+            steps,
+            &metrics,
+            args.watch_options(),
+            &ProgressBar::hidden(),
+            Instant::now(),
         )
         .await
-        .context("timeout expired")??,
-        None => watch_for_condition_met(api, &args.name, state_filter).await?,
-    };
+        .unwrap();
+        let names = states
+            .iter()
+            .map(|(state, _)| state["metadata"]["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["db", "web"]);
+        assert_eq!(states[1].0["status"]["phase"], "Running");
+
+        // The second step starts only when the first one matched.
+        let selectors = api
+            .requests()
+            .iter()
+            .filter_map(|request| request.query("fieldSelector"))
+            .collect::<Vec<_>>();
+        let last_db = selectors
+            .iter()
+            .rposition(|selector| selector == "metadata.name=db")
+            .unwrap();
+        let first_web = selectors
+            .iter()
+            .position(|selector| selector == "metadata.name=web")
+            .unwrap();
+        assert!(last_db < first_web, "{selectors:?}");
+    }
 
-    let serialized = serde_yaml::to_string(&found_state)
-        .context("failed to serialize matching resource state")?;
+    async fn fetch(response: fn() -> Response) -> Result<Filter> {
+        let server = FakeApi::start(move |_| response()).await;
+        let url = format!("{}/filter.yaml", server.url()).parse().unwrap();
+        let filter = fetch_state_filter(&url, "k8s-wait-full/test", Duration::from_secs(5)).await;
+        assert_eq!(
+            server.requests()[0].header("user-agent"),
+            Some("k8s-wait-full/test")
+        );
+        filter
+    }
 
-    println!("{serialized}");
+    #[tokio::test]
+    async fn state_filter_is_fetched() {
+        let filter = fetch(|| Response::Text(200, "status:\n  phase: Running\n".into()))
+            .await
+            .unwrap();
+        assert!(state_matches(&filter, "{ status: { phase: Running } }"));
+        assert!(!state_matches(&filter, "{ status: { phase: Pending } }"));
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn failed_fetches_are_reported() {
+        let error = fetch(|| Response::Text(404, "not found".into()))
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .ends_with("server responded with 404 Not Found"),
+            "{error}"
+        );
+
+        let error = fetch(|| Response::Text(200, "status: [".into()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "failed to deserialize state filter");
+
+        let error = fetch(|| Response::Text(200, "status: { $unknown: 1 }".into()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error::exit_code(&error), ExitCode::from(2));
+    }
+
+    #[test]
+    fn all_namespaces_replace_other_namespaces() {
+        let all = |given: &[&str]| args(given).namespaces() == [Namespace::All];
+        assert!(all(&["pod", "web", "-A"]));
+        assert!(all(&["pod", "web", "--namespace", "all"]));
+        assert!(all(&["pod", "web", "--namespace", "team-a,all"]));
+        assert!(all(&["pod", "web", "-A", "--namespace", "all"]));
+        assert!(!all(&["pod", "web", "--namespace", "team-a"]));
+
+        let named = args(&["pod", "web", "--namespace", "team-a,team-b,team-a"]);
+        assert!(named.namespaces() == [Namespace::Named("team-a"), Namespace::Named("team-b")]);
+        assert!(args(&["pod", "web"]).namespaces() == [Namespace::Default]);
+
+        assert_eq!(
+            parse_error(None, &["pod", "web", "-A", "--namespace", "team-a"]),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
+    #[test]
+    fn subcommands_select_the_mode() {
+        for (subcommand, expected) in [
+            ("wait", Mode::Wait),
+            ("get", Mode::Get),
+            ("count", Mode::Count),
+        ] {
+            let (mode, args) = parse(None, &[subcommand, "Pod", "web", "--timeout", "5"]).unwrap();
+            assert!(mode == expected, "{subcommand}");
+            assert_eq!(args.kind.as_deref(), Some("Pod"));
+            assert_eq!(args.name.as_deref(), Some("web"));
+            assert_eq!(args.timeout, Some(5));
+
+            let cli = Cli::try_parse_from(["k8s-wait-full", subcommand, "Pod", "web"]).unwrap();
+            assert!(cli.command.is_some(), "{subcommand}");
+        }
+
+        let (mode, args) = parse(None, &["Pod", "web"]).unwrap();
+        assert!(mode == Mode::Wait);
+        assert_eq!(args.name.as_deref(), Some("web"));
+        let cli = Cli::try_parse_from(["k8s-wait-full", "Pod", "web"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.args.kind.as_deref(), Some("Pod"));
+    }
+
+    #[test]
+    fn subcommands_reject_misplaced_options() {
+        // Options of the bare form cannot be combined with a subcommand.
+        assert_eq!(
+            parse_error(None, &["--timeout", "5", "get", "Pod", "web"]),
+            ErrorKind::ArgumentConflict
+        );
+        // Options that only make sense when waiting.
+        assert_eq!(
+            parse_error(None, &["get", "Pod", "web", "--follow"]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(
+            parse_error(
+                None,
+                &[
+                    "count",
+                    "Pod",
+                    "--selector",
+                    "app=web",
+                    "--confirm-after",
+                    "5s"
+                ]
+            ),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
+    #[test]
+    fn completion_status_describes_the_result() {
+        let metrics = Metrics::default();
+        let status = |result: Result<Vec<Value>>| -> serde_json::Value {
+            let line = completion_status(&result, Duration::from_millis(1500), &metrics);
+            assert!(!line.contains('\n'), "{line}");
+            serde_json::from_str(&line).unwrap()
+        };
+
+        assert_eq!(
+            status(Ok(Vec::new())),
+            serde_json::json!({ "result": "matched", "elapsed_ms": 1500, "events": 0 })
+        );
+
+        let failures = [
+            (
+                anyhow::Error::new(WaitError::Timeout),
+                "timeout",
+                "timeout expired",
+            ),
+            (
+                WaitError::NotMatched.into(),
+                "not_matched",
+                "no resource state matching the filter was found",
+            ),
+            (
+                WaitError::Terminated.into(),
+                "terminated",
+                "terminated by SIGTERM",
+            ),
+            (
+                WaitError::Interrupted.into(),
+                "terminated",
+                "interrupted by SIGINT",
+            ),
+            (
+                WaitError::FailIfMatched {
+                    resource: "default/web".into(),
+                    filter: "failed.yaml".into(),
+                }
+                .into(),
+                "failed",
+                "state of default/web matched the `--fail-if` filter failed.yaml",
+            ),
+            (
+                anyhow::anyhow!("connection refused").context("failed to list resources"),
+                "error",
+                "failed to list resources: connection refused",
+            ),
+        ];
+        for (error, result, message) in failures {
+            assert_eq!(
+                status(Err(error)),
+                serde_json::json!({
+                    "result": result,
+                    "elapsed_ms": 1500,
+                    "events": 0,
+                    "error": message,
+                })
+            );
+        }
+    }
 }
@@ -0,0 +1,303 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use kube::{runtime::watcher::Event, Resource};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Counters describing the progress of the watch.
+#[derive(Default)]
+pub struct Metrics {
+    events: AtomicU64,
+    applied: AtomicU64,
+    listed: AtomicU64,
+    deleted: AtomicU64,
+    restarted: AtomicU64,
+    match_attempts: AtomicU64,
+    watch_errors: AtomicU64,
+    matched: AtomicBool,
+    /// Highest resourceVersion of the received resources, zero if none was received.
+    max_resource_version: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_event<K: Resource>(&self, event: &Event<K>) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+        let counter = match event {
+            Event::Apply(_) => &self.applied,
+            Event::InitApply(_) => &self.listed,
+            Event::Delete(_) => &self.deleted,
+            Event::Init => &self.restarted,
+            Event::InitDone => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        if let Event::Apply(resource) | Event::InitApply(resource) | Event::Delete(resource) = event
+        {
+            self.record_resource_version(resource);
+        }
+    }
+
+    /// Records a resource listed in a poll, like one listed after a watch (re)start.
+    pub fn record_listed<K: Resource>(&self, resource: &K) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+        self.listed.fetch_add(1, Ordering::Relaxed);
+        self.record_resource_version(resource);
+    }
+
+    /// resourceVersions are opaque strings, but the API server backed by etcd uses integers.
+    /// Other resourceVersions are ignored.
+    fn record_resource_version<K: Resource>(&self, resource: &K) {
+        if let Some(version) = resource
+            .meta()
+            .resource_version
+            .as_deref()
+            .and_then(|version| version.parse().ok())
+        {
+            self.max_resource_version
+                .fetch_max(version, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_match_attempt(&self) {
+        self.match_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_watch_error(&self) {
+        self.watch_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_matched(&self) {
+        self.matched.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns the number of events received from the watch stream, or resources listed in polls.
+    pub fn events(&self) -> u64 {
+        self.events.load(Ordering::Relaxed)
+    }
+
+    /// Returns the highest resourceVersion of the resources received so far, for `--report-rv`.
+    pub fn max_resource_version(&self) -> Option<u64> {
+        Some(self.max_resource_version.load(Ordering::Relaxed)).filter(|version| *version > 0)
+    }
+
+    /// Summarizes the processed events for `--report-stats`, e.g.
+    /// `Processed 7 events in 1.250s: 2 applied, 3 listed, 0 deleted, 1 restarted, 0 errors.`
+    /// The end of a list after a watch (re)start counts only towards the total.
+    pub fn report(&self, elapsed: Duration) -> String {
+        format!(
+            "Processed {} events in {:.3}s: {} applied, {} listed, {} deleted, {} restarted, {} errors.",
+            self.events(),
+            elapsed.as_secs_f64(),
+            self.applied.load(Ordering::Relaxed),
+            self.listed.load(Ordering::Relaxed),
+            self.deleted.load(Ordering::Relaxed),
+            self.restarted.load(Ordering::Relaxed),
+            self.watch_errors.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, help, kind, value) in [
+            (
+                "k8s_wait_watch_events_total",
+                "Number of events received from the watch stream.",
+                "counter",
+                self.events.load(Ordering::Relaxed),
+            ),
+            (
+                "k8s_wait_match_attempts_total",
+                "Number of resource states evaluated against the state filter.",
+                "counter",
+                self.match_attempts.load(Ordering::Relaxed),
+            ),
+            (
+                "k8s_wait_watch_errors_total",
+                "Number of errors encountered by the watch stream.",
+                "counter",
+                self.watch_errors.load(Ordering::Relaxed),
+            ),
+            (
+                "k8s_wait_matched",
+                "Whether a resource state matching the state filter was found.",
+                "gauge",
+                self.matched.load(Ordering::Relaxed).into(),
+            ),
+        ] {
+            writeln!(out, "# HELP {name} {help}").expect("writing to a string should not fail");
+            writeln!(out, "# TYPE {name} {kind}").expect("writing to a string should not fail");
+            writeln!(out, "{name} {value}").expect("writing to a string should not fail");
+        }
+
+        out
+    }
+}
+
+/// Serves the metrics over HTTP on `/metrics` until the returned future is dropped.
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                eprintln!("Metrics server failed to accept a connection: {error}.");
+                continue;
+            }
+        };
+
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(error) = handle_connection(stream, &metrics).await {
+                eprintln!("Metrics server failed to handle a connection: {error:#}.");
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+    let mut buf = Vec::with_capacity(1024);
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        anyhow::ensure!(buf.len() < MAX_REQUEST_HEAD, "request head too large");
+
+        let mut chunk = [0; 1024];
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .context("failed to read the request")?;
+        anyhow::ensure!(
+            read > 0,
+            "connection closed before the request was complete"
+        );
+        buf.extend_from_slice(&chunk[..read]);
+    }
+
+    let request_line = buf.split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|b| *b == b' ');
+    let response = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".into(),
+    };
+
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .context("failed to write the response")?;
+    stream
+        .shutdown()
+        .await
+        .context("failed to close the connection")
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::api::DynamicObject;
+
+    use super::*;
+
+    fn pod(resource_version: &str) -> DynamicObject {
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": { "name": "web", "resourceVersion": resource_version },
+        }))
+        .unwrap()
+    }
+
+    async fn scrape(url: &str) -> (u16, String) {
+        let response = reqwest::get(url).await.unwrap();
+        (response.status().as_u16(), response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn counters_are_served_on_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+        let metrics = Arc::new(Metrics::default());
+        let server = tokio::spawn(serve(listener, metrics.clone()));
+
+        let (status, body) = scrape(&url).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("# TYPE k8s_wait_watch_events_total counter\n"));
+        assert!(body.contains("\nk8s_wait_watch_events_total 0\n"));
+        assert!(body.contains("\nk8s_wait_matched 0\n"));
+
+        metrics.record_event(&Event::Apply(pod("1")));
+        metrics.record_event(&Event::Apply(pod("2")));
+        metrics.record_match_attempt();
+        metrics.record_watch_error();
+        metrics.record_matched();
+
+        let (_, body) = scrape(&url).await;
+        assert!(body.contains("\nk8s_wait_watch_events_total 2\n"));
+        assert!(body.contains("\nk8s_wait_match_attempts_total 1\n"));
+        assert!(body.contains("\nk8s_wait_watch_errors_total 1\n"));
+        assert!(body.contains("\nk8s_wait_matched 1\n"));
+
+        let (status, _) = scrape(&url.replace("/metrics", "/other")).await;
+        assert_eq!(status, 404);
+
+        server.abort();
+    }
+
+    #[test]
+    fn events_are_counted_by_type() {
+        let metrics = Metrics::default();
+        let events = [
+            Event::Init,
+            Event::InitApply(pod("3")),
+            Event::InitApply(pod("5")),
+            Event::InitDone,
+            Event::Apply(pod("7")),
+            Event::Delete(pod("8")),
+            // The watch restarts after an error.
+            Event::Init,
+            Event::InitApply(pod("8")),
+            Event::InitDone,
+        ];
+        for event in &events {
+            metrics.record_event(event);
+        }
+        metrics.record_listed(&pod("not-a-number"));
+        metrics.record_watch_error();
+
+        assert_eq!(
+            metrics.report(Duration::from_millis(1250)),
+            "Processed 10 events in 1.250s: 1 applied, 4 listed, 1 deleted, 2 restarted, 1 errors."
+        );
+        assert_eq!(metrics.max_resource_version(), Some(8));
+    }
+
+    #[test]
+    fn highest_integer_resource_version_is_kept() {
+        let metrics = Metrics::default();
+        assert_eq!(metrics.max_resource_version(), None);
+
+        metrics.record_event(&Event::InitApply(pod("12")));
+        metrics.record_event(&Event::Apply(pod("9")));
+        metrics.record_listed(&pod("opaque-42"));
+        metrics.record_event(&Event::Delete(pod("")));
+        assert_eq!(metrics.max_resource_version(), Some(12));
+
+        metrics.record_event(&Event::Apply(pod("18446744073709551615")));
+        assert_eq!(metrics.max_resource_version(), Some(u64::MAX));
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::{header, Url};
+use serde_yaml::Value;
+
+/// Sends the resource state to the webhook with an HTTP POST request, e.g.
+///
+/// ```json
+/// { "object": { "apiVersion": "v1", "kind": "Pod", ... }, "elapsedSeconds": 12.5, "matched": true }
+/// ```
+///
+/// `elapsed` is the time from the start of the program until the match, or until the timeout.
+/// `matched` is `false` when the state is only the last observed one, because the wait timed out.
+pub async fn notify(
+    url: &Url,
+    user_agent: &str,
+    timeout: Duration,
+    state: &Value,
+    elapsed: Duration,
+    matched: bool,
+) -> Result<()> {
+    let body = serde_json::to_vec(&serde_json::json!({
+        "object": state,
+        "elapsedSeconds": elapsed.as_secs_f64(),
+        "matched": matched,
+    }))
+    .context("failed to serialize notification")?;
+
+    let client = reqwest::Client::builder()
+        .user_agent(user_agent)
+        .timeout(timeout)
+        .build()
+        .context("failed to create HTTP client")?;
+
+    let response = client
+        .post(url.clone())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        .with_context(|| format!("failed to send notification to {url}"))?;
+
+    let status = response.status();
+    anyhow::ensure!(
+        status.is_success(),
+        "failed to send notification to {url}: server responded with {status}"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeApi, Response};
+
+    #[tokio::test]
+    async fn state_is_posted_as_json() {
+        let webhook = FakeApi::start(|_| Response::Text(204, String::new())).await;
+        let url = format!("{}/hooks/ready", webhook.url()).parse().unwrap();
+        let state = serde_yaml::from_str("{ kind: Pod, metadata: { name: web } }").unwrap();
+
+        notify(
+            &url,
+            "k8s-wait-full/test",
+            Duration::from_secs(5),
+            &state,
+            Duration::from_millis(12500),
+            true,
+        )
+        .await
+        .unwrap();
+
+        let requests = webhook.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path(), "/hooks/ready");
+        assert_eq!(requests[0].header("content-type"), Some("application/json"));
+        assert_eq!(requests[0].header("user-agent"), Some("k8s-wait-full/test"));
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "object": { "kind": "Pod", "metadata": { "name": "web" } },
+                "elapsedSeconds": 12.5,
+                "matched": true,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn unsuccessful_responses_are_errors() {
+        let webhook = FakeApi::start(|_| Response::Text(503, "unavailable".into())).await;
+        let url = webhook.url().parse().unwrap();
+
+        let error = notify(
+            &url,
+            "k8s-wait-full/test",
+            Duration::from_secs(5),
+            &Value::Null,
+            Duration::ZERO,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("failed to send notification to {url}: server responded with 503 Service Unavailable")
+        );
+    }
+}
@@ -0,0 +1,467 @@
+use std::{borrow::Cow, path::Path, str::FromStr};
+
+use anyhow::{Context, Result};
+use minijinja::{syntax::SyntaxConfig, value::Serde, Environment, UndefinedBehavior};
+use serde_yaml::Value;
+use tokio::fs;
+
+use crate::path::ValuePath;
+
+/// Named value extracted from the matched resource state, e.g. `ip=.status.podIP`.
+#[derive(Clone)]
+pub struct OutputField {
+    pub name: String,
+    pub path: ValuePath,
+}
+
+impl FromStr for OutputField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `<name>=<path>`, got `{s}`"))?;
+
+        Ok(Self {
+            name: name.into(),
+            path: path.parse()?,
+        })
+    }
+}
+
+/// Parses a field for `--output-env`, whose name must be a valid shell variable name, e.g. `IP=.status.podIP`.
+pub fn parse_env_field(s: &str) -> Result<OutputField, String> {
+    let field: OutputField = s.parse()?;
+
+    let mut chars = field.name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!(
+            "invalid variable name `{}`, expected letters, digits and underscores, not starting with a digit",
+            field.name
+        ));
+    }
+
+    Ok(field)
+}
+
+/// How the matching resource states are printed.
+pub enum OutputFormat {
+    /// Whole states as YAML documents.
+    /// `metadata.managedFields` is removed unless `keep_managed_fields`, see [`displayed_state`].
+    State { keep_managed_fields: bool },
+    /// Whole states as single lines of flow-style YAML, which is also compact JSON.
+    FlowState { keep_managed_fields: bool },
+    /// kubectl references as lines, e.g. `deployment.apps/web`, see [`resource_name`].
+    Name,
+    /// kubectl references prefixed with the namespace as lines, e.g. `default/deployment.apps/web`.
+    Ref,
+    /// Selected fields as `<name>\t<value>` lines.
+    Fields(Vec<OutputField>),
+    /// Labels as `<key>=<value>` lines, sorted by key.
+    Labels,
+    /// Selected fields as shell variable assignments, e.g. `IP='10.0.0.1'`.
+    /// Missing values are assigned empty strings, or skipped with `skip_missing`.
+    Env {
+        fields: Vec<OutputField>,
+        skip_missing: bool,
+    },
+    /// States rendered through a template.
+    Template(OutputTemplate),
+}
+
+/// Jinja template rendered with the fields of the matching resource state, e.g. `{{ metadata.name }}`.
+pub struct OutputTemplate {
+    name: String,
+    source: String,
+}
+
+impl OutputTemplate {
+    /// Reads the template from the file, failing if it is not a valid template.
+    pub async fn read(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read output template from {}", path.display()))?;
+        let template = Self {
+            name: path.display().to_string(),
+            source,
+        };
+        template
+            .environment()
+            .with_context(|| format!("invalid output template {}", template.name))?;
+
+        Ok(template)
+    }
+
+    /// Printing undefined values fails, so that e.g. typos in field names are reported,
+    /// but they can still be checked in conditions, e.g. `{% if status.podIP %}`.
+    fn environment(&self) -> Result<Environment<'_>, minijinja::Error> {
+        let mut environment = Environment::new();
+        environment.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+        environment.set_syntax(
+            SyntaxConfig::builder()
+                .keep_trailing_newline(true)
+                .build()?,
+        );
+        environment.add_template(&self.name, &self.source)?;
+        Ok(environment)
+    }
+
+    fn render(&self, state: &Value) -> Result<String> {
+        self.environment()
+            .and_then(|environment| {
+                environment
+                    .get_template(&self.name)?
+                    .render(minijinja::Value::from(Serde(state)))
+            })
+            .with_context(|| format!("failed to render output template {}", self.name))
+    }
+}
+
+impl OutputFormat {
+    /// Returns the separator printed between rendered states.
+    /// Shell assignments are separated with an empty line, so that the output can still be evaluated,
+    /// and single lines and templates are printed one after another.
+    pub fn separator(&self) -> &'static str {
+        match self {
+            Self::Env { .. } => "\n",
+            Self::FlowState { .. } | Self::Template(_) | Self::Name | Self::Ref => "",
+            _ => "---\n",
+        }
+    }
+}
+
+/// Renders the matching resource states in the format, separated with [`OutputFormat::separator`].
+pub fn render_states(format: &OutputFormat, states: &[Value]) -> Result<String> {
+    let mut rendered = String::new();
+    for (i, state) in states.iter().enumerate() {
+        if i > 0 {
+            rendered.push_str(format.separator());
+        }
+
+        match format {
+            OutputFormat::State {
+                keep_managed_fields,
+            } => {
+                let serialized =
+                    serde_yaml::to_string(&displayed_state(state, *keep_managed_fields))
+                        .context("failed to serialize matching resource state")?;
+                rendered.push_str(&serialized);
+            }
+            OutputFormat::FlowState {
+                keep_managed_fields,
+            } => {
+                let serialized =
+                    serde_json::to_string(&displayed_state(state, *keep_managed_fields))
+                        .context("failed to serialize matching resource state")?;
+                rendered.push_str(&serialized);
+                rendered.push('\n');
+            }
+            OutputFormat::Name => rendered.push_str(&format!("{}\n", resource_name(state)?)),
+            OutputFormat::Ref => {
+                let name = resource_name(state)?;
+                match state["metadata"]["namespace"].as_str() {
+                    Some(namespace) => rendered.push_str(&format!("{namespace}/{name}\n")),
+                    None => rendered.push_str(&format!("{name}\n")),
+                }
+            }
+            OutputFormat::Fields(fields) => rendered.push_str(&render_fields(fields, state)),
+            OutputFormat::Labels => rendered.push_str(&render_labels(state)),
+            OutputFormat::Env {
+                fields,
+                skip_missing,
+            } => rendered.push_str(&render_env(fields, *skip_missing, state)),
+            OutputFormat::Template(template) => rendered.push_str(&template.render(state)?),
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Returns the state without `metadata.managedFields`, unless `keep_managed_fields`.
+/// The field tracks which client set which field, and only clutters the printed state.
+fn displayed_state(state: &Value, keep_managed_fields: bool) -> Cow<'_, Value> {
+    if keep_managed_fields || state["metadata"].get("managedFields").is_none() {
+        return Cow::Borrowed(state);
+    }
+
+    let mut state = state.clone();
+    if let Some(metadata) = state.get_mut("metadata").and_then(Value::as_mapping_mut) {
+        metadata.remove("managedFields");
+    }
+    Cow::Owned(state)
+}
+
+/// Returns the reference to the resource as printed by `kubectl get -o name`, e.g. `deployment.apps/web`.
+/// The kind is lowercase, and followed by the group unless the resource is in the core group, e.g. `pod/web-0`.
+fn resource_name(state: &Value) -> Result<String> {
+    let (Some(api_version), Some(kind), Some(name)) = (
+        state["apiVersion"].as_str(),
+        state["kind"].as_str(),
+        state["metadata"]["name"].as_str(),
+    ) else {
+        anyhow::bail!("matching resource state is missing the apiVersion, kind or name");
+    };
+
+    let kind = kind.to_lowercase();
+    Ok(match api_version.split_once('/') {
+        Some((group, _)) => format!("{kind}.{group}/{name}"),
+        None => format!("{kind}/{name}"),
+    })
+}
+
+/// Renders the fields as `<name>\t<value>` lines.
+/// Missing values are rendered as empty strings.
+pub fn render_fields(fields: &[OutputField], state: &Value) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let value = field.path.get(state).map(render_value).unwrap_or_default();
+            format!("{}\t{value}\n", field.name)
+        })
+        .collect()
+}
+
+/// Renders the labels as `<key>=<value>` lines, sorted by key.
+/// A resource without labels is rendered as an empty string.
+fn render_labels(state: &Value) -> String {
+    let Some(labels) = state["metadata"]["labels"].as_mapping() else {
+        return String::new();
+    };
+
+    let mut lines = labels
+        .iter()
+        .map(|(key, value)| (render_value(key), render_value(value)))
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    lines
+        .into_iter()
+        .map(|(key, value)| format!("{key}={value}\n"))
+        .collect()
+}
+
+/// Renders the fields as `<name>=<value>` lines, with values quoted for POSIX shells.
+fn render_env(fields: &[OutputField], skip_missing: bool, state: &Value) -> String {
+    fields
+        .iter()
+        .filter_map(|field| match field.path.get(state) {
+            Some(value) => Some(format!(
+                "{}={}\n",
+                field.name,
+                shell_quote(&render_value(value))
+            )),
+            None if skip_missing => None,
+            None => Some(format!("{}=''\n", field.name)),
+        })
+        .collect()
+}
+
+/// Quotes the string in single quotes, so that the shell takes it literally, e.g. `it's` as `'it'\''s'`.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Renders scalars as plain text, without quotes, and collections as compact JSON.
+pub fn render_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Tagged(tagged) => render_value(&tagged.value),
+        collection => serde_json::to_string(collection).expect("serialization should not fail"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Value {
+        serde_yaml::from_str(
+            "{ metadata: { name: web-0 }, spec: { containers: [{ name: app }] }, status: { podIP: 10.0.0.1, ready: true } }",
+        )
+        .unwrap()
+    }
+
+    fn fields(fields: &[&str]) -> Vec<OutputField> {
+        fields.iter().map(|field| field.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn fields_are_rendered_as_lines() {
+        let fields = fields(&[
+            "name=.metadata.name",
+            "ip=.status.podIP",
+            "ready=.status.ready",
+            "containers=.spec.containers",
+        ]);
+        assert_eq!(
+            render_fields(&fields, &state()),
+            "name\tweb-0\nip\t10.0.0.1\nready\ttrue\ncontainers\t[{\"name\":\"app\"}]\n"
+        );
+    }
+
+    #[test]
+    fn missing_fields_are_rendered_empty() {
+        let fields = fields(&["ip=.status.podIP", "host=.status.hostIP"]);
+        assert_eq!(render_fields(&fields, &state()), "ip\t10.0.0.1\nhost\t\n");
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        assert!("name".parse::<OutputField>().is_err());
+        assert!("name=..metadata".parse::<OutputField>().is_err());
+    }
+
+    #[test]
+    fn shell_quoting_keeps_values_literal() {
+        assert_eq!(shell_quote("web"), "'web'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a\nb"), "'a\nb'");
+        assert_eq!(
+            shell_quote("$(reboot) `id` \"x\""),
+            "'$(reboot) `id` \"x\"'"
+        );
+
+        // The shell reads the quoted values back unchanged.
+        for value in ["it's", "", "a\nb", "$HOME \\ 'a' \"b\""] {
+            let output = std::process::Command::new("sh")
+                .args([
+                    "-c",
+                    &format!("VALUE={}; printf %s \"$VALUE\"", shell_quote(value)),
+                ])
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn env_assignments_are_rendered() {
+        let fields = fields(&[
+            "NAME=.metadata.name",
+            "IP=.status.podIP",
+            "HOST=.status.hostIP",
+        ]);
+        assert_eq!(
+            render_env(&fields, false, &state()),
+            "NAME='web-0'\nIP='10.0.0.1'\nHOST=''\n"
+        );
+        assert_eq!(
+            render_env(&fields, true, &state()),
+            "NAME='web-0'\nIP='10.0.0.1'\n"
+        );
+    }
+
+    fn template(name: &str, source: &str) -> OutputTemplate {
+        OutputTemplate {
+            name: name.into(),
+            source: source.into(),
+        }
+    }
+
+    #[test]
+    fn templates_are_rendered_with_the_state() {
+        let template = template(
+            "pod.txt",
+            "{{ metadata.name }} at {{ status.podIP }}{% if status.hostIP %} on {{ status.hostIP }}{% endif %}\n",
+        );
+        assert_eq!(template.render(&state()).unwrap(), "web-0 at 10.0.0.1\n");
+    }
+
+    #[test]
+    fn missing_values_fail_the_template() {
+        let template = template("pod.txt", "{{ status.hostIP }}");
+        let error = template.render(&state()).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("failed to render output template pod.txt"),
+            "{error:#}"
+        );
+        assert!(
+            format!("{error:#}").contains("undefined value"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn html_templates_escape_values() {
+        let state = serde_yaml::from_str("{ metadata: { name: \"<b>&web\" } }").unwrap();
+        let source = "<p>{{ metadata.name }}</p>";
+        assert_eq!(
+            template("pod.html", source).render(&state).unwrap(),
+            "<p>&lt;b&gt;&amp;web</p>"
+        );
+        assert_eq!(
+            template("pod.txt", source).render(&state).unwrap(),
+            "<p><b>&web</p>"
+        );
+    }
+
+    #[test]
+    fn names_and_refs_are_rendered_as_kubectl_prints_them() {
+        let states = [
+            "{ apiVersion: v1, kind: Pod, metadata: { name: web-0, namespace: default } }",
+            "{ apiVersion: apps/v1, kind: Deployment, metadata: { name: web, namespace: prod } }",
+            "{ apiVersion: rbac.authorization.k8s.io/v1, kind: ClusterRole, metadata: { name: view } }",
+        ]
+        .map(|state| serde_yaml::from_str(state).unwrap());
+
+        assert_eq!(
+            render_states(&OutputFormat::Name, &states).unwrap(),
+            "pod/web-0\ndeployment.apps/web\nclusterrole.rbac.authorization.k8s.io/view\n"
+        );
+        assert_eq!(
+            render_states(&OutputFormat::Ref, &states).unwrap(),
+            "default/pod/web-0\nprod/deployment.apps/web\nclusterrole.rbac.authorization.k8s.io/view\n"
+        );
+
+        let error = resource_name(&state()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "matching resource state is missing the apiVersion, kind or name"
+        );
+    }
+
+    #[test]
+    fn flow_states_are_single_json_lines() {
+        let mut state = state();
+        state["metadata"]["labels"] = serde_yaml::from_str("{ app: \"multi\\nline\" }").unwrap();
+        let format = OutputFormat::FlowState {
+            keep_managed_fields: false,
+        };
+
+        let rendered = render_states(&format, &[state.clone(), state.clone()]).unwrap();
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{rendered}");
+        for line in lines {
+            let parsed: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(serde_yaml::to_value(parsed).unwrap(), state);
+        }
+    }
+
+    #[test]
+    fn labels_are_rendered_sorted_by_key() {
+        let labeled = serde_yaml::from_str(
+            "{ metadata: { labels: { tier: frontend, app: web, app.kubernetes.io/version: 1.2 } } }",
+        )
+        .unwrap();
+        assert_eq!(
+            render_labels(&labeled),
+            "app=web\napp.kubernetes.io/version=1.2\ntier=frontend\n"
+        );
+
+        assert_eq!(render_labels(&state()), "");
+        assert_eq!(
+            render_states(&OutputFormat::Labels, &[state(), state()]).unwrap(),
+            "---\n"
+        );
+    }
+}
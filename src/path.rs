@@ -0,0 +1,249 @@
+use std::{fmt, str::FromStr};
+
+use serde_yaml::Value;
+
+/// Path to a value inside a resource, e.g. `.status.loadBalancer.ingress[0].ip`.
+///
+/// Consists of `.key` segments selecting mapping keys and `[index]` segments selecting sequence elements.
+/// The leading dot is optional.
+///
+/// Keys containing `.` or `[`, e.g. label keys, can be quoted in brackets instead:
+/// `.metadata.labels['app.kubernetes.io/name']` or `.metadata.labels["app.kubernetes.io/name"]`.
+/// Inside the quotes, a backslash escapes the quote character or another backslash.
+#[derive(Clone, Debug, Default)]
+pub struct ValuePath(Vec<Segment>);
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl ValuePath {
+    /// Returns the value at this path, if present.
+    pub fn get<'a>(&self, mut value: &'a Value) -> Option<&'a Value> {
+        for segment in &self.0 {
+            value = match segment {
+                Segment::Key(key) => value.as_mapping()?.get(key.as_str())?,
+                Segment::Index(index) => value.as_sequence()?.get(*index)?,
+            };
+        }
+
+        Some(value)
+    }
+
+    /// Returns this path extended with a `.key` segment.
+    pub fn with_key(&self, key: &str) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(Segment::Key(key.into()));
+        Self(segments)
+    }
+
+    /// Returns this path extended with an `[index]` segment.
+    pub fn with_index(&self, index: usize) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(Segment::Index(index));
+        Self(segments)
+    }
+
+    /// Returns the keys selected by this path, or [`None`] if it selects sequence elements.
+    pub fn keys(&self) -> Option<Vec<&str>> {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Key(key) => Some(key.as_str()),
+                Segment::Index(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// Displays the path as it is parsed, e.g. `.metadata.labels["app.kubernetes.io/name"]`, or `.` for the whole value.
+impl fmt::Display for ValuePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str(".");
+        }
+
+        for segment in &self.0 {
+            match segment {
+                Segment::Key(key) if key.contains(['.', '[']) => {
+                    write!(f, "[\"{}\"]", key.replace('\\', r"\\").replace('"', "\\\""))?
+                }
+                Segment::Key(key) => write!(f, ".{key}")?,
+                Segment::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for ValuePath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut rest = s.strip_prefix('.').unwrap_or(s);
+
+        if rest.is_empty() {
+            return Ok(Self(segments));
+        }
+
+        loop {
+            if let Some(after_bracket) = rest.strip_prefix('[') {
+                if let Some(quote) = after_bracket
+                    .chars()
+                    .next()
+                    .filter(|c| matches!(c, '\'' | '"'))
+                {
+                    let (key, after) = parse_quoted(&after_bracket[1..], quote)
+                        .ok_or_else(|| format!("unclosed quote in path `{s}`"))?;
+                    segments.push(Segment::Key(key));
+                    rest = after
+                        .strip_prefix(']')
+                        .ok_or_else(|| format!("expected `]` after quoted key in path `{s}`"))?;
+                } else {
+                    let (index, after) = after_bracket
+                        .split_once(']')
+                        .ok_or_else(|| format!("unclosed `[` in path `{s}`"))?;
+                    let index = index
+                        .parse()
+                        .map_err(|_| format!("invalid index `{index}` in path `{s}`"))?;
+                    segments.push(Segment::Index(index));
+                    rest = after;
+                }
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                let key = &rest[..end];
+                if key.is_empty() {
+                    return Err(format!("empty key in path `{s}`"));
+                }
+                segments.push(Segment::Key(key.into()));
+                rest = &rest[end..];
+            }
+
+            if rest.is_empty() {
+                break;
+            }
+
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                rest = after_dot;
+            } else if !rest.starts_with('[') {
+                return Err(format!("unexpected `{rest}` in path `{s}`"));
+            }
+        }
+
+        Ok(Self(segments))
+    }
+}
+
+/// Parses a key up to the closing `quote`, returns the unescaped key and the rest of the input.
+fn parse_quoted(s: &str, quote: char) -> Option<(String, &str)> {
+    let mut key = String::new();
+    let mut chars = s.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => key.push(chars.next()?.1),
+            c if c == quote => return Some((key, &s[i + c.len_utf8()..])),
+            c => key.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> ValuePath {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn paths_select_nested_values() {
+        let state: Value =
+            serde_yaml::from_str("{ status: { loadBalancer: { ingress: [{ ip: 10.0.0.1 }] } } }")
+                .unwrap();
+        assert_eq!(
+            path(".status.loadBalancer.ingress[0].ip").get(&state),
+            Some(&Value::from("10.0.0.1"))
+        );
+        assert_eq!(
+            path("status.loadBalancer.ingress[0].ip").get(&state),
+            Some(&Value::from("10.0.0.1"))
+        );
+        assert!(path(".status.loadBalancer.ingress[1].ip")
+            .get(&state)
+            .is_none());
+        assert!(path(".status[0]").get(&state).is_none());
+        assert_eq!(path(".").get(&state), Some(&state));
+    }
+
+    #[test]
+    fn invalid_paths_are_rejected() {
+        for s in ["..status", ".status.", "[x]", "[0", ".status[-1]"] {
+            assert!(s.parse::<ValuePath>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn quoted_keys_can_contain_dots() {
+        let state: Value =
+            serde_yaml::from_str("{ metadata: { labels: { app.kubernetes.io/name: web } } }")
+                .unwrap();
+        for s in [
+            "metadata.labels['app.kubernetes.io/name']",
+            ".metadata.labels[\"app.kubernetes.io/name\"]",
+            "['metadata']['labels']['app.kubernetes.io/name']",
+        ] {
+            assert_eq!(path(s).get(&state), Some(&Value::from("web")), "{s}");
+        }
+    }
+
+    #[test]
+    fn escaped_quotes_in_keys() {
+        let state: Value = serde_yaml::from_str(r#"{ "it's": { 'a\b': 1 } }"#).unwrap();
+        assert_eq!(
+            path(r"['it\'s']['a\\b']").get(&state),
+            Some(&Value::from(1))
+        );
+        assert_eq!(
+            path(r#"["it's"]["a\\b"]"#).get(&state),
+            Some(&Value::from(1))
+        );
+    }
+
+    #[test]
+    fn invalid_quoted_keys_are_rejected() {
+        for s in ["['key", "['key'x", "['key\"]", "[key]"] {
+            assert!(s.parse::<ValuePath>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn keys_are_none_with_indices() {
+        assert_eq!(path(".status.phase").keys(), Some(vec!["status", "phase"]));
+        assert_eq!(
+            path(".metadata.labels['app.kubernetes.io/name']").keys(),
+            Some(vec!["metadata", "labels", "app.kubernetes.io/name"])
+        );
+        assert_eq!(path(".").keys(), Some(vec![]));
+        assert_eq!(path(".status.conditions[0]").keys(), None);
+    }
+
+    #[test]
+    fn paths_are_displayed_as_parsed() {
+        for s in [
+            ".",
+            ".status.conditions[0].type",
+            ".metadata.labels[\"app.kubernetes.io/name\"]",
+            ".data[\"say.\\\"hi\\\"\"]",
+        ] {
+            assert_eq!(path(s).to_string(), s);
+        }
+        assert_eq!(path("spec['replicas']").to_string(), ".spec.replicas");
+    }
+}
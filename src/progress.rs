@@ -0,0 +1,48 @@
+use std::{
+    io::{self, IsTerminal},
+    time::Duration,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Creates a spinner displaying elapsed time and the last received event.
+///
+/// The spinner is drawn on standard error. It's hidden when `quiet` is set or when standard error
+/// is not a terminal.
+pub fn spinner(quiet: bool) -> ProgressBar {
+    if !is_visible(quiet, io::stderr().is_terminal()) {
+        return ProgressBar::hidden();
+    }
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::with_template("{spinner} [{elapsed_precise}] {msg}")
+            .expect("template should be valid"),
+    );
+    spinner.set_message("Waiting for the first event");
+    spinner.enable_steady_tick(Duration::from_millis(100));
+
+    spinner
+}
+
+fn is_visible(quiet: bool, terminal: bool) -> bool {
+    !quiet && terminal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spinner_is_hidden_when_quiet() {
+        assert!(spinner(true).is_hidden());
+    }
+
+    #[test]
+    fn spinner_is_shown_only_on_a_terminal() {
+        assert!(is_visible(false, true));
+        assert!(!is_visible(false, false));
+        assert!(!is_visible(true, true));
+        assert!(!is_visible(true, false));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fmt, str::FromStr};
+
+use serde_yaml::Value;
+
+/// Kubernetes quantity, e.g. `10Gi`, `500m` or `1.5e3`, stored exactly in nano-units.
+///
+/// Values with more precision than nano-units are rounded up, as in Kubernetes.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Quantity(i128);
+
+impl Quantity {
+    /// Parses a string or a number as a quantity.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(s) => s.parse().ok(),
+            Value::Number(n) => n.to_string().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Displays the value as a plain decimal number, e.g. `5Gi` as `5368709120` and `500m` as `0.5`.
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NANOS_PER_UNIT: u128 = 1_000_000_000;
+
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let nanos = self.0.unsigned_abs();
+        let (integer, fraction) = (nanos / NANOS_PER_UNIT, nanos % NANOS_PER_UNIT);
+        if fraction == 0 {
+            write!(f, "{sign}{integer}")
+        } else {
+            let fraction = format!("{fraction:09}");
+            write!(f, "{sign}{integer}.{}", fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+
+        let number_end = unsigned
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(unsigned.len());
+        let (number, suffix) = unsigned.split_at(number_end);
+        let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(());
+        }
+
+        let (decimal_exponent, binary_exponent) = parse_suffix(suffix).ok_or(())?;
+
+        let mantissa: i128 = format!("{integer}{fraction}").parse().map_err(|_| ())?;
+        let fraction_digits = i32::try_from(fraction.len()).map_err(|_| ())?;
+        // Nano-units, so the value is scaled by 10^9.
+        let exponent = decimal_exponent
+            .checked_add(9)
+            .and_then(|exponent| exponent.checked_sub(fraction_digits))
+            .ok_or(())?;
+
+        let scaled = mantissa.checked_mul(1024_i128.checked_pow(binary_exponent).ok_or(())?);
+        let nanos = if exponent >= 0 {
+            scaled.and_then(|value| value.checked_mul(10_i128.checked_pow(exponent as u32)?))
+        } else {
+            let divisor = 10_i128.checked_pow(exponent.unsigned_abs());
+            match (scaled, divisor) {
+                (Some(value), Some(divisor)) => {
+                    Some(value / divisor + i128::from(value % divisor != 0))
+                }
+                // Too small to be represented in nano-units, rounded up to the smallest one.
+                (Some(value), None) => Some(value.signum()),
+                (None, _) => None,
+            }
+        }
+        .ok_or(())?;
+
+        Ok(Self(if negative { -nanos } else { nanos }))
+    }
+}
+
+/// Parses the suffix of a quantity into decimal and binary (power of 1024) exponents.
+fn parse_suffix(suffix: &str) -> Option<(i32, u32)> {
+    let exponents = match suffix {
+        "" => (0, 0),
+        "n" => (-9, 0),
+        "u" => (-6, 0),
+        "m" => (-3, 0),
+        "k" => (3, 0),
+        "M" => (6, 0),
+        "G" => (9, 0),
+        "T" => (12, 0),
+        "P" => (15, 0),
+        "E" => (18, 0),
+        "Ki" => (0, 1),
+        "Mi" => (0, 2),
+        "Gi" => (0, 3),
+        "Ti" => (0, 4),
+        "Pi" => (0, 5),
+        "Ei" => (0, 6),
+        _ => {
+            let exponent = suffix.strip_prefix(['e', 'E'])?;
+            let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            (exponent.parse().ok()?, 0)
+        }
+    };
+
+    Some(exponents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quantity(s: &str) -> Quantity {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn suffixes_scale_the_value() {
+        assert_eq!(quantity("500m").to_string(), "0.5");
+        assert_eq!(quantity("1k").to_string(), "1000");
+        assert_eq!(quantity("5Gi").to_string(), "5368709120");
+        assert_eq!(quantity("1.5e3").to_string(), "1500");
+        assert_eq!(quantity("2E-3").to_string(), "0.002");
+        assert_eq!(quantity("-1.5Ki").to_string(), "-1536");
+        assert_eq!(quantity("+.5").to_string(), "0.5");
+    }
+
+    #[test]
+    fn equal_quantities_with_different_suffixes() {
+        assert!(quantity("1Gi") == quantity("1024Mi"));
+        assert!(quantity("1") == quantity("1000m"));
+        assert!(quantity("1G") < quantity("1Gi"));
+        assert!(quantity("-1") < quantity("1n"));
+    }
+
+    #[test]
+    fn precision_below_nano_units_is_rounded_up() {
+        assert_eq!(quantity("1.0000000001").to_string(), "1.000000001");
+        assert_eq!(quantity("1e-30").to_string(), "0.000000001");
+    }
+
+    #[test]
+    fn numbers_are_quantities() {
+        let value = |yaml: &str| serde_yaml::from_str::<Value>(yaml).unwrap();
+        assert!(Quantity::from_value(&value("1.5")) == Some(quantity("1500m")));
+        assert!(Quantity::from_value(&value("2Gi")) == Some(quantity("2Gi")));
+        assert!(Quantity::from_value(&value("true")).is_none());
+    }
+
+    #[test]
+    fn invalid_quantities_are_rejected() {
+        for s in [
+            "",
+            "m",
+            "1x",
+            "1Gib",
+            "1e",
+            "1e+",
+            "1.2.3",
+            "--1",
+            "1e99999999999",
+        ] {
+            assert!(s.parse::<Quantity>().is_err(), "{s}");
+        }
+    }
+}
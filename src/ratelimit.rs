@@ -0,0 +1,141 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::time::{self, Instant, Sleep};
+use tower::{Layer, Service};
+
+/// Token bucket rate limiter for requests sent to the API server.
+///
+/// The bucket holds at most `burst` tokens and is refilled with `qps` tokens per second.
+/// Every request takes one token, requests wait until a token is available.
+pub struct RateLimitLayer {
+    qps: f64,
+    burst: u32,
+}
+
+impl RateLimitLayer {
+    pub fn new(qps: f64, burst: u32) -> Self {
+        Self {
+            qps,
+            burst: burst.max(1),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            qps: self.qps,
+            burst: self.burst.into(),
+            tokens: self.burst.into(),
+            refilled_at: Instant::now(),
+            sleep: None,
+        }
+    }
+}
+
+pub struct RateLimit<S> {
+    inner: S,
+    qps: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    /// Sleep until the next token is available.
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> RateLimit<S> {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.qps).min(self.burst);
+        self.refilled_at = now;
+    }
+}
+
+impl<S, R> Service<R> for RateLimit<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            if let Some(sleep) = self.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+
+            self.refill();
+            if self.tokens >= 1.0 {
+                break;
+            }
+
+            let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.qps);
+            self.sleep = Some(Box::pin(time::sleep(wait)));
+        }
+
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.tokens -= 1.0;
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    /// Sends the requests one after another and returns how long after the start each was sent.
+    async fn send(layer: RateLimitLayer, requests: usize) -> Vec<Duration> {
+        let started = Instant::now();
+        let mut service = layer.layer(service_fn(|()| async move {
+            Ok::<_, Infallible>(started.elapsed())
+        }));
+
+        let mut sent_after = Vec::new();
+        for _ in 0..requests {
+            sent_after.push(service.ready().await.unwrap().call(()).await.unwrap());
+        }
+        sent_after
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_beyond_burst_are_delayed() {
+        let sent_after = send(RateLimitLayer::new(2.0, 3), 5).await;
+        assert_eq!(sent_after, [0, 0, 0, 500, 1000].map(Duration::from_millis));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_are_refilled_up_to_burst() {
+        let started = Instant::now();
+        let mut service = RateLimitLayer::new(1.0, 2).layer(service_fn(|()| async move {
+            Ok::<_, Infallible>(started.elapsed())
+        }));
+        for _ in 0..2 {
+            service.ready().await.unwrap().call(()).await.unwrap();
+        }
+
+        time::advance(Duration::from_secs(10)).await;
+        let mut sent_after = Vec::new();
+        for _ in 0..3 {
+            sent_after.push(service.ready().await.unwrap().call(()).await.unwrap());
+        }
+        assert_eq!(sent_after, [10, 10, 11].map(Duration::from_secs));
+    }
+}
@@ -0,0 +1,575 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use kube::{
+    api::{ApiResource, DynamicObject, GroupVersionKind},
+    core::Version,
+    discovery::{ApiCapabilities, ApiGroup, Scope},
+    Api, Client, Discovery,
+};
+
+use crate::error::WaitError;
+
+/// Criteria for selecting the watched resource among the resources discovered in the cluster.
+pub struct ResourceFilter {
+    pub kind: String,
+    /// Resource group, empty for the core group.
+    pub group: Option<String>,
+    pub version: Option<String>,
+    pub plural: Option<String>,
+    /// When the version is not constrained, pick the most stable version instead of the preferred one.
+    pub prefer_stable: bool,
+    /// Scope of the resource, which together with the group, the version and the plural
+    /// describes the resource completely, see [`Self::explicit`].
+    pub scope: Option<Scope>,
+    /// Groups and versions tried in order before the regular selection, see [`Self::prefer`].
+    preferred: Vec<(String, String)>,
+}
+
+impl ResourceFilter {
+    /// Creates a filter from the given constraints.
+    /// `api_version` is split into group and version, which must agree with `group` and `version` if given.
+    pub fn new(
+        kind: String,
+        group: Option<String>,
+        version: Option<String>,
+        api_version: Option<&str>,
+        plural: Option<String>,
+    ) -> Result<Self, String> {
+        let mut filter = Self {
+            kind,
+            group,
+            version,
+            plural,
+            prefer_stable: false,
+            scope: None,
+            preferred: Vec::new(),
+        };
+
+        if let Some(api_version) = api_version {
+            let (group, version) = split_api_version(api_version)?;
+            filter.group = Some(merge(filter.group, group, "group")?);
+            filter.version = Some(merge(filter.version, version, "group version")?);
+        }
+
+        Ok(filter)
+    }
+
+    /// Sets apiVersions, e.g. `apps/v1`, tried in order before the regular selection.
+    /// The first apiVersion serving a matching resource wins.
+    pub fn prefer(&mut self, api_versions: &[String]) -> Result<(), String> {
+        self.preferred = api_versions
+            .iter()
+            .map(|api_version| split_api_version(api_version))
+            .collect::<Result<_, _>>()?;
+
+        Ok(())
+    }
+
+    fn matches(&self, api_resource: &ApiResource) -> bool {
+        self.kind == api_resource.kind
+            && self
+                .group
+                .as_ref()
+                .is_none_or(|group| *group == api_resource.group)
+            && self
+                .version
+                .as_ref()
+                .is_none_or(|version| *version == api_resource.version)
+            && self
+                .plural
+                .as_ref()
+                .is_none_or(|plural| *plural == api_resource.plural)
+    }
+
+    /// Returns all discovered resources matching this filter.
+    ///
+    /// Preferred apiVersions are tried first, see [`Self::prefer`].
+    /// When the version is not constrained, only the preferred version of every group is considered.
+    /// With [`Self::prefer_stable`], the most stable version of every group is considered instead,
+    /// and only the most stable of the resources found in different groups are returned.
+    pub fn find(&self, discovery: &Discovery) -> Vec<(ApiResource, ApiCapabilities)> {
+        for (group, version) in &self.preferred {
+            let found = discovery
+                .get(group)
+                .map(|group| group.versioned_resources(version))
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(api_resource, _)| self.matches(api_resource))
+                .collect::<Vec<_>>();
+
+            if !found.is_empty() {
+                return found;
+            }
+        }
+
+        let mut found = discovery
+            .groups()
+            .filter(|group| self.group.as_ref().is_none_or(|name| name == group.name()))
+            .flat_map(|group| self.candidates(group))
+            .filter(|(api_resource, _)| self.matches(api_resource))
+            .collect::<Vec<_>>();
+
+        if self.prefer_stable {
+            let priority =
+                |api_resource: &ApiResource| Version::parse(&api_resource.version).priority();
+            if let Some(max) = found
+                .iter()
+                .map(|(api_resource, _)| priority(api_resource))
+                .max()
+            {
+                found.retain(|(api_resource, _)| priority(api_resource) == max);
+            }
+        }
+
+        found
+    }
+
+    /// Returns the resource described completely by this filter, with the group, the version, the plural
+    /// and the scope, so that it can be used without discovery.
+    pub fn explicit(&self) -> Option<(ApiResource, ApiCapabilities)> {
+        let (Some(group), Some(version), Some(plural), Some(scope)) =
+            (&self.group, &self.version, &self.plural, &self.scope)
+        else {
+            return None;
+        };
+
+        let gvk = GroupVersionKind::gvk(group, version, &self.kind);
+        let api_capabilities = ApiCapabilities {
+            scope: scope.clone(),
+            subresources: Vec::new(),
+            operations: Vec::new(),
+        };
+
+        Some((
+            ApiResource::from_gvk_with_plural(&gvk, plural),
+            api_capabilities,
+        ))
+    }
+
+    /// Returns the resource described completely by this filter, see [`Self::explicit`],
+    /// or the single discovered resource matching this filter.
+    /// The discovery is required unless the resource is described completely.
+    pub fn select(&self, discovery: Option<&Discovery>) -> Result<(ApiResource, ApiCapabilities)> {
+        match (self.explicit(), discovery) {
+            (Some(explicit), _) => Ok(explicit),
+            (None, Some(discovery)) => self.resolve(discovery),
+            (None, None) => {
+                unreachable!("discovery is required for resources that are not explicit")
+            }
+        }
+    }
+
+    /// Returns the single discovered resource matching this filter.
+    pub fn resolve(&self, discovery: &Discovery) -> Result<(ApiResource, ApiCapabilities)> {
+        let found = self.find(discovery);
+
+        anyhow::ensure!(!found.is_empty(), WaitError::ApiResourceNotFound);
+        anyhow::ensure!(
+            found.len() == 1,
+            WaitError::AmbiguousApiResource(self.available_api_versions(discovery))
+        );
+
+        Ok(found.into_iter().next().expect("length was just checked"))
+    }
+
+    /// Returns apiVersions of all discovered resources matching this filter, whatever their version.
+    pub fn available_api_versions(&self, discovery: &Discovery) -> Vec<String> {
+        let any_version = Self {
+            kind: self.kind.clone(),
+            group: self.group.clone(),
+            version: None,
+            plural: self.plural.clone(),
+            prefer_stable: false,
+            scope: None,
+            preferred: Vec::new(),
+        };
+
+        discovery
+            .groups()
+            .flat_map(|group| {
+                group
+                    .versions()
+                    .flat_map(|version| group.versioned_resources(version))
+            })
+            .filter(|(api_resource, _)| any_version.matches(api_resource))
+            .map(|(api_resource, _)| api_resource.api_version)
+            .collect()
+    }
+
+    fn candidates(&self, group: &ApiGroup) -> Vec<(ApiResource, ApiCapabilities)> {
+        match &self.version {
+            Some(version) => group.versioned_resources(version),
+            None if self.prefer_stable => group.resources_by_stability(),
+            None => group.recommended_resources(),
+        }
+    }
+}
+
+/// Path of a subresource relative to the resource, e.g. `status` or `scale`, or a custom one, e.g. `metrics/latest`.
+/// Consists of segments of letters, digits, `.`, `_` and `-`, separated with slashes,
+/// since it is appended to the URL of the resource as it is.
+#[derive(Clone)]
+pub struct SubresourcePath(String);
+
+impl SubresourcePath {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for SubresourcePath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = s.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        });
+        if !valid {
+            return Err(format!(
+                "invalid subresource path `{s}`, expected segments of letters, digits, `.`, `_` and `-` separated with `/`"
+            ));
+        }
+
+        Ok(Self(s.into()))
+    }
+}
+
+/// Namespace in which namespaced resources are accessed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Namespace<'a> {
+    /// Default namespace of the client.
+    Default,
+    Named(&'a str),
+    /// All namespaces.
+    All,
+}
+
+impl<'a> Namespace<'a> {
+    /// Parses a namespace name, `all` stands for all namespaces.
+    pub fn parse(namespace: &'a str) -> Self {
+        match namespace {
+            "all" => Self::All,
+            name => Self::Named(name),
+        }
+    }
+
+    /// Returns the name of this namespace, or [`None`] for all namespaces.
+    pub fn name(self, client: &'a Client) -> Option<&'a str> {
+        match self {
+            Self::Default => Some(client.default_namespace()),
+            Self::Named(name) => Some(name),
+            Self::All => None,
+        }
+    }
+}
+
+/// Returns the API for the resource.
+/// Namespaced resources are accessed in the given namespace, cluster-wide resources ignore it.
+pub fn api(
+    client: &Client,
+    api_resource: &ApiResource,
+    api_capabilities: &ApiCapabilities,
+    namespace: Namespace,
+) -> Api<DynamicObject> {
+    match (&api_capabilities.scope, namespace.name(client)) {
+        (Scope::Namespaced, Some(name)) => Api::namespaced_with(client.clone(), name, api_resource),
+        _ => Api::all_with(client.clone(), api_resource),
+    }
+}
+
+/// Describes the selected resource as `<name>\t<value>` lines, like `--output-fields`, e.g. `plural\tdeployments`.
+/// The core group is empty.
+pub fn describe(api_resource: &ApiResource, api_capabilities: &ApiCapabilities) -> String {
+    let scope = match api_capabilities.scope {
+        Scope::Cluster => "Cluster",
+        Scope::Namespaced => "Namespaced",
+    };
+
+    format!(
+        "group\t{}\nversion\t{}\nkind\t{}\nplural\t{}\nscope\t{scope}\n",
+        api_resource.group, api_resource.version, api_resource.kind, api_resource.plural
+    )
+}
+
+/// Returns the apiVersion preferred by the API server for the kind of the resource,
+/// if it is more stable or more recent than the version of the resource.
+pub fn preferred_replacement(discovery: &Discovery, api_resource: &ApiResource) -> Option<String> {
+    let group = discovery.get(&api_resource.group)?;
+    let preferred = group.preferred_version_or_latest();
+    if Version::parse(preferred).priority() <= Version::parse(&api_resource.version).priority() {
+        return None;
+    }
+
+    group
+        .versioned_resources(preferred)
+        .into_iter()
+        .find(|(candidate, _)| candidate.kind == api_resource.kind)
+        .map(|(candidate, _)| candidate.api_version)
+}
+
+/// Splits an apiVersion into group and version, e.g. `apps/v1` into `apps` and `v1`.
+/// An apiVersion without a slash, e.g. `v1`, belongs to the core group, which has an empty name.
+fn split_api_version(api_version: &str) -> Result<(String, String), String> {
+    let (group, version) = api_version.split_once('/').unwrap_or(("", api_version));
+
+    if version.is_empty()
+        || version.contains('/')
+        || (api_version.contains('/') && group.is_empty())
+    {
+        return Err(format!(
+            "invalid apiVersion `{api_version}`, expected `<group>/<version>` or `<version>`"
+        ));
+    }
+
+    Ok((group.into(), version.into()))
+}
+
+/// Returns the value derived from the apiVersion, unless it contradicts the value given explicitly.
+fn merge(explicit: Option<String>, derived: String, what: &str) -> Result<String, String> {
+    match explicit {
+        Some(explicit) if explicit != derived => Err(format!(
+            "{what} `{explicit}` conflicts with {what} `{derived}` from the apiVersion"
+        )),
+        _ => Ok(derived),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeApi, Response};
+
+    fn filter(api_version: Option<&str>) -> Result<ResourceFilter, String> {
+        ResourceFilter::new("Deployment".into(), None, None, api_version, None)
+    }
+
+    #[test]
+    fn api_versions_are_split_into_group_and_version() {
+        assert_eq!(
+            split_api_version("apps/v1"),
+            Ok(("apps".into(), "v1".into()))
+        );
+        assert_eq!(split_api_version("v1"), Ok(("".into(), "v1".into())));
+        for api_version in ["", "apps/", "/v1", "a/b/c"] {
+            assert!(split_api_version(api_version).is_err(), "{api_version}");
+        }
+    }
+
+    #[test]
+    fn api_version_must_agree_with_group_and_version() {
+        let filter = filter(Some("apps/v1")).unwrap();
+        assert_eq!(filter.group.as_deref(), Some("apps"));
+        assert_eq!(filter.version.as_deref(), Some("v1"));
+
+        let new = |group: Option<&str>, version: Option<&str>| {
+            ResourceFilter::new(
+                "Deployment".into(),
+                group.map(Into::into),
+                version.map(Into::into),
+                Some("apps/v1"),
+                None,
+            )
+        };
+        assert!(new(Some("apps"), Some("v1")).is_ok());
+        assert!(new(Some("batch"), None).is_err());
+        assert!(new(None, Some("v1beta1")).is_err());
+    }
+
+    async fn discover(resources: &'static [(&'static str, &'static str)]) -> Discovery {
+        let api = FakeApi::start(|request| {
+            testing::discovery(request, resources).unwrap_or_else(|| {
+                Response::Json(404, testing::status(404, "NotFound", "not found"))
+            })
+        })
+        .await;
+        Discovery::new(api.client()).run().await.unwrap()
+    }
+
+    /// Returns the sorted apiVersions, as discovered groups are not ordered.
+    fn api_versions(found: Vec<(ApiResource, ApiCapabilities)>) -> Vec<String> {
+        let mut api_versions = found
+            .into_iter()
+            .map(|(api_resource, _)| api_resource.api_version)
+            .collect::<Vec<_>>();
+        api_versions.sort();
+        api_versions
+    }
+
+    #[tokio::test]
+    async fn stable_versions_are_preferred() {
+        let discovery = discover(&[
+            ("a.example.com/v1beta1", "Widget"),
+            ("b.example.com/v1", "Widget"),
+            ("c.example.com/v2alpha1", "Widget"),
+            ("d.example.com/v1beta1", "Widget"),
+            ("d.example.com/v1", "Widget"),
+        ])
+        .await;
+
+        let mut filter = ResourceFilter::new("Widget".into(), None, None, None, None).unwrap();
+        assert_eq!(
+            api_versions(filter.find(&discovery)),
+            [
+                "a.example.com/v1beta1",
+                "b.example.com/v1",
+                "c.example.com/v2alpha1",
+                "d.example.com/v1beta1",
+            ]
+        );
+
+        filter.prefer_stable = true;
+        assert_eq!(
+            api_versions(filter.find(&discovery)),
+            ["b.example.com/v1", "d.example.com/v1"]
+        );
+
+        filter.group = Some("a.example.com".into());
+        assert_eq!(
+            api_versions(filter.find(&discovery)),
+            ["a.example.com/v1beta1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn ambiguous_resources_list_the_available_versions() {
+        let discovery = discover(&[
+            ("a.example.com/v1", "Widget"),
+            ("a.example.com/v1beta1", "Widget"),
+            ("b.example.com/v1", "Widget"),
+        ])
+        .await;
+
+        let filter = ResourceFilter::new("Widget".into(), None, None, None, None).unwrap();
+        let error = filter.resolve(&discovery).unwrap_err();
+        let Some(WaitError::AmbiguousApiResource(api_versions)) = error.downcast_ref() else {
+            panic!("unexpected error: {error:#}");
+        };
+        let mut api_versions = api_versions.clone();
+        api_versions.sort();
+        assert_eq!(
+            api_versions,
+            [
+                "a.example.com/v1",
+                "a.example.com/v1beta1",
+                "b.example.com/v1"
+            ]
+        );
+        assert!(error
+            .to_string()
+            .contains("e.g. with `--api-version` set to one of: "));
+
+        let filter = ResourceFilter::new("Gadget".into(), None, None, None, None).unwrap();
+        let error = filter.resolve(&discovery).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(WaitError::ApiResourceNotFound)
+        ));
+    }
+
+    #[test]
+    fn preferred_api_versions_are_validated() {
+        let mut filter = filter(None).unwrap();
+        assert!(filter.prefer(&["apps/v1".into(), "v1".into()]).is_ok());
+        assert_eq!(
+            filter.preferred,
+            [("apps".into(), "v1".into()), ("".into(), "v1".into())]
+        );
+        assert!(filter.prefer(&["apps/".into()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn preferred_api_versions_are_tried_in_order() {
+        let discovery = discover(&[
+            ("a.example.com/v1", "Widget"),
+            ("b.example.com/v1", "Widget"),
+            ("b.example.com/v1beta1", "Widget"),
+        ])
+        .await;
+        let prefer = |preferred: &[&str]| {
+            let mut filter = ResourceFilter::new("Widget".into(), None, None, None, None).unwrap();
+            let preferred = preferred.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            filter.prefer(&preferred).unwrap();
+            api_versions(filter.find(&discovery))
+        };
+
+        assert_eq!(
+            prefer(&["b.example.com/v1beta1", "a.example.com/v1"]),
+            ["b.example.com/v1beta1"]
+        );
+        assert_eq!(
+            prefer(&["c.example.com/v1", "a.example.com/v1", "b.example.com/v1"]),
+            ["a.example.com/v1"]
+        );
+        // None of the preferred versions is served, so the regular selection applies.
+        assert_eq!(
+            prefer(&["c.example.com/v1", "a.example.com/v2"]),
+            ["a.example.com/v1", "b.example.com/v1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn deprecated_versions_have_a_replacement() {
+        let discovery = discover(&[
+            ("a.example.com/v1", "Widget"),
+            ("a.example.com/v1beta1", "Widget"),
+            ("a.example.com/v1beta1", "Gadget"),
+            ("b.example.com/v1beta1", "Widget"),
+            ("b.example.com/v1", "Widget"),
+        ])
+        .await;
+        let replacement = |api_version: &str, kind: &str| {
+            let (group, version) = split_api_version(api_version).unwrap();
+            let gvk = GroupVersionKind::gvk(&group, &version, kind);
+            preferred_replacement(&discovery, &ApiResource::from_gvk(&gvk))
+        };
+
+        assert_eq!(
+            replacement("a.example.com/v1beta1", "Widget").as_deref(),
+            Some("a.example.com/v1")
+        );
+        assert_eq!(replacement("a.example.com/v1", "Widget"), None);
+        // The preferred version does not serve the kind.
+        assert_eq!(replacement("a.example.com/v1beta1", "Gadget"), None);
+        // The API server prefers the less stable version.
+        assert_eq!(replacement("b.example.com/v1", "Widget"), None);
+    }
+
+    #[test]
+    fn all_namespaces() {
+        assert!(Namespace::parse("all") == Namespace::All);
+        assert!(Namespace::parse("default") == Namespace::Named("default"));
+    }
+
+    #[test]
+    fn explicit_requires_every_field() {
+        let mut filter = filter(Some("apps/v1")).unwrap();
+        filter.plural = Some("deployments".into());
+        assert!(filter.explicit().is_none());
+
+        // Explicit resources are selected without discovery.
+        filter.scope = Some(Scope::Namespaced);
+        let (api_resource, api_capabilities) = filter.select(None).unwrap();
+        assert_eq!(api_resource.api_version, "apps/v1");
+        assert_eq!(api_resource.kind, "Deployment");
+        assert_eq!(api_resource.plural, "deployments");
+        assert_eq!(api_capabilities.scope, Scope::Namespaced);
+    }
+
+    #[test]
+    fn subresource_paths() {
+        for path in ["status", "scale", "metrics/latest", "v1.2_a-b"] {
+            assert_eq!(path.parse::<SubresourcePath>().unwrap().as_str(), path);
+        }
+        for path in [
+            "", "/status", "status/", "a//b", "..", "a/./b", "a?b", "a b",
+        ] {
+            assert!(path.parse::<SubresourcePath>().is_err(), "{path}");
+        }
+    }
+}
@@ -0,0 +1,231 @@
+/// Validates the syntax of a label selector, e.g. `env in (prod,stage),!deprecated`, and returns it unmodified.
+/// Selectors are passed to the API server as they are, this only rejects malformed ones early.
+pub fn parse_label_selector(selector: &str) -> Result<String, String> {
+    for requirement in split_requirements(selector)? {
+        validate_requirement(requirement.trim())
+            .map_err(|error| format!("invalid requirement `{}`: {error}", requirement.trim()))?;
+    }
+
+    Ok(selector.to_string())
+}
+
+/// Parses a label requirement in the `<key>=<value>` form, e.g. `app.kubernetes.io/name=web`.
+pub fn parse_label(label: &str) -> Result<(String, String), String> {
+    let (key, value) = label
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<key>=<value>`, got `{label}`"))?;
+    validate_key(key)?;
+    validate_value(value)?;
+
+    Ok((key.into(), value.into()))
+}
+
+/// Parses an annotation in the `<key>=<value>` form, e.g. `example.com/owner=team-a`.
+/// Unlike label values, annotation values are not restricted.
+pub fn parse_annotation(annotation: &str) -> Result<(String, String), String> {
+    let (key, value) = annotation
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<key>=<value>`, got `{annotation}`"))?;
+    validate_key(key)?;
+
+    Ok((key.into(), value.into()))
+}
+
+/// Splits the selector on commas that are not inside parentheses.
+fn split_requirements(selector: &str) -> Result<Vec<&str>, String> {
+    let mut requirements = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in selector.char_indices() {
+        match c {
+            '(' if depth == 0 => depth += 1,
+            '(' => return Err("nested parentheses are not allowed".into()),
+            ')' if depth == 1 => depth -= 1,
+            ')' => return Err("unmatched `)`".into()),
+            ',' if depth == 0 => {
+                requirements.push(&selector[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        return Err("unclosed `(`".into());
+    }
+    requirements.push(&selector[start..]);
+
+    Ok(requirements)
+}
+
+fn validate_requirement(requirement: &str) -> Result<(), String> {
+    if requirement.is_empty() {
+        return Err("empty requirement".into());
+    }
+
+    if let Some(key) = requirement.strip_prefix('!') {
+        return validate_key(key.trim());
+    }
+
+    if let Some((before, after)) = requirement.split_once('(') {
+        let mut words = before.split_whitespace();
+        let (Some(key), Some(operator), None) = (words.next(), words.next(), words.next()) else {
+            return Err("expected `<key> in (<values>)` or `<key> notin (<values>)`".into());
+        };
+        validate_key(key)?;
+        if operator != "in" && operator != "notin" {
+            return Err(format!("unknown operator `{operator}`"));
+        }
+
+        let values = after
+            .strip_suffix(')')
+            .ok_or("unexpected characters after `)`")?;
+        if values.trim().is_empty() {
+            return Err(format!("`{operator}` requires at least one value"));
+        }
+
+        return values
+            .split(',')
+            .try_for_each(|value| validate_value(value.trim()));
+    }
+
+    for operator in ["!=", "==", "=", ">", "<"] {
+        if let Some((key, value)) = requirement.split_once(operator) {
+            validate_key(key.trim())?;
+            let value = value.trim();
+
+            if operator == ">" || operator == "<" {
+                return value
+                    .parse::<i64>()
+                    .map(|_| ())
+                    .map_err(|_| format!("`{operator}` requires an integer value"));
+            }
+
+            return validate_value(value);
+        }
+    }
+
+    validate_key(requirement)
+}
+
+/// Validates a label key, which is an optional DNS subdomain prefix followed by `/` and a name.
+fn validate_key(key: &str) -> Result<(), String> {
+    let name = match key.split_once('/') {
+        Some((prefix, name)) => {
+            let valid_prefix = !prefix.is_empty()
+                && prefix.len() <= 253
+                && prefix.split('.').all(|label| {
+                    !label.is_empty()
+                        && label
+                            .chars()
+                            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                        && !label.starts_with('-')
+                        && !label.ends_with('-')
+                });
+            if !valid_prefix {
+                return Err(format!("invalid key prefix `{prefix}`"));
+            }
+            name
+        }
+        None => key,
+    };
+
+    if name.is_empty() || !is_valid_name(name) {
+        return Err(format!("invalid key `{key}`"));
+    }
+
+    Ok(())
+}
+
+/// Validates a label value, which is either empty or a valid name.
+fn validate_value(value: &str) -> Result<(), String> {
+    if value.is_empty() || is_valid_name(value) {
+        Ok(())
+    } else {
+        Err(format!("invalid value `{value}`"))
+    }
+}
+
+/// Checks whether the string is at most 63 alphanumeric characters, `-`, `_` or `.`,
+/// starting and ending with an alphanumeric character.
+fn is_valid_name(name: &str) -> bool {
+    name.len() <= 63
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_label_selectors() {
+        for selector in [
+            "app=web",
+            "app==web,tier!=db",
+            "env in (prod, stage),!deprecated",
+            "app.kubernetes.io/name notin (web)",
+            "release",
+            "replicas>2",
+            "app=",
+        ] {
+            assert_eq!(parse_label_selector(selector).as_deref(), Ok(selector));
+        }
+    }
+
+    #[test]
+    fn invalid_label_selectors() {
+        for selector in [
+            "",
+            "app=web,",
+            "env in (prod",
+            "env in prod)",
+            "env in ()",
+            "env in ((prod))",
+            "env within (prod)",
+            "replicas>two",
+            "-app=web",
+            "Example.com/app=web",
+            "app=web!",
+        ] {
+            assert!(parse_label_selector(selector).is_err(), "{selector}");
+        }
+    }
+
+    #[test]
+    fn labels_are_validated() {
+        assert_eq!(
+            parse_label("app.kubernetes.io/name=web"),
+            Ok(("app.kubernetes.io/name".into(), "web".into()))
+        );
+        assert_eq!(parse_label("app="), Ok(("app".into(), "".into())));
+        assert!(parse_label("app").is_err());
+        assert!(parse_label("app=not valid").is_err());
+        assert!(parse_label("-app=web").is_err());
+    }
+
+    #[test]
+    fn annotation_values_are_not_restricted() {
+        assert_eq!(
+            parse_annotation("example.com/owner=team a, b=c"),
+            Ok(("example.com/owner".into(), "team a, b=c".into()))
+        );
+        assert_eq!(
+            parse_annotation(&format!("note={}", "a".repeat(100))),
+            Ok(("note".into(), "a".repeat(100)))
+        );
+        assert!(parse_annotation("example.com/owner").is_err());
+        assert!(parse_annotation("-owner=team-a").is_err());
+    }
+
+    #[test]
+    fn names_are_limited_to_63_characters() {
+        assert!(parse_label(&format!("app={}", "a".repeat(63))).is_ok());
+        assert!(parse_label(&format!("app={}", "a".repeat(64))).is_err());
+        assert!(parse_label(&format!("{}=web", "a".repeat(64))).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_yaml::Value;
+use tokio::fs;
+
+use crate::{condition::WaitFor, error::WaitError, filter::Filter, resource::ResourceFilter};
+
+/// Single resource waited for in a sequence, read from its YAML representation, e.g.
+///
+/// ```yaml
+/// - kind: Deployment
+///   apiVersion: apps/v1
+///   name: operator
+///   for: scaled
+///   filter:
+///     metadata:
+///       labels:
+///         app: operator
+/// ```
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RawStep {
+    kind: String,
+    name: String,
+    namespace: Option<String>,
+    group: Option<String>,
+    group_version: Option<String>,
+    api_version: Option<String>,
+    plural: Option<String>,
+    /// State filter, matches any state when omitted.
+    filter: Option<Value>,
+    #[serde(rename = "for")]
+    wait_for: Option<String>,
+}
+
+/// Single resource waited for in a sequence.
+pub struct Step {
+    pub resource: ResourceFilter,
+    pub name: String,
+    /// Namespace of the resource, defaults to `--namespace`.
+    pub namespace: Option<String>,
+    pub filter: Filter,
+    pub wait_for: Option<WaitFor>,
+}
+
+impl Step {
+    fn parse(raw: RawStep) -> Result<Self> {
+        anyhow::ensure!(!raw.kind.is_empty(), "`kind` must not be empty");
+        anyhow::ensure!(!raw.name.is_empty(), "`name` must not be empty");
+
+        let resource = ResourceFilter::new(
+            raw.kind,
+            raw.group,
+            raw.group_version,
+            raw.api_version.as_deref(),
+            raw.plural,
+        )
+        .map_err(anyhow::Error::msg)
+        .context("invalid resource")?;
+
+        let filter = match raw.filter {
+            Some(filter) => Filter::parse(filter)
+                .context(WaitError::InvalidStateFilter)
+                .context("invalid `filter`")?,
+            None => Filter::any(),
+        };
+
+        let wait_for = raw
+            .wait_for
+            .map(|wait_for| wait_for.parse())
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .context("invalid `for`")?;
+
+        Ok(Self {
+            resource,
+            name: raw.name,
+            namespace: raw.namespace,
+            filter,
+            wait_for,
+        })
+    }
+}
+
+/// Reads the sequence of resources to wait for, one after another.
+/// All steps are validated before the first wait starts.
+pub async fn read_sequence(path: &Path) -> Result<Vec<Step>> {
+    let raw_bytes = fs::read(path)
+        .await
+        .context("failed to read sequence from file")?;
+
+    let raw_steps: Vec<RawStep> = serde_yaml::from_slice(&raw_bytes)
+        .map_err(|error| deserialization_error(&raw_bytes, error))
+        .context(WaitError::InvalidSequence)?;
+    if raw_steps.is_empty() {
+        return Err(anyhow::anyhow!("sequence is empty").context(WaitError::InvalidSequence));
+    }
+
+    raw_steps
+        .into_iter()
+        .enumerate()
+        .map(|(i, raw)| {
+            Step::parse(raw)
+                .with_context(|| format!("invalid step {}", i + 1))
+                .context(WaitError::InvalidSequence)
+        })
+        .collect()
+}
+
+/// Names the step that failed to deserialize, in addition to the field and the line reported by the deserializer,
+/// e.g. `invalid step 2: .[1]: unknown field `nmae` ... at line 4 column 3`.
+fn deserialization_error(raw_bytes: &[u8], error: serde_yaml::Error) -> anyhow::Error {
+    let failed_step = serde_yaml::from_slice::<Vec<Value>>(raw_bytes)
+        .ok()
+        .and_then(|steps| {
+            steps
+                .into_iter()
+                .position(|step| serde_yaml::from_value::<RawStep>(step).is_err())
+        });
+
+    match failed_step {
+        Some(i) => anyhow::Error::new(error).context(format!("invalid step {}", i + 1)),
+        None => anyhow::Error::new(error).context("failed to deserialize sequence"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<Step> {
+        Step::parse(serde_yaml::from_str(yaml)?)
+    }
+
+    #[test]
+    fn steps_are_parsed() {
+        let step = parse(
+            "{ kind: Deployment, apiVersion: apps/v1, name: operator, namespace: infra, for: scaled, filter: { metadata: { labels: { app: operator } } } }",
+        )
+        .unwrap();
+        assert_eq!(step.resource.kind, "Deployment");
+        assert_eq!(step.resource.group.as_deref(), Some("apps"));
+        assert_eq!(step.resource.version.as_deref(), Some("v1"));
+        assert_eq!(step.name, "operator");
+        assert_eq!(step.namespace.as_deref(), Some("infra"));
+        assert!(matches!(step.wait_for, Some(WaitFor::Scaled)));
+    }
+
+    #[test]
+    fn filter_defaults_to_any_state() {
+        let step = parse("{ kind: Pod, name: web }").unwrap();
+        let state = serde_yaml::from_str("{ status: { phase: Pending } }").unwrap();
+        assert!(crate::filter::match_state(&step.filter, &state));
+        assert!(step.wait_for.is_none());
+    }
+
+    #[test]
+    fn invalid_steps_are_rejected() {
+        for yaml in [
+            "{ kind: '', name: web }",
+            "{ kind: Pod, name: '' }",
+            "{ kind: Pod, name: web, group: batch, apiVersion: apps/v1 }",
+            "{ kind: Pod, name: web, for: healthy }",
+            "{ kind: Pod, name: web, filter: { $unknown: 1 } }",
+            "{ kind: Pod, name: web, nmae: web }",
+            "{ kind: Pod }",
+        ] {
+            assert!(parse(yaml).is_err(), "{yaml}");
+        }
+    }
+
+    #[test]
+    fn deserialization_errors_name_the_step() {
+        let raw = b"- { kind: Pod, name: a }\n- { kind: Pod, nmae: b }\n";
+        let error = serde_yaml::from_slice::<Vec<RawStep>>(raw).err().unwrap();
+        let error = deserialization_error(raw, error);
+        assert_eq!(error.to_string(), "invalid step 2");
+        assert!(
+            format!("{error:#}").contains("unknown field `nmae`"),
+            "{error:#}"
+        );
+
+        let raw = b"- { kind: Pod, name: a\n";
+        let error = serde_yaml::from_slice::<Vec<RawStep>>(raw).err().unwrap();
+        assert_eq!(
+            deserialization_error(raw, error).to_string(),
+            "failed to deserialize sequence"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::future::{self, Future};
+
+use tokio::signal;
+
+use crate::error::WaitError;
+
+/// Waits until the program is asked to stop, e.g. with Ctrl-C or when the pod of a Kubernetes Job is deleted,
+/// and returns the matching error, so that the wait ends with its exit code.
+/// The signals are listened for from the call, not from the first poll, so none is missed
+/// while the requests raced against the returned future are sent.
+/// Signals that cannot be listened for are left to their default handling.
+pub fn terminated() -> impl Future<Output = WaitError> {
+    #[cfg(unix)]
+    {
+        use signal::unix::{signal, SignalKind};
+
+        let wait_for = |kind, error: WaitError| {
+            let signal = signal(kind);
+            async move {
+                match signal {
+                    Ok(mut signal) => {
+                        signal.recv().await;
+                        error
+                    }
+                    Err(_) => future::pending().await,
+                }
+            }
+        };
+        let interrupted = wait_for(SignalKind::interrupt(), WaitError::Interrupted);
+        let terminated = wait_for(SignalKind::terminate(), WaitError::Terminated);
+
+        async {
+            tokio::select! {
+                error = interrupted => error,
+                error = terminated => error,
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    async {
+        match signal::ctrl_c().await {
+            Ok(()) => WaitError::Interrupted,
+            Err(_) => future::pending().await,
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Fake Kubernetes API server used in tests.
+
+use std::sync::{Arc, Mutex};
+
+use k8s_openapi::api::core::v1::Pod;
+use kube::{
+    api::{ApiResource, DynamicObject},
+    Api, Client, Config,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// Request received by the [`FakeApi`].
+#[derive(Clone, Debug)]
+pub struct Request {
+    /// Path of the request, including the query.
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the path of the request, without the query.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    /// Returns the decoded value of the query parameter.
+    pub fn query(&self, name: &str) -> Option<String> {
+        let (_, query) = self.target.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| decode(value))
+    }
+
+    /// Returns whether the request is a watch.
+    pub fn is_watch(&self) -> bool {
+        self.query("watch").as_deref() == Some("true")
+    }
+
+    /// Returns the value of the header, matching its name case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Response sent by the [`FakeApi`].
+pub enum Response {
+    /// JSON body with the status code.
+    Json(u16, Value),
+    /// Plain text body with the status code.
+    Text(u16, String),
+    /// Watch events sent one per line, after which the connection is kept open
+    /// until the client closes it.
+    Events(Vec<Value>),
+}
+
+/// HTTP server answering requests with the responses returned by a handler.
+/// Every received request is recorded.
+pub struct FakeApi {
+    url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+    server: JoinHandle<()>,
+}
+
+impl FakeApi {
+    pub async fn start<H>(handler: H) -> Self
+    where
+        H: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+
+        let server = tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio::spawn(serve(stream, handler.clone(), requests.clone()));
+                }
+            }
+        });
+
+        Self {
+            url,
+            requests,
+            server,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns a client sending requests to this server, with `default` as the default namespace.
+    pub fn client(&self) -> Client {
+        Client::try_from(Config::new(self.url.parse().unwrap())).unwrap()
+    }
+
+    /// Returns a kubeconfig with a single context, pointing to this server.
+    pub fn kubeconfig(&self) -> String {
+        format!(
+            "apiVersion: v1
+kind: Config
+clusters:
+- name: fake
+  cluster:
+    server: {}
+contexts:
+- name: fake
+  context:
+    cluster: fake
+    user: fake
+current-context: fake
+users:
+- name: fake
+  user: {{}}
+",
+            self.url
+        )
+    }
+
+    /// Returns the requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for FakeApi {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Answers the requests received through the connection until it is closed.
+async fn serve<H>(stream: TcpStream, handler: Arc<H>, requests: Arc<Mutex<Vec<Request>>>)
+where
+    H: Fn(&Request) -> Response,
+{
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let target = line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let Some((name, value)) = line.trim_end().split_once(':') else {
+                break;
+            };
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+
+        let mut request = Request {
+            target,
+            headers,
+            body: Vec::new(),
+        };
+        let length = request
+            .header("content-length")
+            .map(|length| length.parse().unwrap())
+            .unwrap_or(0);
+        request.body = vec![0; length];
+        stream.read_exact(&mut request.body).await.unwrap();
+
+        requests.lock().unwrap().push(request.clone());
+        match handler(&request) {
+            Response::Json(status, body) => {
+                write_body(&mut stream, status, "application/json", &body.to_string()).await
+            }
+            Response::Text(status, body) => {
+                write_body(&mut stream, status, "text/plain", &body).await
+            }
+            Response::Events(events) => {
+                let head = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ntransfer-encoding: chunked\r\n\r\n";
+                stream.write_all(head.as_bytes()).await.unwrap();
+                for event in events {
+                    let line = format!("{event}\n");
+                    let chunk = format!("{:x}\r\n{line}\r\n", line.len());
+                    stream.write_all(chunk.as_bytes()).await.unwrap();
+                }
+                stream.flush().await.unwrap();
+                // The stream never ends, as a watch that is not timed out.
+                while stream.read(&mut [0; 64]).await.unwrap_or(0) > 0 {}
+                return;
+            }
+        }
+    }
+}
+
+async fn write_body(
+    stream: &mut BufReader<TcpStream>,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) {
+    let head = format!(
+        "HTTP/1.1 {status} Fake\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body.as_bytes()).await.unwrap();
+}
+
+/// Decodes percent-encoded characters and `+` as a space.
+fn decode(value: &str) -> String {
+    let mut decoded = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [bytes.next().unwrap(), bytes.next().unwrap()];
+                let hex = std::str::from_utf8(&hex).unwrap();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+/// Returns the API of pods in the `default` namespace.
+pub fn pods(client: Client) -> Api<DynamicObject> {
+    Api::default_namespaced_with(client, &ApiResource::erase::<Pod>(&()))
+}
+
+/// Returns a pod with the given name, used also as its UID, and phase.
+pub fn pod(name: &str, phase: &str) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": {
+            "name": name,
+            "namespace": "default",
+            "uid": name,
+            "resourceVersion": "1",
+        },
+        "status": { "phase": phase },
+    })
+}
+
+/// Returns a list of pods with the given items.
+pub fn pod_list(items: Vec<Value>) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "PodList",
+        "metadata": { "resourceVersion": "1" },
+        "items": items,
+    })
+}
+
+/// Returns a watch event of the type, e.g. `MODIFIED`.
+pub fn event(kind: &str, object: Value) -> Value {
+    json!({ "type": kind, "object": object })
+}
+
+/// Returns a failure status with the given code, reason and message.
+pub fn status(code: u16, reason: &str, message: &str) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Status",
+        "status": "Failure",
+        "code": code,
+        "reason": reason,
+        "message": message,
+        "metadata": {},
+    })
+}
+
+/// Answers discovery requests, serving the resources given as apiVersions and kinds,
+/// e.g. `("apps/v1", "Deployment")`, with lowercase plural names, e.g. `deployments`.
+/// The first apiVersion of every group is its preferred version.
+/// The core group always serves `v1`, even without resources.
+///
+/// Returns [`None`] for other requests.
+pub fn discovery(request: &Request, resources: &[(&str, &str)]) -> Option<Response> {
+    let split = |api_version: &str| match api_version.split_once('/') {
+        Some((group, version)) => (group.to_string(), version.to_string()),
+        None => (String::new(), api_version.to_string()),
+    };
+    let mut groups = vec![(String::new(), vec!["v1".to_string()])];
+    for (api_version, _) in resources {
+        let (group, version) = split(api_version);
+        match groups.iter_mut().find(|(name, _)| *name == group) {
+            Some((_, versions)) if versions.contains(&version) => {}
+            Some((_, versions)) => versions.push(version),
+            None => groups.push((group, vec![version])),
+        }
+    }
+    let resource_list = |api_version: &str| {
+        let resources = resources
+            .iter()
+            .filter(|(candidate, _)| *candidate == api_version)
+            .map(|(_, kind)| {
+                json!({
+                    "name": format!("{}s", kind.to_lowercase()),
+                    "singularName": kind.to_lowercase(),
+                    "namespaced": true,
+                    "kind": kind,
+                    "verbs": ["get", "list", "watch"],
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "kind": "APIResourceList",
+            "apiVersion": "v1",
+            "groupVersion": api_version,
+            "resources": resources,
+        })
+    };
+
+    let body = match request.path() {
+        "/api" => json!({
+            "kind": "APIVersions",
+            "versions": groups
+                .iter()
+                .find(|(name, _)| name.is_empty())
+                .map(|(_, versions)| versions.clone()),
+            "serverAddressByClientCIDRs": [],
+        }),
+        "/apis" => {
+            let groups = groups
+                .iter()
+                .filter(|(name, _)| !name.is_empty())
+                .map(|(name, versions)| {
+                    let versions = versions
+                        .iter()
+                        .map(|version| json!({ "groupVersion": format!("{name}/{version}"), "version": version }))
+                        .collect::<Vec<_>>();
+                    json!({ "name": name, "versions": versions, "preferredVersion": versions[0] })
+                })
+                .collect::<Vec<_>>();
+            json!({ "kind": "APIGroupList", "apiVersion": "v1", "groups": groups })
+        }
+        path => {
+            let api_version = path
+                .strip_prefix("/api/")
+                .or_else(|| path.strip_prefix("/apis/"))?;
+            if api_version != "v1"
+                && !resources
+                    .iter()
+                    .any(|(candidate, _)| *candidate == api_version)
+            {
+                return None;
+            }
+            resource_list(api_version)
+        }
+    };
+
+    Some(Response::Json(200, body))
+}
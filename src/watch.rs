@@ -0,0 +1,2190 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use futures::{stream::FuturesUnordered, StreamExt};
+use glob::Pattern;
+use indicatif::ProgressBar;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::{
+    api::{DynamicObject, ListParams, ObjectList},
+    core::ErrorResponse,
+    runtime::{
+        self,
+        watcher::{self, Config, Event},
+        WatchStreamExt,
+    },
+    Api,
+};
+use rand::Rng;
+use serde_yaml::Value;
+use tokio::time::{self, Instant};
+
+use crate::{
+    cel::CelExpression,
+    condition::WaitFor,
+    error::WaitError,
+    explain,
+    filter::{self, match_state, Filter, NamedFilter},
+    history::History,
+    metrics::Metrics,
+    path::ValuePath,
+    resource::SubresourcePath,
+};
+
+/// Client-side criteria checked on every observed resource before matching its state.
+#[derive(Clone)]
+pub struct ObjectFilter {
+    /// Pattern that the resource name must match.
+    pub name_glob: Option<Pattern>,
+    /// Names of which the resource name must be one, any name if empty.
+    pub names: Vec<String>,
+    /// Instant before which the resource must not have been created.
+    pub since: Option<DateTime<Utc>>,
+    /// Minimal age of the resource, computed from its creation timestamp.
+    pub min_age: Option<Duration>,
+    /// UID that the resource must have.
+    pub uid: Option<String>,
+    /// UID of an owner that must be listed in the resource owner references.
+    pub owner_uid: Option<String>,
+}
+
+impl ObjectFilter {
+    fn matches(&self, object: &DynamicObject) -> bool {
+        self.name_glob
+            .as_ref()
+            .map(|p| {
+                object
+                    .metadata
+                    .name
+                    .as_deref()
+                    .is_some_and(|n| p.matches(n))
+            })
+            .unwrap_or(true)
+            && (self.names.is_empty()
+                || object
+                    .metadata
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| self.names.contains(name)))
+            && self
+                .since
+                .map(|since| {
+                    object
+                        .metadata
+                        .creation_timestamp
+                        .as_ref()
+                        .is_some_and(|created| created.0 >= since)
+                })
+                .unwrap_or(true)
+            && self
+                .uid
+                .as_ref()
+                .map(|uid| object.metadata.uid.as_ref() == Some(uid))
+                .unwrap_or(true)
+            && self
+                .owner_uid
+                .as_ref()
+                .map(|uid| {
+                    object
+                        .metadata
+                        .owner_references
+                        .iter()
+                        .flatten()
+                        .any(|owner| owner.uid == *uid)
+                })
+                .unwrap_or(true)
+    }
+
+    /// Returns the instant at which the resource reaches the minimal age.
+    /// Resources without a creation timestamp are treated as just created.
+    fn old_enough_at(&self, object: &DynamicObject) -> Instant {
+        let now = Instant::now();
+        let Some(min_age) = self.min_age else {
+            return now;
+        };
+
+        let age = object
+            .metadata
+            .creation_timestamp
+            .as_ref()
+            .and_then(|created| (Utc::now() - created.0).to_std().ok())
+            .unwrap_or_default();
+
+        now + min_age.saturating_sub(age)
+    }
+}
+
+/// How the resources are observed.
+#[derive(Clone, Copy)]
+pub enum WatchMode {
+    /// Watch the resources.
+    /// When the API server does not support watching them, fall back to polling at the given interval, if any.
+    Watch { poll_fallback: Option<Duration> },
+    /// List the resources repeatedly at the given interval.
+    Poll(Duration),
+    /// List the resources once and fail if none matches.
+    CheckOnce,
+}
+
+#[derive(Clone, Copy)]
+pub struct WatchOptions {
+    pub mode: WatchMode,
+    /// Maximal number of resource events processed without a match.
+    pub max_events: Option<u64>,
+    /// Maximal number of consecutive watch or list errors.
+    pub max_errors: Option<u64>,
+    /// Maximal total number of watch or list errors.
+    pub error_threshold: Option<u64>,
+    /// Instant at which the timeout expires, after which the watch is no longer restarted.
+    pub deadline: Option<Instant>,
+    /// Fraction by which every polling interval is randomly shortened or lengthened, see [`PollTimer`].
+    pub poll_jitter: f64,
+}
+
+/// Schedules polls, the first one immediately and every next one after the interval,
+/// randomly shortened or lengthened by up to the jitter fraction of it, e.g. 9 to 11 seconds for 10 seconds and 0.1.
+/// Pollers started at the same time, e.g. in many jobs, then spread their requests instead of sending them together.
+/// The interval is measured from the previous poll, so polls that take long delay the next ones.
+struct PollTimer {
+    interval: Duration,
+    jitter: f64,
+    next: Instant,
+    delay: Duration,
+}
+
+impl PollTimer {
+    fn new(interval: Duration, jitter: f64) -> Self {
+        Self {
+            interval,
+            jitter,
+            next: Instant::now(),
+            delay: interval,
+        }
+    }
+
+    /// Waits until the next poll is due and schedules the one after it.
+    async fn tick(&mut self) {
+        time::sleep_until(self.next).await;
+
+        self.delay = self.next_delay(&mut rand::thread_rng());
+        self.next = Instant::now() + self.delay;
+    }
+
+    /// Returns the randomly jittered interval, but never less than [`MIN_POLL_DELAY`],
+    /// so that short intervals with a large jitter do not poll in a busy loop.
+    fn next_delay(&self, rng: &mut impl Rng) -> Duration {
+        let factor = if self.jitter > 0.0 {
+            1.0 + rng.gen_range(-self.jitter..=self.jitter)
+        } else {
+            1.0
+        };
+        self.interval.mul_f64(factor).max(MIN_POLL_DELAY)
+    }
+
+    /// Returns the delay before the next poll, as scheduled by the last [`Self::tick`],
+    /// truncated to whole milliseconds for reporting.
+    fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay.as_millis() as u64)
+    }
+}
+
+/// Minimal delay between polls, see [`PollTimer::next_delay`].
+const MIN_POLL_DELAY: Duration = Duration::from_millis(1);
+
+/// Delay before the watch is restarted after the watcher stream finishes.
+const STREAM_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Counts processed resource events against [`WatchOptions::max_events`].
+struct EventCounter {
+    count: u64,
+    max: Option<u64>,
+}
+
+impl EventCounter {
+    fn record(&mut self) -> Result<()> {
+        self.count += 1;
+        if let Some(max) = self.max.filter(|max| self.count > *max) {
+            anyhow::bail!("no matching resource state was found within the limit of {max} events");
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts watch or list errors against [`WatchOptions::max_errors`] and [`WatchOptions::error_threshold`].
+struct ErrorCounter {
+    consecutive: u64,
+    total: u64,
+    max_consecutive: Option<u64>,
+    max_total: Option<u64>,
+}
+
+impl ErrorCounter {
+    fn new(options: &WatchOptions) -> Self {
+        Self {
+            consecutive: 0,
+            total: 0,
+            max_consecutive: options.max_errors,
+            max_total: options.error_threshold,
+        }
+    }
+
+    /// Records the error, returning it back if it is still tolerated.
+    fn record<E>(&mut self, error: E) -> Result<E>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        self.consecutive += 1;
+        self.total += 1;
+
+        if self
+            .max_consecutive
+            .is_some_and(|max| self.consecutive > max)
+        {
+            return Err(anyhow::Error::new(error).context(format!(
+                "giving up after {} consecutive errors",
+                self.consecutive
+            )));
+        }
+        if self.max_total.is_some_and(|max| self.total > max) {
+            return Err(anyhow::Error::new(error)
+                .context(format!("giving up after {} errors in total", self.total)));
+        }
+
+        Ok(error)
+    }
+
+    fn reset(&mut self) {
+        self.consecutive = 0;
+    }
+}
+
+/// Returns whether the event shows that the watch recovered from previous errors.
+/// A restart emits `Init` before listing the resources, so only later events mean recovery.
+fn is_recovery<K>(event: &Event<K>) -> bool {
+    !matches!(event, Event::Init)
+}
+
+/// Matches observed resource states, keeping track of the ones that wait for the minimal age
+/// or for the confirmation of the match.
+#[derive(Clone)]
+pub struct Matcher<'a> {
+    object_filter: ObjectFilter,
+    filter: Filter,
+    wait_for: Option<WaitFor>,
+    cel: Option<CelExpression>,
+    /// Alternative filters, one of which must match, see [`Self::with_any_of`].
+    any_of: Vec<NamedFilter>,
+    /// Additional filters, all of which must match, see [`Self::with_all_of`].
+    all_of: Vec<NamedFilter>,
+    /// Filters of states that fail the wait, see [`Self::with_fail_if`].
+    fail_if: Vec<NamedFilter>,
+    metrics: &'a Metrics,
+    /// How long a resource must keep matching before the match is confirmed.
+    confirm_after: Option<Duration>,
+    /// Latest matching states of resources that are not old enough or not confirmed yet,
+    /// keyed by resource UID, with the instants at which they match.
+    pending: HashMap<String, (Instant, Value)>,
+    /// UIDs of pending resources that were not listed again yet, see [`Self::start_relist`].
+    unlisted: HashSet<String>,
+    /// Progress bar suspended while explaining why observed states do not match, see [`Self::with_explain`].
+    explain: Option<ProgressBar>,
+    /// Callback receiving matching states instead of ending the watch, see [`Self::with_follow`].
+    follow: Option<&'a dyn Fn(&Value)>,
+    /// Resource versions of the states passed to [`Self::follow`], keyed by resource UID.
+    followed: HashMap<String, String>,
+    /// Maximal size of matched resources and the progress bar suspended while warning about larger ones,
+    /// see [`Self::with_max_object_bytes`].
+    max_object_bytes: Option<(usize, ProgressBar)>,
+    /// UIDs of resources that were reported as too large.
+    oversized: HashSet<String>,
+    /// Latest statuses of the resources, keyed by resource UID, if only status changes are matched,
+    /// see [`Self::with_status_changes_only`].
+    statuses: Option<HashMap<String, serde_json::Value>>,
+    /// Path of the value that must change and its initial values, keyed by resource UID,
+    /// see [`Self::with_changed`].
+    changed: Option<(ValuePath, HashMap<String, Option<Value>>)>,
+    /// Whether the last matching state of a list is returned instead of the first one,
+    /// see [`Self::with_last_listed_match`].
+    last_listed_match: bool,
+    /// Last matching state of the current list.
+    listed_match: Option<Value>,
+    /// Receives every observed state, matching or not, see [`Self::with_last_observed`].
+    last_observed: Option<&'a RefCell<Option<Value>>>,
+    /// Records every observed state, matching or not, see [`Self::with_history`].
+    history: Option<&'a RefCell<History>>,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(
+        object_filter: ObjectFilter,
+        filter: Filter,
+        wait_for: Option<WaitFor>,
+        cel: Option<CelExpression>,
+        metrics: &'a Metrics,
+    ) -> Self {
+        Self {
+            object_filter,
+            filter,
+            wait_for,
+            cel,
+            any_of: Vec::new(),
+            all_of: Vec::new(),
+            fail_if: Vec::new(),
+            metrics,
+            confirm_after: None,
+            pending: Default::default(),
+            unlisted: Default::default(),
+            explain: None,
+            follow: None,
+            followed: Default::default(),
+            max_object_bytes: None,
+            oversized: Default::default(),
+            statuses: None,
+            changed: None,
+            last_listed_match: false,
+            listed_match: None,
+            last_observed: None,
+            history: None,
+        }
+    }
+
+    /// When the resources are listed, e.g. after a watch (re)start or in a poll,
+    /// returns the last matching state of the list once it is done, instead of the first one right away.
+    pub fn with_last_listed_match(mut self, last_listed_match: bool) -> Self {
+        self.last_listed_match = last_listed_match;
+        self
+    }
+
+    /// Matches resources only when they are first observed and when their status changes,
+    /// ignoring updates that leave the status untouched, e.g. updates of the spec.
+    pub fn with_status_changes_only(mut self, status_changes_only: bool) -> Self {
+        self.statuses = status_changes_only.then(HashMap::new);
+        self
+    }
+
+    /// Requires the value at the path to differ from its value in the first observed state of the resource,
+    /// which never matches. A value missing at first differs from any value present later.
+    pub fn with_changed(mut self, path: Option<ValuePath>) -> Self {
+        self.changed = path.map(|path| (path, HashMap::new()));
+        self
+    }
+
+    /// Skips resources that take more than the given number of bytes when serialized as JSON,
+    /// with a warning printed once for every resource.
+    pub fn with_max_object_bytes(mut self, max: Option<usize>, progress: &ProgressBar) -> Self {
+        self.max_object_bytes = max.map(|max| (max, progress.clone()));
+        self
+    }
+
+    /// Passes every matching state to the callback and keeps watching, instead of returning the first one.
+    /// Every resource version is passed only once, e.g. resources listed again after a watch restart are skipped.
+    pub fn with_follow(mut self, follow: Option<&'a dyn Fn(&Value)>) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Requires the state to match any of the alternative filters, in addition to the others.
+    pub fn with_any_of(mut self, any_of: Vec<NamedFilter>) -> Self {
+        self.any_of = any_of;
+        self
+    }
+
+    /// Requires the state to match all of the additional filters, in addition to the others.
+    pub fn with_all_of(mut self, all_of: Vec<NamedFilter>) -> Self {
+        self.all_of = all_of;
+        self
+    }
+
+    /// Fails with [`WaitError::FailIfMatched`] as soon as an observed state matches any of the filters,
+    /// e.g. a failed job, instead of waiting for a match until the timeout.
+    pub fn with_fail_if(mut self, fail_if: Vec<NamedFilter>) -> Self {
+        self.fail_if = fail_if;
+        self
+    }
+
+    /// Keeps the last observed state of any resource in the cell, whether it matches or not,
+    /// e.g. to print it when the wait ends without a match.
+    pub fn with_last_observed(mut self, last_observed: Option<&'a RefCell<Option<Value>>>) -> Self {
+        self.last_observed = last_observed;
+        self
+    }
+
+    /// Records every observed state of any resource in the history, whether it matches or not.
+    pub fn with_history(mut self, history: Option<&'a RefCell<History>>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Prints an explanation of every observed state that does not match to standard error.
+    pub fn with_explain(mut self, progress: Option<ProgressBar>) -> Self {
+        self.explain = progress;
+        self
+    }
+
+    /// Requires a resource to keep matching for the duration after it first matched.
+    /// The latest state of the resource is returned once the match is confirmed.
+    pub fn with_confirm_after(mut self, confirm_after: Option<Duration>) -> Self {
+        self.confirm_after = confirm_after;
+        self
+    }
+
+    /// Returns the serialized state if it matches, the resource is old enough and the match is confirmed.
+    /// Fails if the state matches any of [`Self::fail_if`].
+    fn observe(&mut self, state: DynamicObject) -> Result<Option<Value>> {
+        let uid = state.metadata.uid.clone().unwrap_or_default();
+        self.unlisted.remove(&uid);
+
+        if !self.object_filter.matches(&state) || self.is_oversized(&state) {
+            self.pending.remove(&uid);
+            return Ok(None);
+        }
+
+        if let Some(statuses) = &mut self.statuses {
+            let status = state.data.get("status").cloned().unwrap_or_default();
+            if statuses.get(&uid) == Some(&status) {
+                return Ok(None);
+            }
+            statuses.insert(uid.clone(), status);
+        }
+
+        self.metrics.record_match_attempt();
+        let old_enough_at = self.object_filter.old_enough_at(&state);
+        let serialized = serde_yaml::to_value(state).expect("serialization should not fail");
+        if let Some(last_observed) = self.last_observed {
+            last_observed.replace(Some(serialized.clone()));
+        }
+        if let Some(history) = self.history {
+            history.borrow_mut().record(&serialized);
+        }
+        if let Some(named) = filter::first_matching(&self.fail_if, &serialized) {
+            return Err(WaitError::FailIfMatched {
+                resource: display_name(&serialized),
+                filter: named.name.clone(),
+            }
+            .into());
+        }
+        if !self.has_changed(&uid, &serialized) {
+            self.pending.remove(&uid);
+            return Ok(None);
+        }
+        if !self.matches(&serialized) {
+            tracing::debug!(
+                resource = %display_name(&serialized),
+                resource_version = %serialized["metadata"]["resourceVersion"].as_str().unwrap_or_default(),
+                first_mismatch = %self.first_mismatch(&serialized),
+                "observed state does not match"
+            );
+            if let Some(progress) = &self.explain {
+                let explanation = self.explain(&serialized);
+                progress.suspend(|| eprint!("{explanation}"));
+            }
+            self.pending.remove(&uid);
+            return Ok(None);
+        }
+
+        tracing::debug!(
+            resource = %display_name(&serialized),
+            resource_version = %serialized["metadata"]["resourceVersion"].as_str().unwrap_or_default(),
+            "observed state matches"
+        );
+
+        let matches_at = match (self.confirm_after, self.pending.get(&uid)) {
+            // The confirmation started with the first match and goes on.
+            (Some(_), Some((at, _))) => *at,
+            (Some(confirm_after), None) => old_enough_at + confirm_after,
+            (None, _) => old_enough_at,
+        };
+        if matches_at > Instant::now() {
+            self.pending.insert(uid, (matches_at, serialized));
+            return Ok(None);
+        }
+        self.pending.remove(&uid);
+
+        Ok(self.matched(uid, serialized))
+    }
+
+    /// Returns whether the serialized state matches all filters and conditions.
+    fn matches(&self, serialized: &Value) -> bool {
+        match_state(&self.filter, serialized)
+            && self
+                .wait_for
+                .as_ref()
+                .map(|wait_for| wait_for.matches(serialized))
+                .unwrap_or(true)
+            && self
+                .cel
+                .as_ref()
+                .map(|cel| cel.matches(serialized))
+                .unwrap_or(true)
+            && (self.any_of.is_empty()
+                || filter::first_matching(&self.any_of, serialized).is_some())
+            && self
+                .all_of
+                .iter()
+                .all(|named| match_state(&named.filter, serialized))
+    }
+
+    /// Returns whether the value at [`Self::changed`] differs from its initial value,
+    /// recording the value as the initial one in the first observed state of the resource.
+    fn has_changed(&mut self, uid: &str, serialized: &Value) -> bool {
+        let Some((path, initial)) = &mut self.changed else {
+            return true;
+        };
+
+        let value = path.get(serialized);
+        match initial.get(uid) {
+            Some(initial) => initial.as_ref() != value,
+            None => {
+                initial.insert(uid.into(), value.cloned());
+                false
+            }
+        }
+    }
+
+    /// Returns the serialized state if it matches right away, ignoring the minimal age, `Some(None)` if it does not,
+    /// or [`None`] if the resource is excluded by the object filter.
+    fn match_now(&mut self, state: &DynamicObject) -> Option<Option<Value>> {
+        if !self.object_filter.matches(state) || self.is_oversized(state) {
+            return None;
+        }
+
+        self.metrics.record_match_attempt();
+        let serialized = serde_yaml::to_value(state).expect("serialization should not fail");
+        Some(self.matches(&serialized).then_some(serialized))
+    }
+
+    /// Returns whether the resource exceeds [`Self::max_object_bytes`], warning about it the first time.
+    fn is_oversized(&mut self, state: &DynamicObject) -> bool {
+        let Some((max, progress)) = &self.max_object_bytes else {
+            return false;
+        };
+        if !exceeds_size(state, *max) {
+            return false;
+        }
+
+        let uid = state.metadata.uid.clone().unwrap_or_default();
+        if self.oversized.insert(uid) {
+            progress.suspend(|| {
+                eprintln!(
+                    "Warning: skipping resource {}, which is larger than {max} bytes.",
+                    state.metadata.name.as_deref().unwrap_or("unknown")
+                )
+            });
+        }
+
+        true
+    }
+
+    /// Returns the matching state, unless it is passed to [`Self::follow`].
+    fn matched(&mut self, uid: String, state: Value) -> Option<Value> {
+        let Some(follow) = self.follow else {
+            self.metrics.record_matched();
+            return Some(state);
+        };
+
+        let version = state["metadata"]["resourceVersion"]
+            .as_str()
+            .unwrap_or_default();
+        if self.followed.get(&uid).map(String::as_str) != Some(version) {
+            self.metrics.record_matched();
+            self.followed.insert(uid, version.into());
+            follow(&state);
+        }
+
+        None
+    }
+
+    fn explain(&self, state: &Value) -> String {
+        let mut explanation = format!("State of {} does not match:\n", display_name(state));
+        explanation.push_str(&explain::explain_state(&self.filter, state));
+        if let Some(wait_for) = &self.wait_for {
+            explain::push_line(
+                &mut explanation,
+                0,
+                wait_for.matches(state),
+                "`--for` condition",
+            );
+        }
+        if let Some(cel) = &self.cel {
+            explain::push_line(
+                &mut explanation,
+                0,
+                cel.matches(state),
+                "`--cel` expression",
+            );
+        }
+        if !self.any_of.is_empty() {
+            let matches = filter::first_matching(&self.any_of, state).is_some();
+            explain::push_line(&mut explanation, 0, matches, "`--any-of` filters");
+            Self::explain_named(&self.any_of, state, &mut explanation);
+        }
+        if !self.all_of.is_empty() {
+            let matches = self
+                .all_of
+                .iter()
+                .all(|named| match_state(&named.filter, state));
+            explain::push_line(&mut explanation, 0, matches, "`--all-of` filters");
+            Self::explain_named(&self.all_of, state, &mut explanation);
+        }
+
+        explanation
+    }
+
+    /// Describes the first filter or condition that the state does not match, in the order of [`Self::explain`],
+    /// with the path of the first failing value for state filters, e.g. `.status.phase`.
+    fn first_mismatch(&self, state: &Value) -> String {
+        if let Some(path) = explain::first_mismatch(&self.filter, state) {
+            return path.to_string();
+        }
+        if self
+            .wait_for
+            .as_ref()
+            .is_some_and(|wait_for| !wait_for.matches(state))
+        {
+            return "`--for` condition".into();
+        }
+        if self.cel.as_ref().is_some_and(|cel| !cel.matches(state)) {
+            return "`--cel` expression".into();
+        }
+        if !self.any_of.is_empty() && filter::first_matching(&self.any_of, state).is_none() {
+            return "`--any-of` filters".into();
+        }
+
+        self.all_of
+            .iter()
+            .find_map(|named| {
+                explain::first_mismatch(&named.filter, state)
+                    .map(|path| format!("`--all-of` {} at {path}", named.name))
+            })
+            .unwrap_or_default()
+    }
+
+    fn explain_named(filters: &[NamedFilter], state: &Value, explanation: &mut String) {
+        for named in filters {
+            let matches = match_state(&named.filter, state);
+            explain::push_line(explanation, 1, matches, &named.name);
+            explain::explain(&named.filter, state, 2, explanation);
+        }
+    }
+
+    /// Selects the resources by name client-side instead of with the field selector,
+    /// when the API server does not support selecting them by name, see [`is_field_selector_unsupported`].
+    /// Returns whether the field selector selected the resources by name and was removed.
+    fn select_name_client_side(
+        &mut self,
+        field_selector: &mut Option<String>,
+        progress: &ProgressBar,
+    ) -> bool {
+        let Some(name) = field_selector
+            .as_deref()
+            .and_then(|selector| selector.strip_prefix("metadata.name="))
+        else {
+            return false;
+        };
+
+        self.object_filter.names = vec![name.into()];
+        *field_selector = None;
+        progress.suspend(|| {
+            eprintln!("API server does not support selecting the resource by name, the name is matched client-side instead.")
+        });
+        true
+    }
+
+    fn forget(&mut self, state: &DynamicObject) {
+        if let Some(uid) = &state.metadata.uid {
+            self.pending.remove(uid);
+            self.followed.remove(uid);
+            if let Some(statuses) = &mut self.statuses {
+                statuses.remove(uid);
+            }
+            if let Some((_, initial)) = &mut self.changed {
+                initial.remove(uid);
+            }
+        }
+    }
+
+    /// Starts listing the resources again, e.g. after a watch restart.
+    /// Pending resources that are not listed again until [`Self::finish_relist`] are forgotten,
+    /// and so is the match kept from an unfinished previous list.
+    fn start_relist(&mut self) {
+        self.unlisted = self.pending.keys().cloned().collect();
+        self.listed_match = None;
+    }
+
+    /// Observes a resource listed after [`Self::start_relist`].
+    /// With [`Self::last_listed_match`], the match is kept until [`Self::finish_relist`].
+    fn observe_listed(&mut self, state: DynamicObject) -> Result<Option<Value>> {
+        let Some(serialized) = self.observe(state)? else {
+            return Ok(None);
+        };
+        if !self.last_listed_match {
+            return Ok(Some(serialized));
+        }
+
+        self.listed_match = Some(serialized);
+        Ok(None)
+    }
+
+    /// Finishes the list, returning the last matching state kept by [`Self::observe_listed`].
+    fn finish_relist(&mut self) -> Option<Value> {
+        for uid in self.unlisted.drain() {
+            self.pending.remove(&uid);
+        }
+
+        self.listed_match.take()
+    }
+
+    /// Returns the UID and the instant of the pending state that matches first.
+    fn next_pending(&self) -> Option<(String, Instant)> {
+        self.pending
+            .iter()
+            .min_by_key(|(_, (at, _))| *at)
+            .map(|(uid, (at, _))| (uid.clone(), *at))
+    }
+
+    fn take_pending(&mut self, uid: &str) -> Option<Value> {
+        let (_, state) = self
+            .pending
+            .remove(uid)
+            .expect("pending state should be present");
+        self.matched(uid.into(), state)
+    }
+}
+
+/// Watches the resources through all APIs at once, e.g. in multiple namespaces,
+/// and returns the first matching state found through any of them.
+/// Other errors fail the whole wait, except for [`WaitError::NotMatched`],
+/// which is returned only when no API has a matching state.
+pub async fn watch_any_for_condition_met(
+    apis: Vec<Api<DynamicObject>>,
+    config: Config,
+    matcher: Matcher<'_>,
+    options: WatchOptions,
+    progress: &ProgressBar,
+) -> Result<Value> {
+    let mut watches = apis
+        .into_iter()
+        .map(|api| watch_for_condition_met(api, config.clone(), matcher.clone(), options, progress))
+        .collect::<FuturesUnordered<_>>();
+
+    let mut not_matched = None;
+    while let Some(result) = watches.next().await {
+        match result {
+            Err(error) if matches!(error.downcast_ref(), Some(WaitError::NotMatched)) => {
+                not_matched = Some(error);
+            }
+            result => return result,
+        }
+    }
+
+    Err(not_matched.expect("at least one API should be given"))
+}
+
+async fn watch_for_condition_met(
+    api: Api<DynamicObject>,
+    config: Config,
+    mut matcher: Matcher<'_>,
+    options: WatchOptions,
+    progress: &ProgressBar,
+) -> Result<Value> {
+    let mut config = config;
+    let mut events = EventCounter {
+        count: 0,
+        max: options.max_events,
+    };
+    let mut errors = ErrorCounter::new(&options);
+
+    let poll_fallback = match options.mode {
+        WatchMode::Watch { poll_fallback } => poll_fallback,
+        WatchMode::Poll(interval) => {
+            let timer = PollTimer::new(interval, options.poll_jitter);
+            return poll(api, &config, matcher, events, errors, timer, progress).await;
+        }
+        WatchMode::CheckOnce => return check_once(api, &config, matcher, progress).await,
+    };
+
+    let metrics = matcher.metrics;
+
+    let mut stream = Box::pin(runtime::watcher(api.clone(), config.clone()).default_backoff());
+    loop {
+        let next_pending = matcher.next_pending();
+        let wake_at = next_pending
+            .as_ref()
+            .map(|(_, at)| *at)
+            .unwrap_or_else(Instant::now);
+
+        let item = tokio::select! {
+            item = stream.next() => item,
+            _ = time::sleep_until(wake_at), if next_pending.is_some() => {
+                let (uid, _) = next_pending.expect("branch is enabled only when present");
+                match matcher.take_pending(&uid) {
+                    Some(state) => return Ok(state),
+                    None => continue,
+                }
+            }
+        };
+
+        let Some(item) = item else {
+            // The watcher restarts by itself after errors, so this is not expected to happen.
+            // The restart is delayed, so that a stream finishing right away does not restart in a busy loop.
+            let restart_at = Instant::now() + STREAM_RESTART_DELAY;
+            if options
+                .deadline
+                .is_some_and(|deadline| deadline <= restart_at)
+            {
+                return Err(anyhow::Error::msg("Watcher stream finished unexpectedly")
+                    .context(WaitError::Timeout));
+            }
+
+            progress.suspend(|| {
+                eprintln!(
+                    "Watcher stream finished unexpectedly and will restart in {}.",
+                    humantime::format_duration(STREAM_RESTART_DELAY)
+                )
+            });
+            time::sleep_until(restart_at).await;
+            stream = Box::pin(runtime::watcher(api.clone(), config.clone()).default_backoff());
+            continue;
+        };
+
+        match &item {
+            Ok(event) => {
+                metrics.record_event(event);
+                let kind = match event {
+                    Event::Apply(_) => "Apply",
+                    Event::Delete(_) => "Delete",
+                    Event::Init => "Init",
+                    Event::InitApply(_) => "InitApply",
+                    Event::InitDone => "InitDone",
+                };
+                progress.set_message(format!("Last event: {kind}"));
+            }
+            Err(_) => {
+                metrics.record_watch_error();
+                progress.set_message("Last event: error");
+            }
+        }
+
+        if let Ok(Event::Apply(_) | Event::InitApply(_) | Event::Delete(_)) = &item {
+            events.record()?;
+        }
+
+        if item.as_ref().is_ok_and(is_recovery) {
+            errors.reset();
+        }
+
+        match item {
+            // Objects listed after a (re)start are processed one by one as they arrive,
+            // so the whole list is never held in memory.
+            Ok(Event::Apply(state)) => {
+                if let Some(serialized) = matcher.observe(state)? {
+                    return Ok(serialized);
+                }
+            }
+
+            Ok(Event::InitApply(state)) => {
+                if let Some(serialized) = matcher.observe_listed(state)? {
+                    return Ok(serialized);
+                }
+            }
+
+            Ok(Event::Delete(state)) => matcher.forget(&state),
+
+            Ok(Event::Init) => matcher.start_relist(),
+
+            Ok(Event::InitDone) => {
+                if let Some(serialized) = matcher.finish_relist() {
+                    return Ok(serialized);
+                }
+            }
+
+            Err(error) => {
+                if let Some(interval) = poll_fallback.filter(|_| is_watch_unsupported(&error)) {
+                    progress.suspend(|| {
+                        eprintln!(
+                            "API server does not support watching the resource, polling every {} instead.",
+                            humantime::format_duration(interval)
+                        )
+                    });
+                    let timer = PollTimer::new(interval, options.poll_jitter);
+                    return poll(api, &config, matcher, events, errors, timer, progress).await;
+                }
+
+                if is_expired(&error) {
+                    report_expired(progress);
+                    continue;
+                }
+
+                if is_field_selector_unsupported(&error)
+                    && matcher.select_name_client_side(&mut config.field_selector, progress)
+                {
+                    stream =
+                        Box::pin(runtime::watcher(api.clone(), config.clone()).default_backoff());
+                    continue;
+                }
+
+                let error = errors.record(error)?;
+                progress.suspend(|| {
+                    eprintln!(
+                        "Watcher stream encountered an error and will restart with backoff: {error}."
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Watches the resources through all APIs at once and reports the number of matching resources
+/// and the number of all watched resources, whenever any of them changes.
+/// Returns the matching states, sorted by namespace and name, once at least `target` resources match
+/// at the same time, runs until cancelled without a target.
+/// The minimal age and the confirmation of matches are not supported.
+pub async fn watch_count(
+    apis: Vec<Api<DynamicObject>>,
+    config: Config,
+    mut matcher: Matcher<'_>,
+    options: WatchOptions,
+    target: Option<usize>,
+    progress: &ProgressBar,
+    report: impl Fn(usize, usize),
+) -> Result<Vec<Value>> {
+    let mut errors = ErrorCounter::new(&options);
+
+    // States of the matching resources, or `None` for the other resources, keyed by resource UID, for every API.
+    let mut listed = vec![HashMap::<String, Option<Value>>::new(); apis.len()];
+    // Resources listed again after a (re)start, which replace the previous ones once the list is done.
+    let mut relisted = vec![None::<HashMap<String, Option<Value>>>; apis.len()];
+    // Counts are reported only after every API listed the resources, so that partial counts are never reported.
+    let mut initialized = vec![false; apis.len()];
+    let mut last_reported = None;
+
+    let mut stream = futures::stream::select_all(apis.into_iter().enumerate().map(|(i, api)| {
+        Box::pin(
+            runtime::watcher(api, config.clone())
+                .default_backoff()
+                .map(move |item| (i, item)),
+        )
+    }));
+    while let Some((i, item)) = stream.next().await {
+        match &item {
+            Ok(event) => {
+                matcher.metrics.record_event(event);
+                if is_recovery(event) {
+                    errors.reset();
+                }
+            }
+            Err(_) => matcher.metrics.record_watch_error(),
+        }
+
+        match item {
+            Ok(Event::Init) => {
+                relisted[i] = Some(HashMap::new());
+                continue;
+            }
+
+            Ok(Event::InitApply(state)) => {
+                if let (Some(uid), Some(matching)) =
+                    (state.metadata.uid.clone(), matcher.match_now(&state))
+                {
+                    relisted[i].get_or_insert_default().insert(uid, matching);
+                }
+                continue;
+            }
+
+            Ok(Event::InitDone) => {
+                listed[i] = relisted[i].take().unwrap_or_default();
+                initialized[i] = true;
+            }
+
+            Ok(Event::Apply(state)) => {
+                let Some(uid) = state.metadata.uid.clone() else {
+                    continue;
+                };
+                match matcher.match_now(&state) {
+                    Some(matching) => listed[i].insert(uid, matching),
+                    None => listed[i].remove(&uid),
+                };
+            }
+
+            Ok(Event::Delete(state)) => {
+                if let Some(uid) = &state.metadata.uid {
+                    listed[i].remove(uid);
+                }
+            }
+
+            Err(error) if is_expired(&error) => {
+                report_expired(progress);
+                continue;
+            }
+            Err(error) => {
+                let error = errors.record(error)?;
+                progress.suspend(|| {
+                    eprintln!(
+                        "Watcher stream encountered an error and will restart with backoff: {error}."
+                    )
+                });
+                continue;
+            }
+        }
+
+        if !initialized.iter().all(|initialized| *initialized) {
+            continue;
+        }
+
+        let total = listed.iter().map(HashMap::len).sum::<usize>();
+        let matching = listed
+            .iter()
+            .flat_map(HashMap::values)
+            .filter(|matching| matching.is_some())
+            .count();
+        if last_reported != Some((matching, total)) {
+            last_reported = Some((matching, total));
+            progress.set_message(format!("{matching}/{total} matching"));
+            report(matching, total);
+        }
+
+        if target.is_some_and(|target| matching >= target) {
+            matcher.metrics.record_matched();
+            let mut states = listed
+                .into_iter()
+                .flat_map(HashMap::into_values)
+                .flatten()
+                .collect::<Vec<_>>();
+            states.sort_by_cached_key(display_name);
+            return Ok(states);
+        }
+    }
+
+    anyhow::bail!("Watcher stream finished unexpectedly");
+}
+
+/// Returns whether the resource takes more than `max` bytes when serialized as JSON.
+/// The serialization stops as soon as the size is exceeded, so large resources are never serialized whole.
+fn exceeds_size(state: &DynamicObject, max: usize) -> bool {
+    /// Writer that fails once more than the remaining number of bytes is written.
+    struct Limited(usize);
+
+    impl io::Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 = self
+                .0
+                .checked_sub(buf.len())
+                .ok_or_else(|| io::Error::other("size exceeded"))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    serde_json::to_writer(Limited(max), state).is_err()
+}
+
+/// Checks whether the watch failed because the API server does not support watching the resource.
+fn is_watch_unsupported(error: &watcher::Error) -> bool {
+    /// HTTP status returned for unsupported verbs.
+    const METHOD_NOT_ALLOWED: u16 = 405;
+
+    match error {
+        watcher::Error::WatchStartFailed(kube::Error::Api(response))
+        | watcher::Error::WatchFailed(kube::Error::Api(response))
+        | watcher::Error::WatchError(response) => response.code == METHOD_NOT_ALLOWED,
+        _ => false,
+    }
+}
+
+/// Checks whether the resource version of the watch expired (`410 Gone`), e.g. after a long wait.
+/// The watcher then lists the resources again and watches from the new resource version,
+/// so this is not counted towards [`WatchOptions::max_errors`] and [`WatchOptions::error_threshold`].
+fn is_expired(error: &watcher::Error) -> bool {
+    /// HTTP status returned for expired resource versions.
+    const GONE: u16 = 410;
+
+    matches!(error, watcher::Error::WatchError(response) if response.code == GONE)
+}
+
+fn report_expired(progress: &ProgressBar) {
+    progress.suspend(|| {
+        eprintln!("Resource version of the watch expired, the resources will be listed again.")
+    });
+}
+
+/// Checks whether the API server rejected the field selector, e.g. for a custom resource
+/// served by an aggregated API server that does not support selecting resources by name.
+fn is_field_selector_unsupported(error: &watcher::Error) -> bool {
+    match error {
+        watcher::Error::InitialListFailed(kube::Error::Api(response))
+        | watcher::Error::WatchStartFailed(kube::Error::Api(response))
+        | watcher::Error::WatchFailed(kube::Error::Api(response))
+        | watcher::Error::WatchError(response) => rejects_field_selector(response),
+        _ => false,
+    }
+}
+
+fn rejects_field_selector(response: &ErrorResponse) -> bool {
+    /// HTTP status returned for invalid requests.
+    const BAD_REQUEST: u16 = 400;
+
+    response.code == BAD_REQUEST && response.message.contains("field label not supported")
+}
+
+/// Lists the resources, selecting them by name client-side and listing them again
+/// if the API server does not support selecting them by name, see [`is_field_selector_unsupported`].
+async fn list(
+    api: &Api<DynamicObject>,
+    params: &mut ListParams,
+    matcher: &mut Matcher<'_>,
+    progress: &ProgressBar,
+) -> kube::Result<ObjectList<DynamicObject>> {
+    let result = api.list(params).await;
+    match &result {
+        Err(kube::Error::Api(response))
+            if rejects_field_selector(response)
+                && matcher.select_name_client_side(&mut params.field_selector, progress) =>
+        {
+            api.list(params).await
+        }
+        _ => result,
+    }
+}
+
+fn list_params(config: &Config) -> ListParams {
+    ListParams {
+        field_selector: config.field_selector.clone(),
+        label_selector: config.label_selector.clone(),
+        ..Default::default()
+    }
+}
+
+/// Lists the resources at the intervals of the timer until one of them matches.
+/// Every listed resource counts as an event. Failed lists are retried at the next interval.
+async fn poll(
+    api: Api<DynamicObject>,
+    config: &Config,
+    mut matcher: Matcher<'_>,
+    mut events: EventCounter,
+    mut errors: ErrorCounter,
+    mut timer: PollTimer,
+    progress: &ProgressBar,
+) -> Result<Value> {
+    let mut params = list_params(config);
+
+    loop {
+        timer.tick().await;
+
+        let list = match list(&api, &mut params, &mut matcher, progress).await {
+            Ok(list) => list,
+            Err(error) => {
+                matcher.metrics.record_watch_error();
+                progress.set_message("Last poll: error");
+                let error = errors.record(error)?;
+                progress.suspend(|| {
+                    eprintln!(
+                        "Failed to list resources, retrying in {}: {error}.",
+                        humantime::format_duration(timer.delay())
+                    )
+                });
+                continue;
+            }
+        };
+
+        errors.reset();
+        progress.set_message(format!("Last poll: {} resources", list.items.len()));
+        // Resources that are not old enough or not confirmed yet are checked again in the next poll.
+        matcher.start_relist();
+        for state in list.items {
+            matcher.metrics.record_listed(&state);
+            events.record()?;
+            if let Some(serialized) = matcher.observe_listed(state)? {
+                return Ok(serialized);
+            }
+        }
+        if let Some(serialized) = matcher.finish_relist() {
+            return Ok(serialized);
+        }
+    }
+}
+
+/// Gets the subresource of the resource repeatedly and returns the first matching payload.
+/// The API server does not support watching subresources, so with [`WatchMode::Poll`] the subresource is fetched
+/// at the interval, and with [`WatchMode::CheckOnce`] only once. Every fetched payload counts as an event.
+/// Failed requests, e.g. because the resource does not exist yet, are retried at the next interval.
+pub async fn poll_subresource(
+    api: Api<DynamicObject>,
+    name: &str,
+    path: &SubresourcePath,
+    mut matcher: Matcher<'_>,
+    options: WatchOptions,
+    progress: &ProgressBar,
+) -> Result<Value> {
+    let interval = match options.mode {
+        WatchMode::Poll(interval) => interval,
+        WatchMode::CheckOnce => {
+            let state = api
+                .get_subresource(path.as_str(), name)
+                .await
+                .with_context(|| format!("failed to get subresource `{}`", path.as_str()))?;
+            return matcher
+                .observe(state)?
+                .ok_or_else(|| WaitError::NotMatched.into());
+        }
+        WatchMode::Watch { .. } => unreachable!("subresources cannot be watched"),
+    };
+
+    let mut events = EventCounter {
+        count: 0,
+        max: options.max_events,
+    };
+    let mut errors = ErrorCounter::new(&options);
+    let mut timer = PollTimer::new(interval, options.poll_jitter);
+
+    loop {
+        timer.tick().await;
+
+        let state = match api.get_subresource(path.as_str(), name).await {
+            Ok(state) => state,
+            Err(error) => {
+                matcher.metrics.record_watch_error();
+                progress.set_message("Last poll: error");
+                let error = errors.record(error)?;
+                progress.suspend(|| {
+                    eprintln!(
+                        "Failed to get subresource `{}`, retrying in {}: {error}.",
+                        path.as_str(),
+                        humantime::format_duration(timer.delay())
+                    )
+                });
+                continue;
+            }
+        };
+
+        errors.reset();
+        progress.set_message("Last poll: fetched");
+        matcher.metrics.record_listed(&state);
+        events.record()?;
+        // A match that is not confirmed yet is checked again in the next poll.
+        if let Some(serialized) = matcher.observe(state)? {
+            return Ok(serialized);
+        }
+    }
+}
+
+/// Lists the resources once through all APIs and returns all matching states.
+/// Resources that are not old enough yet do not match.
+pub async fn list_matching(
+    apis: Vec<Api<DynamicObject>>,
+    config: &Config,
+    mut matcher: Matcher<'_>,
+    progress: &ProgressBar,
+) -> Result<Vec<Value>> {
+    let mut params = list_params(config);
+
+    let mut states = Vec::new();
+    for api in apis {
+        let list = list(&api, &mut params, &mut matcher, progress)
+            .await
+            .context("failed to list resources")?;
+        for state in list.items {
+            states.extend(matcher.observe(state)?);
+        }
+    }
+
+    Ok(states)
+}
+
+/// Lists the resources once and returns the first matching state, or the last one, see [`Matcher::with_last_listed_match`].
+/// Resources that are not old enough yet do not match.
+async fn check_once(
+    api: Api<DynamicObject>,
+    config: &Config,
+    mut matcher: Matcher<'_>,
+    progress: &ProgressBar,
+) -> Result<Value> {
+    let list = list(&api, &mut list_params(config), &mut matcher, progress)
+        .await
+        .context("failed to list resources")?;
+
+    matcher.start_relist();
+    for state in list.items {
+        if let Some(serialized) = matcher.observe_listed(state)? {
+            return Ok(serialized);
+        }
+    }
+
+    matcher
+        .finish_relist()
+        .ok_or_else(|| WaitError::NotMatched.into())
+}
+
+/// Returns the namespace and the name of the resource, e.g. `default/my-pod`, for diagnostics.
+pub fn display_name(state: &Value) -> String {
+    let metadata = &state["metadata"];
+    match (metadata["namespace"].as_str(), metadata["name"].as_str()) {
+        (Some(namespace), Some(name)) => format!("{namespace}/{name}"),
+        (None, Some(name)) => name.into(),
+        _ => "unknown".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use k8s_openapi::{api::core::v1::Pod, apimachinery::pkg::apis::meta::v1::Time};
+    use kube::api::ApiResource;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::testing::{self, FakeApi, Response};
+
+    fn error_counter(max_consecutive: u64) -> ErrorCounter {
+        ErrorCounter {
+            consecutive: 0,
+            total: 0,
+            max_consecutive: Some(max_consecutive),
+            max_total: None,
+        }
+    }
+
+    fn error() -> std::io::Error {
+        std::io::Error::other("connection refused")
+    }
+
+    fn object_filter() -> ObjectFilter {
+        ObjectFilter {
+            name_glob: None,
+            names: Vec::new(),
+            since: None,
+            min_age: None,
+            uid: None,
+            owner_uid: None,
+        }
+    }
+
+    fn pod(name: &str, phase: &str) -> DynamicObject {
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": { "name": name, "uid": name },
+            "status": { "phase": phase },
+        }))
+        .unwrap()
+    }
+
+    fn running_matcher(metrics: &Metrics) -> Matcher<'_> {
+        let filter = Filter::parse(serde_yaml::from_str("status: { phase: Running }").unwrap());
+        Matcher::new(object_filter(), filter.unwrap(), None, None, metrics)
+    }
+
+    #[test]
+    fn name_glob_selects_resources() {
+        let filter = ObjectFilter {
+            name_glob: Some(Pattern::new("web-*").unwrap()),
+            ..object_filter()
+        };
+        assert!(filter.matches(&pod("web-1", "Running")));
+        assert!(filter.matches(&pod("web-", "Running")));
+        assert!(!filter.matches(&pod("web", "Running")));
+        assert!(!filter.matches(&pod("api-1", "Running")));
+    }
+
+    #[test]
+    fn last_listed_match_is_returned_when_the_list_is_done() {
+        let metrics = Metrics::default();
+        let mut matcher = running_matcher(&metrics).with_last_listed_match(true);
+
+        matcher.start_relist();
+        assert!(matcher
+            .observe_listed(pod("a", "Running"))
+            .unwrap()
+            .is_none());
+        assert!(matcher
+            .observe_listed(pod("b", "Running"))
+            .unwrap()
+            .is_none());
+        assert!(matcher
+            .observe_listed(pod("c", "Pending"))
+            .unwrap()
+            .is_none());
+        let matching = matcher.finish_relist().unwrap();
+        assert_eq!(matching["metadata"]["name"], "b");
+    }
+
+    #[test]
+    fn restarted_list_forgets_the_previous_match() {
+        let metrics = Metrics::default();
+        let mut matcher = running_matcher(&metrics).with_last_listed_match(true);
+
+        matcher.start_relist();
+        assert!(matcher
+            .observe_listed(pod("a", "Running"))
+            .unwrap()
+            .is_none());
+        // The watch restarts before the list is done, and the resource no longer matches.
+        matcher.start_relist();
+        assert!(matcher
+            .observe_listed(pod("a", "Pending"))
+            .unwrap()
+            .is_none());
+        assert!(matcher.finish_relist().is_none());
+    }
+
+    #[test]
+    fn poll_delays_stay_within_jitter() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let timer = PollTimer::new(Duration::from_secs(10), 0.1);
+        for _ in 0..1000 {
+            let delay = timer.next_delay(&mut rng);
+            assert!(delay >= Duration::from_secs(9) && delay <= Duration::from_secs(11));
+        }
+
+        let timer = PollTimer::new(Duration::from_secs(10), 0.0);
+        assert_eq!(timer.next_delay(&mut rng), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn poll_delays_are_never_zero() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let timer = PollTimer::new(Duration::from_micros(500), 0.99);
+        for _ in 0..1000 {
+            assert!(timer.next_delay(&mut rng) >= MIN_POLL_DELAY);
+        }
+    }
+
+    #[test]
+    fn only_events_after_init_mean_recovery() {
+        assert!(!is_recovery(&Event::<()>::Init));
+        assert!(is_recovery(&Event::InitApply(())));
+        assert!(is_recovery(&Event::<()>::InitDone));
+        assert!(is_recovery(&Event::Apply(())));
+        assert!(is_recovery(&Event::Delete(())));
+    }
+
+    #[test]
+    fn restart_does_not_reset_consecutive_errors() {
+        // Every error is followed by the `Init` of the restarted watch.
+        let mut errors = error_counter(2);
+        for _ in 0..2 {
+            errors.record(error()).unwrap();
+            if is_recovery(&Event::<()>::Init) {
+                errors.reset();
+            }
+        }
+        assert!(errors.record(error()).is_err());
+
+        // The restarted watch listed the resources before the next error.
+        let mut errors = error_counter(2);
+        for _ in 0..3 {
+            errors.record(error()).unwrap();
+            if is_recovery(&Event::<()>::InitDone) {
+                errors.reset();
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn young_resources_match_once_old_enough() {
+        let metrics = Metrics::default();
+        let mut matcher = running_matcher(&metrics);
+        matcher.object_filter.min_age = Some(Duration::from_secs(60));
+
+        let mut young = pod("young", "Running");
+        young.metadata.creation_timestamp = Some(Time(Utc::now() - Duration::from_secs(20)));
+        let started = Instant::now();
+        assert!(matcher.observe(young.clone()).unwrap().is_none());
+        let (uid, at) = matcher.next_pending().unwrap();
+        assert_eq!(uid, "young");
+        assert!(at > started + Duration::from_secs(39) && at <= started + Duration::from_secs(40));
+
+        time::advance(Duration::from_secs(40)).await;
+        let matching = matcher.take_pending(&uid).unwrap();
+        assert_eq!(matching["metadata"]["name"], "young");
+        assert!(matcher.next_pending().is_none());
+
+        let mut old = pod("old", "Running");
+        old.metadata.creation_timestamp = Some(Time(Utc::now() - Duration::from_secs(120)));
+        assert!(matcher.observe(old).unwrap().is_some());
+
+        // Resources without a creation timestamp are treated as just created.
+        assert!(matcher.observe(pod("new", "Running")).unwrap().is_none());
+        let (_, at) = matcher.next_pending().unwrap();
+        assert_eq!(at, Instant::now() + Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn young_resources_are_dropped_when_they_stop_matching() {
+        let metrics = Metrics::default();
+        let mut matcher = running_matcher(&metrics);
+        matcher.object_filter.min_age = Some(Duration::from_secs(60));
+
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_none());
+        assert!(matcher.next_pending().is_some());
+        assert!(matcher.observe(pod("a", "Pending")).unwrap().is_none());
+        assert!(matcher.next_pending().is_none());
+    }
+
+    #[tokio::test]
+    async fn matching_listed_states_are_counted() {
+        let api = FakeApi::start(|request| match request.path() {
+            "/api/v1/namespaces/default/pods" => Response::Json(
+                200,
+                testing::pod_list(vec![
+                    testing::pod("a", "Running"),
+                    testing::pod("b", "Pending"),
+                    testing::pod("c", "Running"),
+                ]),
+            ),
+            _ => Response::Json(404, testing::status(404, "NotFound", "not found")),
+        })
+        .await;
+        let metrics = Metrics::default();
+
+        let states = list_matching(
+            vec![testing::pods(api.client())],
+            &Config::default(),
+            running_matcher(&metrics),
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap();
+        let names = states
+            .iter()
+            .map(|state| state["metadata"]["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "c"]);
+    }
+
+    #[test]
+    fn owner_uid_selects_owned_resources() {
+        let filter = ObjectFilter {
+            owner_uid: Some("rs-1".into()),
+            ..object_filter()
+        };
+        let owned_by = |uid: &str| -> DynamicObject {
+            serde_json::from_value(serde_json::json!({
+                "apiVersion": "v1",
+                "kind": "Pod",
+                "metadata": {
+                    "name": "web-1",
+                    "ownerReferences": [{
+                        "apiVersion": "apps/v1",
+                        "kind": "ReplicaSet",
+                        "name": "web",
+                        "uid": uid,
+                    }],
+                },
+            }))
+            .unwrap()
+        };
+        assert!(filter.matches(&owned_by("rs-1")));
+        assert!(!filter.matches(&owned_by("rs-2")));
+        assert!(!filter.matches(&pod("web-1", "Running")));
+    }
+
+    #[test]
+    fn event_limit_fails_after_the_last_allowed_event() {
+        let mut events = EventCounter {
+            count: 0,
+            max: Some(3),
+        };
+        for _ in 0..3 {
+            events.record().unwrap();
+        }
+        let error = events.record().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no matching resource state was found within the limit of 3 events"
+        );
+        // Unlike the timeout, reaching the limit is a regular failure.
+        assert!(error.downcast_ref::<WaitError>().is_none());
+
+        let mut events = EventCounter {
+            count: 0,
+            max: None,
+        };
+        for _ in 0..1000 {
+            events.record().unwrap();
+        }
+    }
+
+    fn watch_options(mode: WatchMode) -> WatchOptions {
+        WatchOptions {
+            mode,
+            max_events: None,
+            max_errors: None,
+            error_threshold: None,
+            deadline: None,
+            poll_jitter: 0.0,
+        }
+    }
+
+    /// Starts a fake API answering the pod lists with the responses in order, repeating the last one.
+    async fn fake_lists(responses: Vec<(u16, serde_json::Value)>) -> FakeApi {
+        let lists = AtomicUsize::new(0);
+        FakeApi::start(move |_| {
+            let i = lists.fetch_add(1, Ordering::SeqCst);
+            let (status, body) = responses[i.min(responses.len() - 1)].clone();
+            Response::Json(status, body)
+        })
+        .await
+    }
+
+    fn internal_error() -> (u16, serde_json::Value) {
+        (500, testing::status(500, "InternalError", "etcd is down"))
+    }
+
+    #[tokio::test]
+    async fn polls_until_the_state_matches() {
+        let api = fake_lists(vec![
+            internal_error(),
+            (200, testing::pod_list(vec![testing::pod("web", "Pending")])),
+            (200, testing::pod_list(vec![testing::pod("web", "Running")])),
+        ])
+        .await;
+        let metrics = Metrics::default();
+
+        let state = watch_for_condition_met(
+            testing::pods(api.client()),
+            Config::default(),
+            running_matcher(&metrics),
+            watch_options(WatchMode::Poll(Duration::from_millis(10))),
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state["status"]["phase"], "Running");
+        assert_eq!(api.requests().len(), 3);
+        assert!(!api.requests().iter().any(|request| request.is_watch()));
+    }
+
+    #[tokio::test]
+    async fn polling_gives_up_after_consecutive_errors() {
+        let api = fake_lists(vec![internal_error()]).await;
+        let metrics = Metrics::default();
+
+        let error = watch_for_condition_met(
+            testing::pods(api.client()),
+            Config::default(),
+            running_matcher(&metrics),
+            WatchOptions {
+                max_errors: Some(2),
+                ..watch_options(WatchMode::Poll(Duration::from_millis(10)))
+            },
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.to_string(), "giving up after 3 consecutive errors");
+        assert_eq!(api.requests().len(), 3);
+    }
+
+    #[test]
+    fn uid_selects_a_single_resource() {
+        let filter = ObjectFilter {
+            uid: Some("web-1".into()),
+            ..object_filter()
+        };
+        assert!(filter.matches(&pod("web-1", "Running")));
+        assert!(!filter.matches(&pod("web-2", "Running")));
+
+        let mut without_uid = pod("web-1", "Running");
+        without_uid.metadata.uid = None;
+        assert!(!filter.matches(&without_uid));
+    }
+
+    #[tokio::test]
+    async fn match_in_any_namespace_is_found() {
+        let api = FakeApi::start(|request| {
+            let pod = match request.path() {
+                "/api/v1/namespaces/team-a/pods" => testing::pod("web", "Pending"),
+                "/api/v1/namespaces/team-b/pods" => testing::pod("api", "Running"),
+                _ => return Response::Json(404, testing::status(404, "NotFound", "not found")),
+            };
+            Response::Json(200, testing::pod_list(vec![pod]))
+        })
+        .await;
+        let metrics = Metrics::default();
+        let api_resource = ApiResource::erase::<Pod>(&());
+        let apis = ["team-a", "team-b"]
+            .into_iter()
+            .map(|namespace| Api::namespaced_with(api.client(), namespace, &api_resource))
+            .collect();
+
+        let state = watch_any_for_condition_met(
+            apis,
+            Config::default(),
+            running_matcher(&metrics),
+            watch_options(WatchMode::Poll(Duration::from_millis(10))),
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state["metadata"]["name"], "api");
+        assert!(api
+            .requests()
+            .iter()
+            .any(|request| request.path() == "/api/v1/namespaces/team-a/pods"));
+    }
+
+    #[test]
+    fn consecutive_errors_fail_past_the_threshold() {
+        let mut errors = error_counter(2);
+        errors.record(error()).unwrap();
+        errors.record(error()).unwrap();
+        let failure = errors.record(error()).unwrap_err();
+        assert_eq!(failure.to_string(), "giving up after 3 consecutive errors");
+        assert_eq!(failure.root_cause().to_string(), "connection refused");
+    }
+
+    #[test]
+    fn reset_forgets_only_consecutive_errors() {
+        let mut errors = ErrorCounter {
+            max_total: Some(3),
+            ..error_counter(1)
+        };
+        errors.record(error()).unwrap();
+        errors.reset();
+        errors.record(error()).unwrap();
+        errors.reset();
+        errors.record(error()).unwrap();
+        errors.reset();
+        let failure = errors.record(error()).unwrap_err();
+        assert_eq!(failure.to_string(), "giving up after 4 errors in total");
+    }
+
+    #[test]
+    fn errors_are_tolerated_without_limits() {
+        let mut errors = ErrorCounter::new(&watch_options(WatchMode::CheckOnce));
+        for _ in 0..1000 {
+            errors.record(error()).unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn matches_are_confirmed_with_the_latest_state() {
+        let metrics = Metrics::default();
+        let mut matcher =
+            running_matcher(&metrics).with_confirm_after(Some(Duration::from_secs(10)));
+        let version = |resource_version: &str| {
+            let mut state = pod("a", "Running");
+            state.metadata.resource_version = Some(resource_version.into());
+            state
+        };
+
+        let started = Instant::now();
+        assert!(matcher.observe(version("1")).unwrap().is_none());
+        time::advance(Duration::from_secs(5)).await;
+        // Later matching states do not restart the confirmation.
+        assert!(matcher.observe(version("2")).unwrap().is_none());
+        let (uid, at) = matcher.next_pending().unwrap();
+        assert_eq!(at, started + Duration::from_secs(10));
+
+        time::advance(Duration::from_secs(5)).await;
+        let confirmed = matcher.take_pending(&uid).unwrap();
+        assert_eq!(confirmed["metadata"]["resourceVersion"], "2");
+        assert!(matcher.next_pending().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn matches_are_not_confirmed_when_the_state_stops_matching() {
+        let metrics = Metrics::default();
+        let mut matcher =
+            running_matcher(&metrics).with_confirm_after(Some(Duration::from_secs(10)));
+
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_none());
+        time::advance(Duration::from_secs(5)).await;
+        assert!(matcher.observe(pod("a", "Pending")).unwrap().is_none());
+        assert!(matcher.next_pending().is_none());
+
+        // The confirmation starts again with the next match.
+        let restarted = Instant::now();
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_none());
+        let (_, at) = matcher.next_pending().unwrap();
+        assert_eq!(at, restarted + Duration::from_secs(10));
+    }
+
+    #[test]
+    fn explanation_shows_the_filter_and_conditions() {
+        console::set_colors_enabled_stderr(false);
+        let metrics = Metrics::default();
+        let filter = serde_yaml::from_str("status: { phase: Running, podIP: 10.0.0.1 }").unwrap();
+        let matcher = Matcher::new(
+            object_filter(),
+            Filter::parse(filter).unwrap(),
+            Some("condition=PodScheduled".parse().unwrap()),
+            None,
+            &metrics,
+        );
+        let state = serde_yaml::from_str(
+            "metadata: { name: my-pod, namespace: default }
+status:
+  phase: Pending
+  conditions: [{ type: PodScheduled, status: \"True\" }]",
+        )
+        .unwrap();
+
+        assert_eq!(
+            matcher.explain(&state),
+            "State of default/my-pod does not match:
+✗ status
+  ✗ phase: expected \"Running\", found \"Pending\"
+  ✗ podIP: missing
+✓ `--for` condition
+"
+        );
+    }
+
+    fn named_filter(name: &str, filter: &str) -> NamedFilter {
+        NamedFilter {
+            name: name.into(),
+            filter: Filter::parse(serde_yaml::from_str(filter).unwrap()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn all_of_filters_must_match_the_same_state() {
+        let metrics = Metrics::default();
+        let mut matcher = Matcher::new(object_filter(), Filter::any(), None, None, &metrics)
+            .with_all_of(vec![
+                named_filter("running.yaml", "status: { phase: Running }"),
+                named_filter("ready.yaml", "status: { ready: true }"),
+            ]);
+        let state = |status: &str| -> DynamicObject {
+            serde_yaml::from_str(&format!(
+                "{{ metadata: {{ name: web, uid: web }}, status: {status} }}"
+            ))
+            .unwrap()
+        };
+
+        assert!(matcher
+            .observe(state("{ phase: Running }"))
+            .unwrap()
+            .is_none());
+        assert!(matcher.observe(state("{ ready: true }")).unwrap().is_none());
+        assert!(matcher
+            .observe(state("{ phase: Running, ready: true }"))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn large_integers_and_numeric_strings_keep_their_values() {
+        let metrics = Metrics::default();
+        let filter = "spec: { generation: 18446744073709551615, offset: -9223372036854775808, code: \"0123\" }";
+        let mut matcher = Matcher::new(
+            object_filter(),
+            Filter::parse(serde_yaml::from_str(filter).unwrap()).unwrap(),
+            None,
+            None,
+            &metrics,
+        );
+        let state = |generation: u64| -> DynamicObject {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": "web", "uid": "web" },
+                "spec": { "generation": generation, "offset": i64::MIN, "code": "0123" },
+            }))
+            .unwrap()
+        };
+
+        assert!(matcher.observe(state(u64::MAX - 1)).unwrap().is_none());
+        let matching = matcher.observe(state(u64::MAX)).unwrap().unwrap();
+        assert_eq!(matching["spec"]["generation"].as_u64(), Some(u64::MAX));
+        assert_eq!(matching["spec"]["offset"].as_i64(), Some(i64::MIN));
+        assert_eq!(matching["spec"]["code"].as_str(), Some("0123"));
+    }
+
+    #[test]
+    fn status_changes_only_skips_unchanged_statuses() {
+        let metrics = Metrics::default();
+        let mut matcher = running_matcher(&metrics).with_status_changes_only(true);
+        matcher.filter = Filter::any();
+
+        assert!(matcher.observe(pod("a", "Pending")).unwrap().is_some());
+        // Changes outside of the status are not matched.
+        let mut relabeled = pod("a", "Pending");
+        relabeled.metadata.labels = Some([("app".into(), "web".into())].into());
+        assert!(matcher.observe(relabeled).unwrap().is_none());
+        // Statuses are tracked for every resource separately.
+        assert!(matcher.observe(pod("b", "Pending")).unwrap().is_some());
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_some());
+    }
+
+    #[test]
+    fn changed_values_match_only_after_the_first_state() {
+        let metrics = Metrics::default();
+        let mut matcher =
+            running_matcher(&metrics).with_changed(Some(".status.phase".parse().unwrap()));
+
+        // The first observed state never matches, even when it passes the filter.
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_none());
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_none());
+        // The initial value is recorded for every resource separately.
+        assert!(matcher.observe(pod("b", "Pending")).unwrap().is_none());
+        assert!(matcher.observe(pod("b", "Running")).unwrap().is_some());
+    }
+
+    #[test]
+    fn values_missing_at_first_change_once_present() {
+        let metrics = Metrics::default();
+        let mut matcher =
+            running_matcher(&metrics).with_changed(Some(".status.podIP".parse().unwrap()));
+
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_none());
+        let mut assigned = pod("a", "Running");
+        assigned.data["status"]["podIP"] = "10.0.0.1".into();
+        assert!(matcher.observe(assigned).unwrap().is_some());
+    }
+
+    #[test]
+    fn fail_if_fails_the_wait() {
+        let metrics = Metrics::default();
+        let failed = Filter::parse(serde_yaml::from_str("status: { phase: Failed }").unwrap());
+        let mut matcher = running_matcher(&metrics).with_fail_if(vec![NamedFilter {
+            name: "failed".into(),
+            filter: failed.unwrap(),
+        }]);
+
+        assert!(matcher.observe(pod("a", "Pending")).unwrap().is_none());
+        let error = matcher.observe(pod("a", "Failed")).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(WaitError::FailIfMatched { resource, filter }) if resource == "a" && filter == "failed"
+        ));
+    }
+
+    fn watch_error(code: u16) -> watcher::Error {
+        watcher::Error::WatchError(ErrorResponse {
+            status: "Failure".into(),
+            message: "too old resource version".into(),
+            reason: "Expired".into(),
+            code,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_versions_relist_without_resetting_matches() {
+        assert!(is_expired(&watch_error(410)));
+        assert!(!is_expired(&watch_error(500)));
+
+        let metrics = Metrics::default();
+        let mut matcher =
+            running_matcher(&metrics).with_confirm_after(Some(Duration::from_secs(10)));
+        let started = Instant::now();
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_none());
+        assert!(matcher.observe(pod("b", "Running")).unwrap().is_none());
+
+        // After the resource version expires, the resources are listed again.
+        time::advance(Duration::from_secs(5)).await;
+        matcher.start_relist();
+        assert!(matcher
+            .observe_listed(pod("a", "Running"))
+            .unwrap()
+            .is_none());
+        assert!(matcher.finish_relist().is_none());
+
+        // The confirmation of the listed resource goes on, the resource that is gone is forgotten.
+        let (uid, at) = matcher.next_pending().unwrap();
+        assert_eq!(uid, "a");
+        assert_eq!(at, started + Duration::from_secs(10));
+        time::advance(Duration::from_secs(5)).await;
+        assert!(matcher.take_pending(&uid).is_some());
+        assert!(matcher.next_pending().is_none());
+    }
+
+    #[tokio::test]
+    async fn subresources_are_polled_until_the_payload_matches() {
+        let api = fake_lists(vec![
+            (
+                404,
+                testing::status(404, "NotFound", "pods \"web\" not found"),
+            ),
+            (200, testing::pod("web", "Pending")),
+            (200, testing::pod("web", "Running")),
+        ])
+        .await;
+        let metrics = Metrics::default();
+
+        let state = poll_subresource(
+            testing::pods(api.client()),
+            "web",
+            &"metrics/latest".parse().unwrap(),
+            running_matcher(&metrics),
+            watch_options(WatchMode::Poll(Duration::from_millis(10))),
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state["status"]["phase"], "Running");
+        let requests = api.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|request| request.path() == "/api/v1/namespaces/default/pods/web/metrics/latest"));
+    }
+
+    #[test]
+    fn any_name_selects_the_named_resources() {
+        let filter = ObjectFilter {
+            names: vec!["leader-0".into(), "leader-1".into()],
+            ..object_filter()
+        };
+        assert!(filter.matches(&pod("leader-0", "Running")));
+        assert!(filter.matches(&pod("leader-1", "Running")));
+        assert!(!filter.matches(&pod("leader-2", "Running")));
+        assert!(!filter.matches(&pod("leader", "Running")));
+    }
+
+    #[test]
+    fn matcher_checks_the_object_filter_first() {
+        let metrics = Metrics::default();
+        let mut matcher = running_matcher(&metrics);
+        matcher.object_filter.names = vec!["b".into()];
+
+        assert!(matcher.observe(pod("a", "Running")).unwrap().is_none());
+        assert!(matcher.observe(pod("b", "Pending")).unwrap().is_none());
+        let matching = matcher.observe(pod("b", "Running")).unwrap().unwrap();
+        assert_eq!(matching["metadata"]["name"], "b");
+    }
+
+    #[tokio::test]
+    async fn rejected_field_selectors_fall_back_to_client_side_names() {
+        let api = FakeApi::start(|request| {
+            if request.query("fieldSelector").is_some() {
+                let message = "field label not supported: metadata.name";
+                return Response::Json(400, testing::status(400, "BadRequest", message));
+            }
+            if request.is_watch() {
+                return Response::Events(Vec::new());
+            }
+            Response::Json(
+                200,
+                testing::pod_list(vec![
+                    testing::pod("other", "Running"),
+                    testing::pod("web", "Running"),
+                ]),
+            )
+        })
+        .await;
+
+        for mode in [
+            WatchMode::Watch {
+                poll_fallback: None,
+            },
+            WatchMode::Poll(Duration::from_millis(10)),
+        ] {
+            let metrics = Metrics::default();
+            let state = watch_for_condition_met(
+                testing::pods(api.client()),
+                Config::default().fields("metadata.name=web"),
+                running_matcher(&metrics),
+                watch_options(mode),
+                &ProgressBar::hidden(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(state["metadata"]["name"], "web");
+        }
+
+        let requests = api.requests();
+        let lists = requests.iter().filter(|request| !request.is_watch());
+        let selectors = lists
+            .map(|request| request.query("fieldSelector"))
+            .collect::<Vec<_>>();
+        let selected = Some("metadata.name=web".to_string());
+        assert_eq!(
+            selectors,
+            [selected.clone(), None, selected, None],
+            "{requests:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn distinct_ready_resources_are_counted() {
+        let ready = |name: &str, ready: bool| {
+            let mut pod = testing::pod(name, "Running");
+            let status = if ready { "True" } else { "False" };
+            pod["status"]["conditions"] =
+                serde_json::json!([{ "type": "Ready", "status": status }]);
+            pod
+        };
+        let events = vec![
+            // Another ready state of a resource already counted.
+            testing::event("MODIFIED", ready("a", true)),
+            testing::event("MODIFIED", ready("b", true)),
+            testing::event("MODIFIED", ready("a", false)),
+            testing::event("MODIFIED", ready("c", true)),
+            testing::event("MODIFIED", ready("a", true)),
+        ];
+        let api = FakeApi::start(move |request| {
+            if request.is_watch() {
+                return Response::Events(events.clone());
+            }
+            Response::Json(
+                200,
+                testing::pod_list(vec![ready("a", true), ready("b", false), ready("c", false)]),
+            )
+        })
+        .await;
+        let metrics = Metrics::default();
+        let matcher = Matcher::new(
+            object_filter(),
+            Filter::any(),
+            Some("ready".parse().unwrap()),
+            None,
+            &metrics,
+        );
+
+        let reports = RefCell::new(Vec::new());
+        let states = watch_count(
+            vec![testing::pods(api.client())],
+            Config::default(),
+            matcher,
+            watch_options(WatchMode::Watch {
+                poll_fallback: None,
+            }),
+            Some(3),
+            &ProgressBar::hidden(),
+            |matching, total| reports.borrow_mut().push((matching, total)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            reports.into_inner(),
+            [(1, 3), (2, 3), (1, 3), (2, 3), (3, 3)]
+        );
+        let names = states
+            .iter()
+            .map(|state| state["metadata"]["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn first_mismatch_follows_the_order_of_the_explanation() {
+        let metrics = Metrics::default();
+        let filter = serde_yaml::from_str("status: { phase: Running }").unwrap();
+        let matcher = Matcher::new(
+            object_filter(),
+            Filter::parse(filter).unwrap(),
+            Some("ready".parse().unwrap()),
+            None,
+            &metrics,
+        )
+        .with_all_of(vec![named_filter("ip.yaml", "status: { podIP: 10.0.0.1 }")]);
+
+        let mismatch = |state: &str| matcher.first_mismatch(&serde_yaml::from_str(state).unwrap());
+        assert_eq!(mismatch("status: { phase: Pending }"), ".status.phase");
+        assert_eq!(mismatch("status: { phase: Running }"), "`--for` condition");
+        assert_eq!(
+            mismatch("status: { phase: Running, conditions: [{ type: Ready, status: \"True\" }] }"),
+            "`--all-of` ip.yaml at .status.podIP"
+        );
+    }
+}
@@ -0,0 +1,449 @@
+//! Tests running the binary against a fake API server.
+
+use std::process::{Output, Stdio};
+
+use serde::Deserialize;
+use serde_json::Value;
+use testing::{FakeApi, Response};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+#[path = "../src/testing.rs"]
+#[allow(dead_code)]
+mod testing;
+
+/// Environment variable with the kubeconfig of the fake API server.
+const KUBECONFIG_ENV: &str = "K8S_WAIT_TEST_KUBECONFIG";
+
+/// Starts a fake API server serving pods, listed with the given states and watched without any events.
+async fn serve_pods(pods: Vec<Value>) -> FakeApi {
+    FakeApi::start(move |request| {
+        if let Some(response) = testing::discovery(request, &[("v1", "Pod")]) {
+            return response;
+        }
+        if request.is_watch() {
+            return Response::Events(Vec::new());
+        }
+        Response::Json(200, testing::pod_list(pods.clone()))
+    })
+    .await
+}
+
+fn command(api: &FakeApi, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_k8s-wait-full"));
+    command
+        .args(args)
+        .args(["--kubeconfig-from-env", KUBECONFIG_ENV])
+        .env(KUBECONFIG_ENV, api.kubeconfig())
+        .env_remove("K8S_WAIT_KIND")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    command
+}
+
+/// Runs the binary with the state filter on standard input.
+async fn run(api: &FakeApi, args: &[&str], filter: &str) -> Output {
+    output(command(api, args), filter).await
+}
+
+/// Runs the command with the state filter on standard input.
+async fn output(mut command: Command, filter: &str) -> Output {
+    let mut child = command.spawn().unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(filter.as_bytes()).await.unwrap();
+    drop(stdin);
+    child.wait_with_output().await.unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[tokio::test]
+async fn zero_timeout_checks_once() {
+    let api = serve_pods(vec![testing::pod("web", "Pending")]).await;
+    let args = ["Pod", "web", "--timeout", "0"];
+
+    let output = run(&api, &args, "status: { phase: Pending }").await;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert!(stdout(&output).contains("phase: Pending"), "{output:?}");
+    assert!(!api.requests().iter().any(|request| request.is_watch()));
+
+    let output = run(&api, &args, "status: { phase: Running }").await;
+    assert_eq!(output.status.code(), Some(124), "{output:?}");
+    assert_eq!(stdout(&output), "");
+
+    let output = run(
+        &api,
+        &["Pod", "web", "--timeout=-5"],
+        "status: { phase: Running }",
+    )
+    .await;
+    assert_eq!(output.status.code(), Some(124), "{output:?}");
+    assert!(!api.requests().iter().any(|request| request.is_watch()));
+}
+
+#[tokio::test]
+async fn diagnostics_go_only_to_stderr() {
+    let api = FakeApi::start(|request| {
+        let resources = [
+            ("example.com/v1", "Widget"),
+            ("example.com/v1beta1", "Widget"),
+        ];
+        if let Some(response) = testing::discovery(request, &resources) {
+            return response;
+        }
+        if request.is_watch() {
+            return Response::Events(Vec::new());
+        }
+        let mut widget = testing::pod("web", "Ready");
+        widget["apiVersion"] = "example.com/v1beta1".into();
+        widget["kind"] = "Widget".into();
+        Response::Json(200, testing::pod_list(vec![widget]))
+    })
+    .await;
+
+    let output = run(
+        &api,
+        &[
+            "Widget",
+            "web",
+            "--api-version",
+            "example.com/v1beta1",
+            "--print-resource",
+            "--report-stats",
+        ],
+        "status: { phase: Ready }",
+    )
+    .await;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+
+    let state: Value = serde_yaml::from_str(stdout(&output)).unwrap();
+    assert_eq!(state["kind"], "Widget");
+    assert_eq!(state["metadata"]["name"], "web");
+
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("Selected resource:"), "{stderr}");
+    assert!(
+        stderr.contains("Warning: example.com/v1beta1 is not the preferred version of Widget"),
+        "{stderr}"
+    );
+}
+
+#[tokio::test]
+async fn no_color_disables_colored_explanations() {
+    let api = serve_pods(vec![testing::pod("web", "Pending")]).await;
+    let explain = |no_color: bool| {
+        let mut args = vec!["Pod", "web", "--timeout", "0", "--explain"];
+        if no_color {
+            args.push("--no-color");
+        }
+        let mut command = command(&api, &args);
+        // Colors are enabled even though standard error is not a terminal.
+        command.env("CLICOLOR_FORCE", "1");
+        output(command, "status: { phase: Running }")
+    };
+
+    let colored = explain(false).await;
+    let stderr = String::from_utf8(colored.stderr).unwrap();
+    assert!(
+        stderr.contains("\x1b[31m✗ phase: expected \"Running\", found \"Pending\""),
+        "{stderr}"
+    );
+
+    let plain = explain(true).await;
+    let stderr = String::from_utf8(plain.stderr).unwrap();
+    assert!(
+        stderr.contains("✗ phase: expected \"Running\", found \"Pending\""),
+        "{stderr}"
+    );
+    assert!(!stderr.contains('\x1b'), "{stderr}");
+}
+
+/// Returns the pod with the given resourceVersion.
+fn pod_version(phase: &str, resource_version: &str) -> Value {
+    let mut pod = testing::pod("web", phase);
+    pod["metadata"]["resourceVersion"] = resource_version.into();
+    pod
+}
+
+/// Returns the resourceVersions of the YAML documents printed to standard output.
+fn printed_versions(output: &Output) -> Vec<String> {
+    serde_yaml::Deserializer::from_str(stdout(output))
+        .map(|document| {
+            let state = Value::deserialize(document).unwrap();
+            state["metadata"]["resourceVersion"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn follow_prints_every_matching_version_once() {
+    let api = FakeApi::start(|request| {
+        if let Some(response) = testing::discovery(request, &[("v1", "Pod")]) {
+            return response;
+        }
+        if request.is_watch() {
+            let events = [
+                pod_version("Running", "1"),
+                pod_version("Running", "2"),
+                pod_version("Running", "2"),
+                pod_version("Pending", "3"),
+                pod_version("Running", "4"),
+            ];
+            return Response::Events(
+                events
+                    .into_iter()
+                    .map(|pod| testing::event("MODIFIED", pod))
+                    .collect(),
+            );
+        }
+        Response::Json(200, testing::pod_list(vec![pod_version("Running", "1")]))
+    })
+    .await;
+
+    let output = run(
+        &api,
+        &["Pod", "web", "--follow", "--timeout", "2"],
+        "status: { phase: Running }",
+    )
+    .await;
+    // The timeout ends following successfully, as states were printed.
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert_eq!(printed_versions(&output), ["1", "2", "4"]);
+}
+
+#[tokio::test]
+async fn follow_fails_on_timeout_without_a_match() {
+    let api = serve_pods(vec![testing::pod("web", "Pending")]).await;
+
+    let output = run(
+        &api,
+        &["Pod", "web", "--follow", "--timeout", "1"],
+        "status: { phase: Running }",
+    )
+    .await;
+    assert_eq!(output.status.code(), Some(124), "{output:?}");
+    assert_eq!(stdout(&output), "");
+}
+
+/// Writes the filter to a file, unique to the test, and returns its path.
+fn filter_file(name: &str, filter: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("k8s-wait-full-{}-{name}.yaml", std::process::id()));
+    std::fs::write(&path, filter).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn first_matching_alternative_is_reported() {
+    let api = serve_pods(vec![testing::pod("web", "Failed")]).await;
+    let succeeded = filter_file("any-of-succeeded", "status: { phase: Succeeded }");
+    let failed = filter_file("any-of-failed", "status: { phase: Failed }");
+    let finished = filter_file(
+        "any-of-finished",
+        "status: { phase: { $regexAny: [Succeeded, Failed] } }",
+    );
+
+    let output = run(
+        &api,
+        &[
+            "Pod",
+            "web",
+            "--timeout",
+            "0",
+            "--any-of",
+            &succeeded,
+            "--any-of",
+            &failed,
+            "--any-of",
+            &finished,
+        ],
+        "{}",
+    )
+    .await;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!("State matched the `--any-of` filter {failed}.")),
+        "{stderr}"
+    );
+    assert!(!stderr.contains(&finished), "{stderr}");
+
+    let output = run(
+        &api,
+        &["Pod", "web", "--timeout", "0", "--any-of", &succeeded],
+        "{}",
+    )
+    .await;
+    assert_eq!(output.status.code(), Some(124), "{output:?}");
+
+    for path in [succeeded, failed, finished] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn oversized_resources_are_skipped_with_a_single_warning() {
+    let large = |resource_version: &str| {
+        let mut pod = pod_version("Running", resource_version);
+        pod["metadata"]["annotations"] = serde_json::json!({ "note": "x".repeat(1000) });
+        pod
+    };
+    let api = FakeApi::start(move |request| {
+        if let Some(response) = testing::discovery(request, &[("v1", "Pod")]) {
+            return response;
+        }
+        if request.is_watch() {
+            return Response::Events(vec![
+                testing::event("MODIFIED", large("2")),
+                testing::event("MODIFIED", large("3")),
+            ]);
+        }
+        Response::Json(200, testing::pod_list(vec![large("1")]))
+    })
+    .await;
+
+    let args = ["Pod", "web", "--timeout", "1", "--max-object-bytes", "500"];
+    let output = run(&api, &args, "status: { phase: Running }").await;
+    assert_eq!(output.status.code(), Some(124), "{output:?}");
+    assert_eq!(stdout(&output), "");
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(
+        stderr
+            .matches("Warning: skipping resource web, which is larger than 500 bytes.")
+            .count(),
+        1,
+        "{stderr}"
+    );
+
+    let args = ["Pod", "web", "--timeout", "1", "--max-object-bytes", "5000"];
+    let output = run(&api, &args, "status: { phase: Running }").await;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+}
+
+/// Sends the signal, e.g. `TERM`, to the binary once the fake API receives a request matching the predicate,
+/// and returns the output. Requests received before the binary is started are ignored.
+async fn signal_when(
+    api: &FakeApi,
+    args: &[&str],
+    signal: &str,
+    received: impl Fn(&testing::Request) -> bool,
+) -> Output {
+    let mut command = command(api, args);
+    command.stdin(Stdio::null());
+    let earlier = api.requests().len();
+    let child = command.spawn().unwrap();
+    while !api.requests()[earlier..].iter().any(&received) {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let pid = child.id().unwrap().to_string();
+    let status = std::process::Command::new("kill")
+        .args([&format!("-{signal}"), &pid])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    child.wait_with_output().await.unwrap()
+}
+
+#[tokio::test]
+async fn signals_end_the_wait_with_their_exit_codes() {
+    let api = serve_pods(vec![testing::pod("web", "Pending")]).await;
+    let args = ["Pod", "web", "--for", "condition=Ready", "--status-json"];
+
+    for (signal, code, result) in [("TERM", 143, "terminated"), ("INT", 130, "terminated")] {
+        let output = signal_when(&api, &args, signal, testing::Request::is_watch).await;
+        assert_eq!(output.status.code(), Some(code), "{output:?}");
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert!(
+            stderr.contains(&format!("\"result\":\"{result}\"")),
+            "{stderr}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn signals_end_listing_with_their_exit_codes() {
+    // Lists never finish, as only the headers of the response are sent.
+    let api = FakeApi::start(|request| {
+        testing::discovery(request, &[("v1", "Pod")]).unwrap_or(Response::Events(Vec::new()))
+    })
+    .await;
+
+    for subcommand in ["get", "count"] {
+        let args = [subcommand, "Pod", "web"];
+        let output = signal_when(&api, &args, "TERM", |request| {
+            request.path().ends_with("/pods")
+        })
+        .await;
+        assert_eq!(output.status.code(), Some(143), "{output:?}");
+        assert_eq!(stdout(&output), "");
+    }
+}
+
+#[tokio::test]
+async fn timeout_exit_zero_prints_the_last_observed_state() {
+    let api = serve_pods(vec![testing::pod("web", "Pending")]).await;
+    let filter = "status: { phase: Running }";
+
+    for timeout in ["0", "1"] {
+        let args = ["Pod", "web", "--timeout", timeout, "--timeout-exit-zero"];
+        let output = run(&api, &args, filter).await;
+        assert_eq!(output.status.code(), Some(0), "{output:?}");
+        let state: Value = serde_yaml::from_str(stdout(&output)).unwrap();
+        assert_eq!(state["status"]["phase"], "Pending");
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert!(
+            stderr.contains("printing the last observed state instead"),
+            "{stderr}"
+        );
+
+        // Without the flag, the same wait fails.
+        let output = run(&api, &args[..4], filter).await;
+        assert_eq!(output.status.code(), Some(124), "{output:?}");
+        assert_eq!(stdout(&output), "");
+    }
+
+    let api = serve_pods(Vec::new()).await;
+    let output = run(
+        &api,
+        &["Pod", "web", "--timeout", "0", "--timeout-exit-zero"],
+        filter,
+    )
+    .await;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert_eq!(stdout(&output), "");
+}
+
+#[tokio::test]
+async fn managed_fields_are_matched_but_not_printed() {
+    let mut pod = testing::pod("web", "Running");
+    pod["metadata"]["managedFields"] =
+        serde_json::json!([{ "manager": "kubectl", "operation": "Apply" }]);
+    let api = serve_pods(vec![pod]).await;
+    let filter = "metadata: { managedFields: [{ manager: kubectl }] }";
+
+    for (args, printed) in [
+        (&["Pod", "web", "--timeout", "0"][..], false),
+        (&["Pod", "web", "--timeout", "0", "-o", "yaml-flow"], false),
+        (
+            &["Pod", "web", "--timeout", "0", "--keep-managed-fields"],
+            true,
+        ),
+    ] {
+        let output = run(&api, args, filter).await;
+        assert_eq!(output.status.code(), Some(0), "{output:?}");
+        // Flow-style YAML is also JSON.
+        let state: Value = serde_yaml::from_str(stdout(&output)).unwrap();
+        assert_eq!(state["metadata"]["name"], "web");
+        assert_eq!(
+            state["metadata"].get("managedFields").is_some(),
+            printed,
+            "{args:?}"
+        );
+    }
+}